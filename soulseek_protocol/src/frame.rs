@@ -1,6 +1,6 @@
 use bytes::Buf;
 use std::{
-    io::{Cursor, Read},
+    io::{Cursor, ErrorKind, Read},
     net::Ipv4Addr,
 };
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
@@ -58,7 +58,8 @@ pub(crate) async fn write_string(
 }
 
 pub(crate) fn read_string(src: &mut Cursor<&[u8]>) -> std::io::Result<String> {
-    let string_len = src.get_u32_le();
+    let string_len = read_u32(src)?;
+    ensure_remaining(src, string_len as usize)?;
     if string_len > 0 {
         let mut string = vec![0u8; string_len as usize];
         src.read_exact(&mut string)?;
@@ -68,6 +69,56 @@ pub(crate) fn read_string(src: &mut Cursor<&[u8]>) -> std::io::Result<String> {
     }
 }
 
+pub(crate) async fn write_bytes(
+    src: &[u8],
+    buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+) -> tokio::io::Result<()> {
+    buffer.write_u32_le(src.len() as u32).await?;
+    buffer.write_all(src).await?;
+    Ok(())
+}
+
+pub(crate) fn read_bytes(src: &mut Cursor<&[u8]>) -> std::io::Result<Vec<u8>> {
+    let bytes_len = read_u32(src)?;
+    ensure_remaining(src, bytes_len as usize)?;
+    let mut bytes = vec![0u8; bytes_len as usize];
+    src.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Unlike the `Buf` getters, these do not panic when a peer sends a truncated message.
+pub(crate) fn read_u8(src: &mut Cursor<&[u8]>) -> std::io::Result<u8> {
+    ensure_remaining(src, 1)?;
+    Ok(src.get_u8())
+}
+
+pub(crate) fn read_u32(src: &mut Cursor<&[u8]>) -> std::io::Result<u32> {
+    ensure_remaining(src, 4)?;
+    Ok(src.get_u32_le())
+}
+
+pub(crate) fn read_u64(src: &mut Cursor<&[u8]>) -> std::io::Result<u64> {
+    ensure_remaining(src, 8)?;
+    Ok(src.get_u64_le())
+}
+
+fn ensure_remaining(src: &Cursor<&[u8]>, len: usize) -> std::io::Result<()> {
+    if src.remaining() < len {
+        Err(std::io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "message shorter than announced",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Capacity to reserve for `count` elements, the element count is sent by peers and can't be
+/// trusted: each element takes at least one byte.
+pub(crate) fn capacity(count: u32, src: &Cursor<&[u8]>) -> usize {
+    (count as usize).min(src.remaining())
+}
+
 pub(crate) fn read_bool(src: &mut Cursor<&[u8]>) -> bool {
     src.get_u8() == 1
 }
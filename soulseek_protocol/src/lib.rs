@@ -13,6 +13,10 @@ use tokio::time::error::Elapsed;
 pub mod frame;
 pub mod message_common;
 pub mod peers;
+/// Soulseek search query parser and matcher, see [`Query`].
+///
+///  [`Query`]: crate::query::Query
+pub mod query;
 /// Contains all the soulseek protocol server message, see [`ServerRequest`] and [`ServerResponse`]
 /// for a detailed explanation of each one.
 ///
@@ -142,7 +146,9 @@ pub trait ProtocolHeader {
         let code = T::Code::read(src);
 
         // We can subtract message code from the length since we already know it
-        let message_len = message_length - Self::Code::LEN;
+        let message_len = message_length
+            .checked_sub(Self::Code::LEN)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
 
         Ok(T::new(message_len, code))
     }
@@ -3,9 +3,12 @@ use crate::{
     message_common::ConnectionType::{DistributedNetwork, FileTransfer, PeerToPeer},
     peers::PeerRequestPacket,
 };
-use std::{io::Cursor, str::Bytes};
+use std::{
+    io::{Cursor, ErrorKind},
+    str::Bytes,
+};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum ConnectionType {
     PeerToPeer,
     FileTransfer,
@@ -15,8 +18,15 @@ pub enum ConnectionType {
 
 impl ParseBytes for ConnectionType {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let raw_c_type = read_string(src)?;
-        Ok(ConnectionType::from(raw_c_type))
+        match read_string(src)?.as_str() {
+            "P" => Ok(PeerToPeer),
+            "F" => Ok(FileTransfer),
+            "D" => Ok(DistributedNetwork),
+            other => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected connection type received : {}", other),
+            )),
+        }
     }
 }
 
@@ -30,17 +40,6 @@ impl From<&PeerRequestPacket> for ConnectionType {
     }
 }
 
-impl From<String> for ConnectionType {
-    fn from(code: String) -> Self {
-        match code {
-            p if p == "P" => PeerToPeer,
-            f if f == "F" => FileTransfer,
-            d if d == "D" => DistributedNetwork,
-            other => panic!("Unexpected connection type received : {}", other),
-        }
-    }
-}
-
 impl AsRef<str> for ConnectionType {
     fn as_ref(&self) -> &str {
         match self {
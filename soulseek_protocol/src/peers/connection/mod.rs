@@ -4,7 +4,7 @@ use bytes::Buf;
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, write_string, ToBytes},
+    frame::{read_string, read_u32, write_string, ParseBytes, ToBytes},
     message_common::ConnectionType,
     MessageCode, ProtocolHeader, ProtocolMessage,
 };
@@ -57,7 +57,7 @@ impl From<u8> for ConnectionMessageCode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum PeerConnectionMessage {
     PierceFirewall(u32),
     PeerInit {
@@ -112,12 +112,12 @@ impl ProtocolMessage for PeerConnectionMessage {
     fn parse(src: &mut Cursor<&[u8]>, header: &ConnectionMessageHeader) -> std::io::Result<Self> {
         match header.code {
             ConnectionMessageCode::PierceFireWall => {
-                Ok(PeerConnectionMessage::PierceFirewall(src.get_u32_le()))
+                Ok(PeerConnectionMessage::PierceFirewall(read_u32(src)?))
             }
             ConnectionMessageCode::PeerInit => {
                 let username = read_string(src)?;
-                let connection_type = ConnectionType::from(read_string(src)?);
-                let token = src.get_u32_le();
+                let connection_type = ConnectionType::parse(src)?;
+                let token = read_u32(src)?;
 
                 Ok(PeerConnectionMessage::PeerInit {
                     username,
@@ -132,3 +132,48 @@ impl ProtocolMessage for PeerConnectionMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        message_common::ConnectionType,
+        peers::connection::{
+            ConnectionMessageCode, ConnectionMessageHeader, PeerConnectionMessage,
+        },
+        ProtocolHeader, ProtocolMessage,
+    };
+
+    fn peer_init(connection_type: &str) -> Vec<u8> {
+        let mut data = vec![];
+        for string in &["alice", connection_type] {
+            data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            data.extend_from_slice(string.as_bytes());
+        }
+        data.extend_from_slice(&42u32.to_le_bytes());
+        data
+    }
+
+    fn parse(data: &[u8]) -> std::io::Result<PeerConnectionMessage> {
+        let header = ConnectionMessageHeader::new(data.len() + 1, ConnectionMessageCode::PeerInit);
+        PeerConnectionMessage::parse(&mut Cursor::new(data), &header)
+    }
+
+    #[test]
+    fn should_parse_peer_init() {
+        assert!(matches!(
+            parse(&peer_init("D")),
+            Ok(PeerConnectionMessage::PeerInit {
+                connection_type: ConnectionType::DistributedNetwork,
+                token: 42,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_reject_unknown_connection_type() {
+        assert!(parse(&peer_init("X")).is_err());
+    }
+}
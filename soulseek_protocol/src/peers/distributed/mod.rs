@@ -4,14 +4,14 @@ use bytes::Buf;
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, ParseBytes, ToBytes},
+    frame::{read_string, read_u32, write_string, ParseBytes, ToBytes},
     MessageCode, ProtocolHeader, ProtocolMessage,
 };
 
 use self::search::SearchRequest;
 
 mod branch;
-mod search;
+pub mod search;
 
 #[derive(Debug)]
 pub struct DistributedMessageHeader {
@@ -67,7 +67,7 @@ impl From<u8> for DistributedMessageCode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub enum DistributedMessage {
     Ping,
     SearchRequest(SearchRequest),
@@ -88,12 +88,29 @@ impl ToBytes for DistributedMessage {
             DistributedMessage::Ping => {
                 write_empty_distributed_msg(DistributedMessageCode::Ping, buffer).await?
             }
-            DistributedMessage::SearchRequest(_) => todo!(),
-            DistributedMessage::BranchLevel(_) => todo!(),
-            DistributedMessage::BranchRoot(_) => todo!(),
-            DistributedMessage::ChildDepth(_) => todo!(),
-            DistributedMessage::ServerSearchRequest => todo!(),
-            DistributedMessage::Unknown => todo!(),
+            DistributedMessage::SearchRequest(request) => request.write_to_buf(buffer).await?,
+            DistributedMessage::BranchLevel(level) => {
+                write_u32_distributed_msg(*level, DistributedMessageCode::BranchLevel, buffer)
+                    .await?
+            }
+            DistributedMessage::BranchRoot(root) => {
+                let message_len = root.len() as u32 + 5;
+                buffer.write_u32_le(message_len).await?;
+                buffer
+                    .write_u8(DistributedMessageCode::BranchRoot as u8)
+                    .await?;
+                write_string(root, buffer).await?;
+            }
+            DistributedMessage::ChildDepth(depth) => {
+                write_u32_distributed_msg(*depth, DistributedMessageCode::ChildDepth, buffer)
+                    .await?
+            }
+            DistributedMessage::ServerSearchRequest | DistributedMessage::Unknown => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Cannot send distributed message {:?}", self),
+                ));
+            }
         }
 
         Ok(())
@@ -110,13 +127,13 @@ impl ProtocolMessage for DistributedMessage {
                 SearchRequest::parse(src).map(DistributedMessage::SearchRequest)
             }
             DistributedMessageCode::BranchLevel => {
-                Ok(DistributedMessage::BranchLevel(src.get_u32_le()))
+                Ok(DistributedMessage::BranchLevel(read_u32(src)?))
             }
             DistributedMessageCode::BranchRoot => {
                 Ok(DistributedMessage::BranchRoot(read_string(src)?))
             }
             DistributedMessageCode::ChildDepth => {
-                Ok(DistributedMessage::ChildDepth(src.get_u32_le()))
+                Ok(DistributedMessage::ChildDepth(read_u32(src)?))
             }
             DistributedMessageCode::ServerSearchRequest => {
                 Ok(DistributedMessage::ServerSearchRequest)
@@ -137,3 +154,83 @@ async fn write_empty_distributed_msg(
     buffer.write_u8(code as u8).await?;
     Ok(())
 }
+
+async fn write_u32_distributed_msg(
+    src: u32,
+    code: DistributedMessageCode,
+    buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+) -> tokio::io::Result<()> {
+    buffer.write_u32_le(5).await?;
+    buffer.write_u8(code as u8).await?;
+    buffer.write_u32_le(src).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use tokio::io::{AsyncWriteExt, BufWriter};
+    use tokio_test::block_on;
+
+    use crate::{
+        frame::ToBytes,
+        peers::distributed::{
+            search::SearchRequest, DistributedMessage, DistributedMessageCode,
+            DistributedMessageHeader,
+        },
+        ProtocolHeader, ProtocolMessage,
+    };
+
+    fn round_trip(message: DistributedMessage) -> DistributedMessage {
+        let mut data = Vec::new();
+        let mut buffer = BufWriter::new(&mut data);
+        block_on(async {
+            message.write_to_buf(&mut buffer).await.unwrap();
+            buffer.flush().await.unwrap();
+        });
+
+        let message_len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        assert_eq!(message_len, data.len() - 4);
+
+        let header =
+            DistributedMessageHeader::new(message_len, DistributedMessageCode::from(data[4]));
+        DistributedMessage::parse(&mut Cursor::new(&data[5..]), &header).unwrap()
+    }
+
+    #[test]
+    fn should_write_branch_messages() {
+        assert!(matches!(
+            round_trip(DistributedMessage::BranchLevel(3)),
+            DistributedMessage::BranchLevel(3)
+        ));
+        assert!(matches!(
+            round_trip(DistributedMessage::ChildDepth(2)),
+            DistributedMessage::ChildDepth(2)
+        ));
+        assert!(matches!(
+            round_trip(DistributedMessage::BranchRoot("oknozor".to_string())),
+            DistributedMessage::BranchRoot(root) if root == "oknozor"
+        ));
+    }
+
+    #[test]
+    fn should_write_search_request() {
+        let request = SearchRequest {
+            unknown: 49,
+            username: "alice".to_string(),
+            ticket: 1234,
+            query: "pink floyd".to_string(),
+        };
+
+        match round_trip(DistributedMessage::SearchRequest(request)) {
+            DistributedMessage::SearchRequest(request) => {
+                assert_eq!(request.unknown, 49);
+                assert_eq!(request.username, "alice");
+                assert_eq!(request.ticket, 1234);
+                assert_eq!(request.query, "pink floyd");
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+}
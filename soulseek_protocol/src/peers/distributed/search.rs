@@ -1,8 +1,12 @@
-use crate::frame::{read_string, ParseBytes};
-use bytes::Buf;
+use crate::{
+    frame::{read_string, read_u32, write_string, ParseBytes, ToBytes},
+    peers::distributed::DistributedMessageCode,
+    server::search::SearchQuery,
+};
 use std::io::Cursor;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchRequest {
     pub unknown: u32,
     pub username: String,
@@ -12,9 +16,9 @@ pub struct SearchRequest {
 
 impl ParseBytes for SearchRequest {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let unknown = src.get_u32_le();
+        let unknown = read_u32(src)?;
         let username = read_string(src)?;
-        let ticket = src.get_u32_le();
+        let ticket = read_u32(src)?;
         let query = read_string(src)?;
 
         Ok(Self {
@@ -25,3 +29,36 @@ impl ParseBytes for SearchRequest {
         })
     }
 }
+
+#[async_trait]
+impl ToBytes for SearchRequest {
+    async fn write_to_buf(
+        &self,
+        buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+    ) -> tokio::io::Result<()> {
+        let username = self.username.as_bytes();
+        let query = self.query.as_bytes();
+        // code + unknown + username + ticket + query
+        let message_len = 1 + 4 + (4 + username.len()) + 4 + (4 + query.len());
+
+        buffer.write_u32_le(message_len as u32).await?;
+        buffer
+            .write_u8(DistributedMessageCode::SearchRequest as u8)
+            .await?;
+        buffer.write_u32_le(self.unknown).await?;
+        write_string(&self.username, buffer).await?;
+        buffer.write_u32_le(self.ticket).await?;
+        write_string(&self.query, buffer).await?;
+        Ok(())
+    }
+}
+
+impl From<SearchRequest> for SearchQuery {
+    fn from(request: SearchRequest) -> Self {
+        SearchQuery {
+            username: request.username,
+            ticket: request.ticket,
+            query: request.query,
+        }
+    }
+}
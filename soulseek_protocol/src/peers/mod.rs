@@ -6,7 +6,7 @@ pub mod connection;
 pub mod distributed;
 pub mod p2p;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum PeerRequestPacket {
     Message(PeerRequest),
     ConnectionMessage(PeerConnectionMessage),
@@ -19,3 +19,11 @@ pub enum PeerResponsePacket {
     ConnectionMessage(PeerConnectionMessage),
     DistributedMessage(DistributedMessage),
 }
+
+/// A request we could not deliver to a peer, reported to the SSE clients.
+#[derive(Debug, Serialize)]
+pub struct UndeliveredRequest {
+    pub username: String,
+    pub reason: String,
+    pub request: PeerRequestPacket,
+}
@@ -10,4 +10,17 @@ pub enum DownloadProgress {
         ticket: u32,
         percent: usize,
     },
+    FolderProgress {
+        user_name: String,
+        folder: String,
+        ticket: u32,
+        percent: usize,
+    },
+    /// Status change of a track imported from a playlist
+    ImportProgress {
+        import_id: u32,
+        index: usize,
+        title: String,
+        status: String,
+    },
 }
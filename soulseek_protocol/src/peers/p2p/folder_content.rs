@@ -1,27 +1,25 @@
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, ParseBytes, ToBytes},
-    peers::p2p::PeerMessageCode,
+    frame::{
+        capacity, read_string, read_u32, write_string, ParseBytes, ToBytes, STR_LENGTH_PREFIX,
+    },
+    peers::p2p::{shared_directories::Directory, zlib, zlib::decompress, PeerMessageCode},
 };
-use bytes::Buf;
 use std::io::Cursor;
 
 #[derive(Debug, Serialize)]
 pub struct FolderContentsRequest {
-    files: Vec<String>,
+    pub ticket: u32,
+    pub folder: String,
 }
 
 impl ParseBytes for FolderContentsRequest {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let file_nth = src.get_u32_le();
-        let mut folder_content_request = FolderContentsRequest { files: vec![] };
+        let ticket = read_u32(src)?;
+        let folder = read_string(src)?;
 
-        for _ in 0..file_nth {
-            folder_content_request.files.push(read_string(src)?);
-        }
-
-        Ok(folder_content_request)
+        Ok(FolderContentsRequest { ticket, folder })
     }
 }
 
@@ -31,18 +29,155 @@ impl ToBytes for FolderContentsRequest {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        let mut files_size = 0;
-        for file in &self.files {
-            files_size += 4;
-            files_size += file.bytes().len() as u32;
-        }
-
-        let length = 4 + self.files.len() as u32 + files_size;
+        let length = 4 + 4 + STR_LENGTH_PREFIX + self.folder.len() as u32;
 
         buffer.write_u32_le(length).await?;
         buffer
             .write_u32_le(PeerMessageCode::FolderContentsRequest as u32)
             .await?;
+        buffer.write_u32_le(self.ticket).await?;
+        write_string(&self.folder, buffer).await?;
+
+        Ok(())
+    }
+}
+
+/// The content of a requested folder, `dirs` contains the folder itself followed by its
+/// sub-directories.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct FolderContentsReply {
+    pub ticket: u32,
+    pub folder: String,
+    pub dirs: Vec<Directory>,
+}
+
+#[async_trait]
+impl ToBytes for FolderContentsReply {
+    async fn write_to_buf(
+        &self,
+        buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+    ) -> tokio::io::Result<()> {
+        // Pack message
+        let inner = &mut vec![];
+        let mut message_buffer = BufWriter::new(inner);
+
+        message_buffer.write_u32_le(self.ticket).await?;
+        write_string(&self.folder, &mut message_buffer).await?;
+        message_buffer.write_u32_le(self.dirs.len() as u32).await?;
+
+        for dir in &self.dirs {
+            dir.write_to_buf(&mut message_buffer).await?;
+        }
+
+        message_buffer.flush().await?;
+        let data = message_buffer.into_inner();
+        // Compress message
+        let compressed_data = zlib::compress(data)?;
+
+        // Write to connection buffer
+        buffer
+            .write_u32_le(compressed_data.len() as u32 + 4)
+            .await?;
+        buffer
+            .write_u32_le(PeerMessageCode::FolderContentsReply as u32)
+            .await?;
+        buffer.write_all(compressed_data.as_slice()).await?;
+
         Ok(())
     }
 }
+
+impl ParseBytes for FolderContentsReply {
+    fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let data = decompress(src)?;
+        let mut cursor = Cursor::new(data.as_slice());
+
+        let ticket = read_u32(&mut cursor)?;
+        let folder = read_string(&mut cursor)?;
+        let directory_nth = read_u32(&mut cursor)?;
+        let mut dirs = Vec::with_capacity(capacity(directory_nth, &cursor));
+
+        for _ in 0..directory_nth {
+            dirs.push(Directory::parse(&mut cursor)?);
+        }
+
+        Ok(FolderContentsReply {
+            ticket,
+            folder,
+            dirs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use bytes::Buf;
+    use tokio::io::BufWriter;
+    use tokio_test::block_on;
+
+    use crate::{
+        frame::{ParseBytes, ToBytes},
+        peers::p2p::{
+            shared_directories::{Directory, File},
+            PeerMessageCode,
+        },
+    };
+
+    use super::{FolderContentsReply, FolderContentsRequest};
+
+    #[test]
+    fn write_folder_contents_request_ok() {
+        let request = FolderContentsRequest {
+            ticket: 42,
+            folder: "@@music\\album".to_string(),
+        };
+
+        let mut vec = vec![];
+        let mut buff = BufWriter::new(&mut vec);
+        block_on(request.write_to_buf(&mut buff)).unwrap();
+        let mut cursor = Cursor::new(buff.buffer());
+
+        let len = cursor.get_u32_le();
+        let code = cursor.get_u32_le();
+        assert_eq!(len as usize, buff.buffer().len() - 4);
+        assert_eq!(code, PeerMessageCode::FolderContentsRequest as u32);
+
+        let parsed = FolderContentsRequest::parse(&mut cursor).unwrap();
+
+        assert_eq!(parsed.ticket, 42);
+        assert_eq!(parsed.folder, "@@music\\album");
+    }
+
+    #[test]
+    fn write_folder_contents_reply_ok() {
+        let reply = FolderContentsReply {
+            ticket: 42,
+            folder: "@@music\\album".to_string(),
+            dirs: vec![Directory {
+                name: "@@music\\album".to_string(),
+                files: vec![File {
+                    name: "01 - track.flac".to_string(),
+                    size: 1337,
+                    extension: "flac".to_string(),
+                    attributes: vec![],
+                }],
+            }],
+        };
+
+        let mut vec = vec![];
+        let mut buff = BufWriter::new(&mut vec);
+        block_on(reply.write_to_buf(&mut buff)).unwrap();
+        let mut cursor = Cursor::new(buff.buffer());
+
+        let len = cursor.get_u32_le();
+        let code = cursor.get_u32_le();
+        assert_eq!(len as usize, buff.buffer().len() - 4);
+        assert_eq!(code, PeerMessageCode::FolderContentsReply as u32);
+
+        let parsed = FolderContentsReply::parse(&mut cursor).unwrap();
+
+        assert_eq!(parsed, reply);
+    }
+}
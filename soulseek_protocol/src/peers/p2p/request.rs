@@ -3,12 +3,16 @@ use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 use crate::{
     frame::{write_string, ToBytes},
     peers::p2p::{
-        folder_content::FolderContentsRequest, search::SearchReply,
-        shared_directories::SharedDirectories, transfer::*, user_info::UserInfo, PeerMessageCode,
+        folder_content::{FolderContentsReply, FolderContentsRequest},
+        search::SearchReply,
+        shared_directories::SharedDirectories,
+        transfer::*,
+        user_info::UserInfo,
+        PeerMessageCode,
     },
 };
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum PeerRequest {
     SharesRequest,
     SharesReply(SharedDirectories),
@@ -16,7 +20,7 @@ pub enum PeerRequest {
     UserInfoRequest,
     UserInfoReply(UserInfo),
     FolderContentsRequest(FolderContentsRequest),
-    FolderContentsReply(SharedDirectories),
+    FolderContentsReply(FolderContentsReply),
     TransferRequest(TransferRequest),
     TransferReply(TransferReply),
     UploadPlaceholder,
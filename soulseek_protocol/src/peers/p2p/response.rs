@@ -1,11 +1,13 @@
 use std::io::Cursor;
 
+use bytes::Buf;
+
 use crate::peers::p2p::transfer::QueueUpload;
 use crate::{
     frame::ParseBytes,
     peers::p2p::{
-        folder_content::FolderContentsRequest,
-        search::SearchReply,
+        folder_content::{FolderContentsReply, FolderContentsRequest},
+        search::{SearchReply, SearchRequest},
         shared_directories::SharedDirectories,
         transfer::{
             PlaceInQueueReply, PlaceInQueueRequest, QueueFailed, TransferReply, TransferRequest,
@@ -14,7 +16,7 @@ use crate::{
         user_info::UserInfo,
         PeerMessageCode, PeerMessageHeader,
     },
-    ProtocolMessage,
+    MessageCode, ProtocolMessage,
 };
 
 #[derive(Debug, Serialize)]
@@ -22,11 +24,12 @@ use crate::{
 pub enum PeerResponse {
     SharesRequest,
     SharesReply(SharedDirectories),
+    SearchRequest(SearchRequest),
     SearchReply(SearchReply),
     UserInfoRequest,
     UserInfoReply(UserInfo),
     FolderContentsRequest(FolderContentsRequest),
-    FolderContentsReply(SharedDirectories),
+    FolderContentsReply(FolderContentsReply),
     TransferRequest(TransferRequest),
     TransferReply(TransferReply),
     UploadPlaceholder,
@@ -36,33 +39,57 @@ pub enum PeerResponse {
     QueueFailed(QueueFailed),
     PlaceInQueueRequest(PlaceInQueueRequest),
     UploadQueueNotification,
-    Unknown,
+    Unknown(UnknownMessage),
+}
+
+/// A message with an unknown code, kept as is for diagnostics.
+#[derive(Debug, Serialize)]
+pub struct UnknownMessage {
+    pub code: u32,
+    pub data: Vec<u8>,
 }
 
 impl ProtocolMessage for PeerResponse {
     type Header = PeerMessageHeader;
 
     fn parse(src: &mut Cursor<&[u8]>, header: &Self::Header) -> std::io::Result<Self> {
+        // Never read past the end of the message, optional trailing fields would otherwise be
+        // read from the next one
+        let raw_code = raw_code(src);
+        let message = src
+            .chunk()
+            .get(..header.message_len)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        let src = &mut Cursor::new(message);
+
         match header.code {
             PeerMessageCode::SharesRequest => Ok(PeerResponse::SharesRequest),
             PeerMessageCode::SharesReply => {
                 SharedDirectories::parse(src).map(PeerResponse::SharesReply)
             }
-            PeerMessageCode::SearchRequest => todo!(),
+            PeerMessageCode::SearchRequest => {
+                SearchRequest::parse(src).map(PeerResponse::SearchRequest)
+            }
             PeerMessageCode::SearchReply => SearchReply::parse(src).map(PeerResponse::SearchReply),
             PeerMessageCode::UserInfoRequest => Ok(PeerResponse::UserInfoRequest),
             PeerMessageCode::UserInfoReply => UserInfo::parse(src).map(PeerResponse::UserInfoReply),
             PeerMessageCode::FolderContentsRequest => {
                 FolderContentsRequest::parse(src).map(PeerResponse::FolderContentsRequest)
             }
-            PeerMessageCode::FolderContentsReply => todo!(),
+            PeerMessageCode::FolderContentsReply => {
+                FolderContentsReply::parse(src).map(PeerResponse::FolderContentsReply)
+            }
             PeerMessageCode::TransferRequest => {
                 TransferRequest::parse(src).map(PeerResponse::TransferRequest)
             }
-            PeerMessageCode::TransferReply => todo!(),
-            PeerMessageCode::UploadPlacehold => todo!(),
+            PeerMessageCode::TransferReply => {
+                TransferReply::parse(src).map(PeerResponse::TransferReply)
+            }
+            PeerMessageCode::UploadPlacehold => Ok(PeerResponse::UploadPlaceholder),
             PeerMessageCode::QueueUpload => QueueUpload::parse(src).map(PeerResponse::QueueUpload),
-            PeerMessageCode::PlaceInQueueReply => todo!(),
+            PeerMessageCode::PlaceInQueueReply => {
+                PlaceInQueueReply::parse(src).map(PeerResponse::PlaceInQueueReply)
+            }
             PeerMessageCode::UploadFailed => {
                 UploadFailed::parse(src).map(PeerResponse::UploadFailed)
             }
@@ -70,11 +97,99 @@ impl ProtocolMessage for PeerResponse {
             PeerMessageCode::PlaceInQueueRequest => {
                 PlaceInQueueRequest::parse(src).map(PeerResponse::PlaceInQueueRequest)
             }
-            PeerMessageCode::UploadQueueNotification => todo!(),
-            PeerMessageCode::Unknown => {
-                warn!("Unknown message from peer : \n{:?}", src);
-                Ok(PeerResponse::Unknown)
+            PeerMessageCode::UploadQueueNotification => Ok(PeerResponse::UploadQueueNotification),
+            PeerMessageCode::Unknown => Ok(PeerResponse::Unknown(UnknownMessage {
+                code: raw_code,
+                data: message.to_vec(),
+            })),
+        }
+    }
+}
+
+/// The message header only keeps known codes, read the raw one back from the frame.
+fn raw_code(src: &Cursor<&[u8]>) -> u32 {
+    let code_start = (src.position() as usize).saturating_sub(PeerMessageCode::LEN);
+    let mut code = [0; 4];
+
+    if let Some(bytes) = src.get_ref().get(code_start..code_start + 4) {
+        code.copy_from_slice(bytes);
+    }
+
+    u32::from_le_bytes(code)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        peers::p2p::{response::PeerResponse, PeerMessageCode},
+        ProtocolMessage,
+    };
+
+    fn frame(code: u32, body: &[u8]) -> Vec<u8> {
+        let mut frame = (body.len() as u32 + 4).to_le_bytes().to_vec();
+        frame.extend_from_slice(&code.to_le_bytes());
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn parse(frame: &[u8]) -> std::io::Result<PeerResponse> {
+        let mut cursor = Cursor::new(frame);
+        let header = PeerResponse::check(&mut cursor).unwrap();
+        PeerResponse::parse(&mut cursor, &header)
+    }
+
+    #[test]
+    fn should_keep_unknown_message_bytes() {
+        let message = parse(&frame(1337, &[1, 2, 3])).unwrap();
+
+        match message {
+            PeerResponse::Unknown(unknown) => {
+                assert_eq!(unknown.code, 1337);
+                assert_eq!(unknown.data, vec![1, 2, 3]);
             }
+            other => panic!("Expected unknown message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_truncated_messages() {
+        let codes = [
+            PeerMessageCode::SharesReply,
+            PeerMessageCode::SearchRequest,
+            PeerMessageCode::SearchReply,
+            PeerMessageCode::UserInfoReply,
+            PeerMessageCode::FolderContentsRequest,
+            PeerMessageCode::FolderContentsReply,
+            PeerMessageCode::TransferRequest,
+            PeerMessageCode::QueueUpload,
+            PeerMessageCode::PlaceInQueueReply,
+            PeerMessageCode::UploadFailed,
+            PeerMessageCode::QueueFailed,
+            PeerMessageCode::PlaceInQueueRequest,
+        ];
+
+        for code in codes {
+            // A string announcing more bytes than the message contains
+            let name = format!("{:?}", code);
+            let message = frame(code as u32, &[255, 255, 255, 255, 1]);
+            assert!(parse(&message).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn should_not_read_past_the_message() {
+        // Transfer reply without file size followed by another message
+        let mut frames = frame(PeerMessageCode::TransferReply as u32, &[1, 0, 0, 0, 1]);
+        frames.extend(frame(PeerMessageCode::UserInfoRequest as u32, &[]));
+
+        match parse(&frames).unwrap() {
+            PeerResponse::TransferReply(reply) => assert_eq!(
+                format!("{:?}", reply),
+                "TransferReplyOk { ticket: 1, file_size: 0 }"
+            ),
+            other => panic!("Expected transfer reply, got {:?}", other),
         }
     }
 }
@@ -1,13 +1,30 @@
 use std::{io::Cursor, usize};
 
 use bytes::Buf;
-use tokio::io::{AsyncWrite, BufWriter};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, ParseBytes, ToBytes},
-    peers::p2p::{shared_directories::File, zlib::decompress},
+    frame::{capacity, read_string, read_u32, read_u8, write_string, ParseBytes, ToBytes},
+    peers::p2p::{shared_directories::File, zlib, zlib::decompress, PeerMessageCode},
+    query::Query,
 };
 
+/// Search request sent directly by a peer, only used by old clients.
+#[derive(Debug, Serialize)]
+pub struct SearchRequest {
+    pub ticket: u32,
+    pub query: String,
+}
+
+impl ParseBytes for SearchRequest {
+    fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let ticket = read_u32(src)?;
+        let query = read_string(src)?;
+
+        Ok(SearchRequest { ticket, query })
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct SearchReply {
     pub username: String,
@@ -19,13 +36,67 @@ pub struct SearchReply {
     pub locked_results: Vec<File>,
 }
 
+impl SearchReply {
+    /// Drop every file, including locked results, not matching the given [`Query`].
+    pub fn retain_matching(&mut self, query: &Query) {
+        self.files.retain(|file| query.matches_file(file));
+        self.locked_results.retain(|file| query.matches_file(file));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.locked_results.is_empty()
+    }
+}
+
 #[async_trait]
 impl ToBytes for SearchReply {
     async fn write_to_buf(
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        todo!()
+        // Pack message
+        let inner = &mut vec![];
+        let mut message_buffer = BufWriter::new(inner);
+
+        write_string(&self.username, &mut message_buffer).await?;
+        message_buffer.write_u32_le(self.ticket).await?;
+        message_buffer.write_u32_le(self.files.len() as u32).await?;
+
+        for file in &self.files {
+            file.write_to_buf(&mut message_buffer).await?;
+        }
+
+        message_buffer.write_u8(self.slot_free as u8).await?;
+        message_buffer.write_u32_le(self.average_speed).await?;
+        message_buffer.write_u32_le(self.queue_length).await?;
+        // Unknown, always 0
+        message_buffer.write_u32_le(0).await?;
+
+        if !self.locked_results.is_empty() {
+            message_buffer
+                .write_u32_le(self.locked_results.len() as u32)
+                .await?;
+
+            for file in &self.locked_results {
+                file.write_to_buf(&mut message_buffer).await?;
+            }
+        }
+
+        message_buffer.flush().await?;
+        let data = message_buffer.into_inner();
+        // Compress message
+        let compressed_data = zlib::compress(data)?;
+
+        // Write to connection buffer
+        buffer
+            .write_u32_le(compressed_data.len() as u32 + 4)
+            .await?;
+        buffer
+            .write_u32_le(PeerMessageCode::SearchReply as u32)
+            .await?;
+        buffer.write_all(compressed_data.as_slice()).await?;
+
+        Ok(())
     }
 }
 
@@ -34,25 +105,29 @@ impl ParseBytes for SearchReply {
         let data = decompress(src)?;
         let src = &mut Cursor::new(data.as_slice());
         let username = read_string(src)?;
-        let ticket = src.get_u32_le();
-        let result_nth = src.get_u32_le();
+        let ticket = read_u32(src)?;
+        let result_nth = read_u32(src)?;
 
-        let mut files = Vec::with_capacity(result_nth as usize);
+        let mut files = Vec::with_capacity(capacity(result_nth, src));
 
         for _ in 0..result_nth {
             let file = File::parse(src)?;
             files.push(file);
         }
 
-        let slot_free = src.get_u8() == 1;
-        let average_speed = src.get_u32_le();
-        let queue_length = src.get_u32_le();
+        let slot_free = read_u8(src)? == 1;
+        let average_speed = read_u32(src)?;
+        let queue_length = read_u32(src)?;
+
+        // Unknown field, always 0, older clients does not send it.
+        if src.remaining() >= 4 {
+            let _unknown = read_u32(src)?;
+        }
 
         let mut locked_results = vec![];
-        // Sometimes this is not present in the search reply.
-        // We should investigate this further
-        if src.has_remaining() {
-            let lock_result_nth = src.get_u32_le();
+        // Locked results are only sent when the peer has some
+        if src.remaining() >= 4 {
+            let lock_result_nth = read_u32(src)?;
             for _ in 0..lock_result_nth {
                 let file = File::parse(src)?;
                 locked_results.push(file);
@@ -75,10 +150,81 @@ impl ParseBytes for SearchReply {
 mod test {
     use std::io::Cursor;
 
-    use crate::frame::ParseBytes;
+    use bytes::Buf;
+    use tokio::io::BufWriter;
+    use tokio_test::block_on;
+
+    use crate::{
+        frame::{ParseBytes, ToBytes},
+        peers::p2p::{shared_directories::File, PeerMessageCode},
+        query::Query,
+    };
 
     use super::SearchReply;
 
+    fn file(name: &str) -> File {
+        File {
+            name: name.to_string(),
+            size: 42,
+            extension: "flac".to_string(),
+            attributes: vec![],
+        }
+    }
+
+    fn search_reply() -> SearchReply {
+        SearchReply {
+            username: "vessel".to_string(),
+            ticket: 1337,
+            files: vec![
+                file("@@music\\Nirvana\\Nevermind\\01 - Breed.flac"),
+                file("@@music\\Nirvana\\Live at Reading\\01 - Breed.flac"),
+            ],
+            slot_free: true,
+            average_speed: 100,
+            queue_length: 2,
+            locked_results: vec![file("@@private\\Nirvana\\Bleach\\01 - Blew.flac")],
+        }
+    }
+
+    #[test]
+    fn write_search_reply_ok() {
+        let reply = search_reply();
+
+        let mut vec = vec![];
+        let mut buff = BufWriter::new(&mut vec);
+        block_on(reply.write_to_buf(&mut buff)).unwrap();
+        let mut cursor = Cursor::new(buff.buffer());
+
+        let len = cursor.get_u32_le();
+        let code = cursor.get_u32_le();
+        assert_eq!(len as usize, buff.buffer().len() - 4);
+        assert_eq!(code, PeerMessageCode::SearchReply as u32);
+
+        let parsed = SearchReply::parse(&mut cursor).unwrap();
+
+        assert_eq!(parsed.username, reply.username);
+        assert_eq!(parsed.ticket, reply.ticket);
+        assert_eq!(parsed.files, reply.files);
+        assert_eq!(parsed.slot_free, reply.slot_free);
+        assert_eq!(parsed.average_speed, reply.average_speed);
+        assert_eq!(parsed.queue_length, reply.queue_length);
+        assert_eq!(parsed.locked_results, reply.locked_results);
+    }
+
+    #[test]
+    fn should_retain_matching_files() {
+        let mut reply = search_reply();
+
+        reply.retain_matching(&Query::parse("breed -live"));
+
+        assert_eq!(
+            reply.files,
+            vec![file("@@music\\Nirvana\\Nevermind\\01 - Breed.flac")]
+        );
+        assert!(reply.locked_results.is_empty());
+        assert!(!reply.is_empty());
+    }
+
     #[test]
     fn parse_search_reply() {
         let data: Vec<u8> = vec![
@@ -1,8 +1,9 @@
 use crate::{
-    frame::{read_string, write_string, ParseBytes, ToBytes},
+    frame::{
+        capacity, read_string, read_u32, read_u64, read_u8, write_string, ParseBytes, ToBytes,
+    },
     peers::p2p::{zlib, zlib::decompress, PeerMessageCode},
 };
-use bytes::Buf;
 use std::io::Cursor;
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
@@ -50,8 +51,8 @@ impl ParseBytes for SharedDirectories {
         let data = decompress(src)?;
         let mut cursor = Cursor::new(data.as_slice());
 
-        let directory_nth = cursor.get_u32_le();
-        let mut dirs = Vec::with_capacity(directory_nth as usize);
+        let directory_nth = read_u32(&mut cursor)?;
+        let mut dirs = Vec::with_capacity(capacity(directory_nth, &cursor));
 
         for _ in 0..directory_nth {
             dirs.push(Directory::parse(&mut cursor)?);
@@ -87,8 +88,8 @@ impl ToBytes for Directory {
 impl ParseBytes for Directory {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
         let name = read_string(src)?;
-        let file_nth = src.get_u32_le();
-        let mut files = Vec::with_capacity(file_nth as usize);
+        let file_nth = read_u32(src)?;
+        let mut files = Vec::with_capacity(capacity(file_nth, src));
         for _ in 0..file_nth {
             files.push(File::parse(src)?);
         }
@@ -97,7 +98,7 @@ impl ParseBytes for Directory {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct File {
     pub name: String,
     pub size: u64,
@@ -127,13 +128,13 @@ impl ToBytes for File {
 
 impl ParseBytes for File {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let _unused_char = src.get_u8();
+        let _unused_char = read_u8(src)?;
         let name = read_string(src)?;
-        let size = src.get_u64_le();
+        let size = read_u64(src)?;
         let extension = read_string(src)?;
-        let attribute_size = src.get_u32_le();
+        let attribute_size = read_u32(src)?;
 
-        let mut attributes = Vec::with_capacity(attribute_size as usize);
+        let mut attributes = Vec::with_capacity(capacity(attribute_size, src));
 
         for _ in 0..attribute_size {
             attributes.push(Attribute::parse(src)?);
@@ -148,28 +149,76 @@ impl ParseBytes for File {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+/// A file attribute, `place` is the attribute type and `attribute` its value.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Attribute {
     pub place: u32,
     pub attribute: u32,
 }
 
+impl Attribute {
+    pub const BITRATE: u32 = 0;
+    pub const DURATION: u32 = 1;
+    pub const VBR: u32 = 2;
+    pub const SAMPLE_RATE: u32 = 4;
+    pub const BIT_DEPTH: u32 = 5;
+
+    /// Bitrate in kbps.
+    pub fn bitrate(kbps: u32) -> Self {
+        Attribute {
+            place: Attribute::BITRATE,
+            attribute: kbps,
+        }
+    }
+
+    /// Duration in seconds.
+    pub fn duration(seconds: u32) -> Self {
+        Attribute {
+            place: Attribute::DURATION,
+            attribute: seconds,
+        }
+    }
+
+    pub fn vbr(vbr: bool) -> Self {
+        Attribute {
+            place: Attribute::VBR,
+            attribute: vbr as u32,
+        }
+    }
+
+    /// Sample rate in Hz.
+    pub fn sample_rate(hz: u32) -> Self {
+        Attribute {
+            place: Attribute::SAMPLE_RATE,
+            attribute: hz,
+        }
+    }
+
+    /// Bits per sample.
+    pub fn bit_depth(bits: u32) -> Self {
+        Attribute {
+            place: Attribute::BIT_DEPTH,
+            attribute: bits,
+        }
+    }
+}
+
 #[async_trait]
 impl ToBytes for Attribute {
     async fn write_to_buf(
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        buffer.write_u32_le(self.attribute).await?;
         buffer.write_u32_le(self.place).await?;
+        buffer.write_u32_le(self.attribute).await?;
         Ok(())
     }
 }
 
 impl ParseBytes for Attribute {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let place = src.get_u32_le();
-        let attribute = src.get_u32_le();
+        let place = read_u32(src)?;
+        let attribute = read_u32(src)?;
 
         Ok(Attribute { place, attribute })
     }
@@ -180,7 +229,7 @@ mod test {
     use crate::{
         frame::{ParseBytes, ToBytes},
         peers::p2p::{
-            shared_directories::{Directory, File, SharedDirectories},
+            shared_directories::{Attribute, Directory, File, SharedDirectories},
             PeerMessageCode,
         },
     };
@@ -216,4 +265,38 @@ mod test {
 
         assert_eq!(parse_result, shared_dirs);
     }
+
+    #[test]
+    fn write_attributes_ok() {
+        let shared_dirs = SharedDirectories {
+            dirs: vec![Directory {
+                name: "music".to_string(),
+                files: vec![File {
+                    name: "01 - Breed.mp3".to_string(),
+                    size: 4_500_000,
+                    extension: "mp3".to_string(),
+                    attributes: vec![
+                        Attribute::bitrate(320),
+                        Attribute::duration(183),
+                        Attribute::vbr(false),
+                    ],
+                }],
+            }],
+        };
+
+        let mut vec = vec![];
+        let mut buff = BufWriter::new(&mut vec);
+        block_on(shared_dirs.write_to_buf(&mut buff)).unwrap();
+        let mut cursor = std::io::Cursor::new(buff.buffer());
+        cursor.advance(8);
+
+        let parse_result = SharedDirectories::parse(&mut cursor).unwrap();
+        let attributes = &parse_result.dirs[0].files[0].attributes;
+
+        assert_eq!(attributes[0].place, Attribute::BITRATE);
+        assert_eq!(attributes[0].attribute, 320);
+        assert_eq!(attributes[1].place, Attribute::DURATION);
+        assert_eq!(attributes[1].attribute, 183);
+        assert_eq!(parse_result, shared_dirs);
+    }
 }
@@ -1,7 +1,10 @@
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, write_string, ParseBytes, ToBytes, STR_LENGTH_PREFIX},
+    frame::{
+        read_string, read_u32, read_u64, read_u8, write_string, ParseBytes, ToBytes,
+        STR_LENGTH_PREFIX,
+    },
     peers::p2p::PeerMessageCode,
 };
 use bytes::Buf;
@@ -39,20 +42,27 @@ impl ToBytes for QueueUpload {
 
 #[derive(Debug, Serialize)]
 pub struct TransferRequest {
-    direction: u32,
+    pub direction: u32,
     pub ticket: u32,
     pub filename: String,
     pub file_size: Option<u64>,
 }
 
+impl TransferRequest {
+    /// The peer wants to download a file from us.
+    pub const DOWNLOAD: u32 = 0;
+    /// The peer wants to upload a file to us, `file_size` is set.
+    pub const UPLOAD: u32 = 1;
+}
+
 impl ParseBytes for TransferRequest {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let direction = src.get_u32_le();
-        let ticket = src.get_u32_le();
+        let direction = read_u32(src)?;
+        let ticket = read_u32(src)?;
         let filename = read_string(src)?;
 
         let file_size = if direction == 1 {
-            Some(src.get_u64_le())
+            Some(read_u64(src)?)
         } else {
             None
         };
@@ -72,14 +82,38 @@ impl ToBytes for TransferRequest {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        todo!()
+        let file_size_len = if self.file_size.is_some() { 8 } else { 0 };
+        let len = 4 + 4 + 4 + STR_LENGTH_PREFIX + self.filename.len() as u32 + file_size_len;
+
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(PeerMessageCode::TransferRequest as u32)
+            .await?;
+        buffer.write_u32_le(self.direction).await?;
+        buffer.write_u32_le(self.ticket).await?;
+        write_string(&self.filename, buffer).await?;
+
+        if let Some(file_size) = self.file_size {
+            buffer.write_u64_le(file_size).await?;
+        }
+
+        Ok(())
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct PlaceInQueueReply {
-    filename: String,
-    place: String,
+    pub filename: String,
+    pub place: u32,
+}
+
+impl ParseBytes for PlaceInQueueReply {
+    fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let filename = read_string(src)?;
+        let place = read_u32(src)?;
+
+        Ok(Self { filename, place })
+    }
 }
 
 #[async_trait]
@@ -88,13 +122,22 @@ impl ToBytes for PlaceInQueueReply {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        todo!()
+        let len = STR_LENGTH_PREFIX + self.filename.len() as u32 + 4 + 4;
+
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(PeerMessageCode::PlaceInQueueReply as u32)
+            .await?;
+        write_string(&self.filename, buffer).await?;
+        buffer.write_u32_le(self.place).await?;
+
+        Ok(())
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct UploadFailed {
-    filename: String,
+    pub filename: String,
 }
 
 impl ParseBytes for UploadFailed {
@@ -111,14 +154,22 @@ impl ToBytes for UploadFailed {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        todo!()
+        let len = STR_LENGTH_PREFIX + self.filename.len() as u32 + 4;
+
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(PeerMessageCode::UploadFailed as u32)
+            .await?;
+        write_string(&self.filename, buffer).await?;
+
+        Ok(())
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct QueueFailed {
-    filename: String,
-    reason: String,
+    pub filename: String,
+    pub reason: String,
 }
 
 #[async_trait]
@@ -155,7 +206,7 @@ impl ParseBytes for QueueFailed {
 
 #[derive(Debug, Serialize)]
 pub struct PlaceInQueueRequest {
-    file_name: String,
+    pub file_name: String,
 }
 
 impl ParseBytes for PlaceInQueueRequest {
@@ -171,7 +222,15 @@ impl ToBytes for PlaceInQueueRequest {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        todo!()
+        let len = STR_LENGTH_PREFIX + self.file_name.len() as u32 + 4;
+
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(PeerMessageCode::PlaceInQueueRequest as u32)
+            .await?;
+        write_string(&self.file_name, buffer).await?;
+
+        Ok(())
     }
 }
 
@@ -181,6 +240,27 @@ pub enum TransferReply {
     TransferRejected { ticket: u32, reason: String },
 }
 
+impl ParseBytes for TransferReply {
+    fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let ticket = read_u32(src)?;
+        let allowed = read_u8(src)? == 1;
+
+        if allowed {
+            // The file size is only sent when replying to an upload request
+            let file_size = if src.has_remaining() {
+                read_u64(src)?
+            } else {
+                0
+            };
+
+            Ok(TransferReply::TransferReplyOk { ticket, file_size })
+        } else {
+            let reason = read_string(src)?;
+            Ok(TransferReply::TransferRejected { ticket, reason })
+        }
+    }
+}
+
 #[async_trait]
 impl ToBytes for TransferReply {
     async fn write_to_buf(
@@ -1,17 +1,19 @@
 use std::io::Cursor;
 
-use bytes::Buf;
 use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 use crate::{
-    frame::{read_string, write_string, ParseBytes, ToBytes},
+    frame::{
+        read_bytes, read_string, read_u32, read_u8, write_bytes, write_string, ParseBytes, ToBytes,
+    },
     peers::p2p::PeerMessageCode,
 };
 
 #[derive(Debug, Serialize)]
 pub struct UserInfo {
     pub description: String,
-    pub picture: Option<String>,
+    /// Raw image bytes, usually a jpeg or png
+    pub picture: Option<Vec<u8>>,
     pub total_upload: u32,
     pub queue_size: u32,
     pub slots_free: bool,
@@ -20,15 +22,15 @@ pub struct UserInfo {
 impl ParseBytes for UserInfo {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
         let description = read_string(src)?;
-        let has_picture = src.get_u8() != 0;
+        let has_picture = read_u8(src)? != 0;
         let picture = if has_picture {
-            read_string(src).ok()
+            Some(read_bytes(src)?)
         } else {
             None
         };
-        let total_upload = src.get_u32_le();
-        let queue_size = src.get_u32_le();
-        let slots_free = src.get_u8() == 1;
+        let total_upload = read_u32(src)?;
+        let queue_size = read_u32(src)?;
+        let slots_free = read_u8(src)? == 1;
 
         Ok(UserInfo {
             description,
@@ -46,7 +48,7 @@ impl ToBytes for UserInfo {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        let description = 4 + self.description.bytes().len() as u32;
+        let description = 4 + self.description.len() as u32;
         let has_picture = 1;
         let picture_len = self
             .picture
@@ -69,7 +71,7 @@ impl ToBytes for UserInfo {
         write_string(&self.description, buffer).await?;
         if let Some(picture) = &self.picture {
             buffer.write_u8(1).await?;
-            write_string(picture, buffer).await?;
+            write_bytes(picture, buffer).await?;
         } else {
             buffer.write_u8(0).await?;
         }
@@ -80,3 +82,78 @@ impl ToBytes for UserInfo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use bytes::Buf;
+    use tokio::io::BufWriter;
+    use tokio_test::block_on;
+
+    use crate::{
+        frame::{ParseBytes, ToBytes},
+        peers::p2p::PeerMessageCode,
+    };
+
+    use super::UserInfo;
+
+    fn write(user_info: &UserInfo) -> Vec<u8> {
+        let mut vec = vec![];
+        let mut buff = BufWriter::new(&mut vec);
+        block_on(user_info.write_to_buf(&mut buff)).unwrap();
+        buff.buffer().to_vec()
+    }
+
+    #[test]
+    fn write_user_info_with_picture_ok() {
+        let user_info = UserInfo {
+            description: "Hello".to_string(),
+            // Not valid utf-8
+            picture: Some(vec![0xff, 0xd8, 0xff, 0xe0]),
+            total_upload: 2,
+            queue_size: 3,
+            slots_free: true,
+        };
+
+        let data = write(&user_info);
+        let mut cursor = Cursor::new(data.as_slice());
+
+        let len = cursor.get_u32_le();
+        let code = cursor.get_u32_le();
+        assert_eq!(len as usize, data.len() - 4);
+        assert_eq!(code, PeerMessageCode::UserInfoReply as u32);
+
+        let parsed = UserInfo::parse(&mut cursor).unwrap();
+
+        assert_eq!(parsed.description, "Hello");
+        assert_eq!(parsed.picture, Some(vec![0xff, 0xd8, 0xff, 0xe0]));
+        assert_eq!(parsed.total_upload, 2);
+        assert_eq!(parsed.queue_size, 3);
+        assert!(parsed.slots_free);
+        assert!(!cursor.has_remaining());
+    }
+
+    #[test]
+    fn write_user_info_without_picture_ok() {
+        let user_info = UserInfo {
+            description: "Hello".to_string(),
+            picture: None,
+            total_upload: 0,
+            queue_size: 0,
+            slots_free: false,
+        };
+
+        let data = write(&user_info);
+        let mut cursor = Cursor::new(data.as_slice());
+
+        let len = cursor.get_u32_le();
+        cursor.get_u32_le();
+        assert_eq!(len as usize, data.len() - 4);
+
+        let parsed = UserInfo::parse(&mut cursor).unwrap();
+
+        assert_eq!(parsed.picture, None);
+        assert!(!parsed.slots_free);
+    }
+}
@@ -0,0 +1,285 @@
+use std::{convert::Infallible, fmt, str::FromStr};
+
+use crate::peers::p2p::shared_directories::File;
+
+/// A parsed Soulseek search query.
+///
+/// Soulseek queries are a whitespace separated list of terms combined with an implicit `AND` :
+/// - `nirvana nevermind` : both words must be present in the file path.
+/// - `-live` : the word "live" must not be present in the file path.
+/// - `*ana` : at least one word of the file path must end with "ana" (matches "Nirvana").
+///
+/// Terms are case insensitive and punctuation is treated as a word separator, `AC/DC` is
+/// equivalent to the word sequence `ac dc`.
+///
+/// ## Example :
+/// ```
+/// # use soulseek_protocol::query::Query;
+/// let query = Query::parse("nirvana -live *mind");
+///
+/// assert!(query.matches("@@music\\Nirvana\\1991 - Nevermind\\01 - Smells Like Teen Spirit.flac"));
+/// assert!(!query.matches("@@music\\Nirvana\\Live at Reading\\01 - Breed.flac"));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Term {
+    /// A sequence of words that must be present in the matched path.
+    Include(String),
+    /// A sequence of words, prefixed with `-` in the raw query, that must not be present in
+    /// the matched path.
+    Exclude(String),
+    /// A word suffix, prefixed with `*` in the raw query, at least one word of the matched path
+    /// must end with it.
+    Wildcard(String),
+}
+
+impl Query {
+    /// Parse a raw search query, terms that does not contain any word character are ignored.
+    pub fn parse(raw: &str) -> Self {
+        let terms = raw
+            .split_whitespace()
+            .map(|term| {
+                if let Some(excluded) = term.strip_prefix('-') {
+                    Term::Exclude(normalize(excluded))
+                } else if let Some(suffix) = term.strip_prefix('*') {
+                    Term::Wildcard(normalize(suffix))
+                } else {
+                    Term::Include(normalize(term))
+                }
+            })
+            .filter(|term| !term.value().is_empty())
+            .collect();
+
+        Query { terms }
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// A query without any [`Term::Include`] or [`Term::Wildcard`] term never matches anything.
+    pub fn is_empty(&self) -> bool {
+        self.terms
+            .iter()
+            .all(|term| matches!(term, Term::Exclude(_)))
+    }
+
+    /// Returns true if every term of the query matches the given path.
+    pub fn matches(&self, path: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let path = Words::from(path);
+        self.terms.iter().all(|term| term.matches(&path))
+    }
+
+    /// Returns true if the query matches the full path of the given [`File`].
+    pub fn matches_file(&self, file: &File) -> bool {
+        self.matches(&file.name)
+    }
+}
+
+impl Term {
+    fn value(&self) -> &str {
+        match self {
+            Term::Include(words) | Term::Exclude(words) | Term::Wildcard(words) => words,
+        }
+    }
+
+    fn matches(&self, path: &Words) -> bool {
+        match self {
+            Term::Include(words) => path.contains_sequence(words),
+            Term::Exclude(words) => !path.contains_sequence(words),
+            Term::Wildcard(suffix) => path.has_word_ending_with(suffix),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = Infallible;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        Ok(Query::parse(raw))
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| match term {
+                Term::Include(words) => words.clone(),
+                Term::Exclude(words) => format!("-{}", words.replace(' ', "-")),
+                Term::Wildcard(suffix) => format!("*{}", suffix),
+            })
+            .collect();
+
+        write!(f, "{}", terms.join(" "))
+    }
+}
+
+/// Lowercase the input and replace any sequence of non alphanumeric characters with a single
+/// space.
+pub(crate) fn normalize(raw: &str) -> String {
+    raw.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// A normalized path, padded with spaces so word sequences can be matched with a plain
+/// substring search.
+pub(crate) struct Words(String);
+
+impl From<&str> for Words {
+    fn from(path: &str) -> Self {
+        Words(format!(" {} ", normalize(path)))
+    }
+}
+
+impl Words {
+    pub(crate) fn contains_sequence(&self, words: &str) -> bool {
+        self.0.contains(&format!(" {} ", words))
+    }
+
+    fn has_word_ending_with(&self, suffix: &str) -> bool {
+        self.0.split_whitespace().any(|word| word.ends_with(suffix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        peers::p2p::shared_directories::File,
+        query::{Query, Term},
+    };
+
+    #[test]
+    fn should_parse_implicit_and() {
+        let query = Query::parse("Nirvana   Nevermind");
+
+        assert_eq!(
+            query.terms(),
+            &[
+                Term::Include("nirvana".to_string()),
+                Term::Include("nevermind".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_excluded_and_wildcard_terms() {
+        let query = Query::parse("nirvana -live *ana");
+
+        assert_eq!(
+            query.terms(),
+            &[
+                Term::Include("nirvana".to_string()),
+                Term::Exclude("live".to_string()),
+                Term::Wildcard("ana".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn should_normalize_punctuation() {
+        let query = Query::parse("\"AC/DC\" -Back_In");
+
+        assert_eq!(
+            query.terms(),
+            &[
+                Term::Include("ac dc".to_string()),
+                Term::Exclude("back in".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_ignore_empty_terms() {
+        let query = Query::parse("nirvana - * -- \"\"");
+
+        assert_eq!(query.terms(), &[Term::Include("nirvana".to_string())]);
+    }
+
+    #[test]
+    fn should_match_all_terms() {
+        let query = Query::parse("nirvana nevermind");
+
+        assert!(query.matches("@@music\\Nirvana\\Nevermind\\01 - Breed.flac"));
+        assert!(!query.matches("@@music\\Nirvana\\In Utero\\01 - Serve the Servants.flac"));
+    }
+
+    #[test]
+    fn should_match_whole_words_only() {
+        let query = Query::parse("live");
+
+        assert!(query.matches("Nirvana/Live at Reading/01 - Breed.flac"));
+        assert!(!query.matches("Nirvana/Unplugged/Oliver.flac"));
+    }
+
+    #[test]
+    fn should_exclude_terms() {
+        let query = Query::parse("nirvana -live");
+
+        assert!(query.matches("Nirvana/Nevermind/01 - Breed.flac"));
+        assert!(!query.matches("Nirvana/LIVE at Reading/01 - Breed.flac"));
+    }
+
+    #[test]
+    fn should_match_wildcard_suffix() {
+        let query = Query::parse("*vana");
+
+        assert!(query.matches("Nirvana/Nevermind/01 - Breed.flac"));
+        assert!(query.matches("Vana/Nevermind/01 - Breed.flac"));
+        assert!(!query.matches("Pixies/Doolittle/01 - Debaser.flac"));
+    }
+
+    #[test]
+    fn should_match_word_sequences() {
+        let query = Query::parse("ac/dc");
+
+        assert!(query.matches("AC-DC/Back In Black/01 - Hells Bells.mp3"));
+        assert!(!query.matches("DC/AC/01 - Unknown.mp3"));
+    }
+
+    #[test]
+    fn should_not_match_empty_query() {
+        assert!(!Query::parse("").matches("Nirvana/Nevermind/01 - Breed.flac"));
+        assert!(!Query::parse("-live").matches("Nirvana/Nevermind/01 - Breed.flac"));
+    }
+
+    #[test]
+    fn should_match_unicode_words() {
+        let query = Query::parse("björk");
+
+        assert!(query.matches("Björk/Homogenic/01 - Hunter.flac"));
+    }
+
+    #[test]
+    fn should_match_file() {
+        let query = Query::parse("breed");
+        let file = File {
+            name: "@@music\\Nirvana\\Nevermind\\01 - Breed.flac".to_string(),
+            size: 0,
+            extension: "flac".to_string(),
+            attributes: vec![],
+        };
+
+        assert!(query.matches_file(&file));
+    }
+
+    #[test]
+    fn should_display_query() {
+        let query = Query::parse("Nirvana -live-at *ANA");
+
+        assert_eq!(query.to_string(), "nirvana -live-at *ana");
+        assert_eq!(Query::parse(&query.to_string()), query);
+    }
+}
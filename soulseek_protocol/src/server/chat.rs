@@ -56,11 +56,11 @@ impl ParseBytes for ChatMessage {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PrivateMessage {
-    id: u32,
-    timestamp: u32,
-    username: String,
-    message: String,
-    is_new: bool,
+    pub id: u32,
+    pub timestamp: u32,
+    pub username: String,
+    pub message: String,
+    pub is_new: bool,
 }
 
 impl ParseBytes for PrivateMessage {
@@ -81,8 +81,60 @@ impl ParseBytes for PrivateMessage {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageUser {
+    pub username: String,
+    pub message: String,
+}
+
+#[async_trait]
+impl ToBytes for MessageUser {
+    async fn write_to_buf(
+        &self,
+        buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+    ) -> tokio::io::Result<()> {
+        let len = 4
+            + STR_LENGTH_PREFIX
+            + self.username.len() as u32
+            + STR_LENGTH_PREFIX
+            + self.message.len() as u32;
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(MessageCode::PrivateMessages as u32)
+            .await?;
+        write_string(&self.username, buffer).await?;
+        write_string(&self.message, buffer).await?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GroupMessage {
-    users: Vec<String>,
-    message: String,
+    pub users: Vec<String>,
+    pub message: String,
+}
+
+#[async_trait]
+impl ToBytes for GroupMessage {
+    async fn write_to_buf(
+        &self,
+        buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+    ) -> tokio::io::Result<()> {
+        let users_len: u32 = self
+            .users
+            .iter()
+            .map(|user| STR_LENGTH_PREFIX + user.len() as u32)
+            .sum();
+        let len = 4 + 4 + users_len + STR_LENGTH_PREFIX + self.message.len() as u32;
+        buffer.write_u32_le(len).await?;
+        buffer
+            .write_u32_le(MessageCode::MessageUsers as u32)
+            .await?;
+        buffer.write_u32_le(self.users.len() as u32).await?;
+        for user in &self.users {
+            write_string(user, buffer).await?;
+        }
+        write_string(&self.message, buffer).await?;
+        Ok(())
+    }
 }
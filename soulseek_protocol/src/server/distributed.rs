@@ -1,4 +1,8 @@
-use crate::frame::ParseBytes;
+use crate::{
+    frame::ParseBytes,
+    peers::distributed::{DistributedMessage, DistributedMessageCode, DistributedMessageHeader},
+    ProtocolHeader, ProtocolMessage,
+};
 use bytes::Buf;
 use std::io::Cursor;
 
@@ -16,3 +20,15 @@ impl ParseBytes for EmbeddedDistributedMessage {
         Ok(Self { code, message })
     }
 }
+
+impl EmbeddedDistributedMessage {
+    /// Parse the distributed message the server sent us as a branch root.
+    pub fn distributed_message(&self) -> std::io::Result<DistributedMessage> {
+        let header = DistributedMessageHeader::new(
+            self.message.len() + 1,
+            DistributedMessageCode::from(self.code),
+        );
+
+        DistributedMessage::parse(&mut Cursor::new(&self.message), &header)
+    }
+}
@@ -134,3 +134,29 @@ impl ParseBytes for LoginResponse {
         }
     }
 }
+
+/// State of our session with the Soulseek server, driven by [`LoginResponse`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "state", content = "reason", rename_all = "snake_case")]
+pub enum SessionState {
+    /// No connection with the server
+    #[default]
+    Disconnected,
+    /// Waiting for the server TCP connection
+    Connecting,
+    /// A [`LoginRequest`] was sent, waiting for the [`LoginResponse`]
+    LoggingIn,
+    /// The server accepted our credentials
+    LoggedIn,
+    /// The server rejected our login, with the reason it gave
+    Failed(String),
+}
+
+impl From<&LoginResponse> for SessionState {
+    fn from(response: &LoginResponse) -> Self {
+        match response {
+            LoginResponse::Failure { reason } => SessionState::Failed(reason.clone()),
+            LoginResponse::Success { .. } => SessionState::LoggedIn,
+        }
+    }
+}
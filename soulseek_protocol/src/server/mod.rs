@@ -102,6 +102,7 @@ pub enum MessageCode {
     ParentsIp = 73,
     ParentMinSpeed = 83,
     ParentSpeedRatio = 84,
+    AddPrivilegedUser = 91,
     CheckPrivileges = 92,
     EmbeddedMessage = 93,
     AcceptChildren = 100,
@@ -142,6 +143,7 @@ pub enum MessageCode {
     AskPublicChat = 150,
     StopPublicChat = 151,
     PublicChatMessage = 152,
+    ExcludedSearchPhrases = 160,
     CantConnectToPeer = 1001,
     CantCreateRoom = 1003,
     Unknown,
@@ -184,6 +186,7 @@ impl From<u32> for MessageCode {
             73 => MessageCode::ParentsIp,
             83 => MessageCode::ParentMinSpeed,
             84 => MessageCode::ParentSpeedRatio,
+            91 => MessageCode::AddPrivilegedUser,
             92 => MessageCode::CheckPrivileges,
             93 => MessageCode::EmbeddedMessage,
             100 => MessageCode::AcceptChildren,
@@ -224,6 +227,7 @@ impl From<u32> for MessageCode {
             150 => MessageCode::AskPublicChat,
             151 => MessageCode::StopPublicChat,
             152 => MessageCode::PublicChatMessage,
+            160 => MessageCode::ExcludedSearchPhrases,
             1001 => MessageCode::CantConnectToPeer,
             1002 => MessageCode::CantCreateRoom,
             _ => MessageCode::Unknown,
@@ -2,7 +2,7 @@ use crate::{
     frame::{write_string, ToBytes},
     server::{
         admin::AdminCommand,
-        chat::{GroupMessage, SayInChat},
+        chat::{GroupMessage, MessageUser, SayInChat},
         login::LoginRequest,
         peer::{PeerConnectionTicket, RequestConnectionToPeer},
         privilege::PrivilegesGift,
@@ -68,6 +68,11 @@ pub enum ServerRequest {
     /// **Response** : no message
     AcknowledgePrivateMessage(u32),
 
+    ///  **Description** : We send this to the server to send a private message to a user.
+    ///
+    /// **Response** : no message
+    SendPrivateMessage(MessageUser),
+
     ///  **Description** : We send this to the server when we search for something. Alternatively,
     /// the server sends this message outside the distributed network to tell us that someone
     /// is searching for something, currently used for UserSearch and RoomSearch requests.
@@ -313,6 +318,20 @@ pub enum ServerRequest {
     CantConnectToPeer(PeerConnectionTicket),
 }
 
+impl ServerRequest {
+    /// Ticket of a file, user, room or wishlist search, `None` for any other request.
+    pub fn search_ticket(&self) -> Option<u32> {
+        match self {
+            ServerRequest::FileSearch(request) | ServerRequest::WishlistSearch(request) => {
+                Some(request.ticket)
+            }
+            ServerRequest::UserSearch(query) => Some(query.ticket),
+            ServerRequest::RoomSearch(query) => Some(query.ticket),
+            _ => None,
+        }
+    }
+}
+
 #[async_trait]
 impl ToBytes for ServerRequest {
     async fn write_to_buf(
@@ -361,6 +380,7 @@ impl ToBytes for ServerRequest {
             ServerRequest::AcknowledgePrivateMessage(message_id) => {
                 write_u32_msg(*message_id, MessageCode::AcknowledgePrivateMessage, buffer).await
             }
+            ServerRequest::SendPrivateMessage(message) => message.write_to_buf(buffer).await,
             ServerRequest::FileSearch(query) => {
                 query
                     .write_to_buf_with_code(buffer, MessageCode::FileSearch)
@@ -411,12 +431,18 @@ impl ToBytes for ServerRequest {
             ServerRequest::SetRoomTicker(_) => todo!(),
             ServerRequest::AddHatedInterest(_) => todo!(),
             ServerRequest::RemoveHatedInterest(_) => todo!(),
-            ServerRequest::RoomSearch(_) => todo!(),
+            ServerRequest::RoomSearch(query) => query.write_to_buf(buffer).await,
             ServerRequest::SendUploadSpeed(_) => todo!(),
-            ServerRequest::GivePrivileges(_) => todo!(),
-            ServerRequest::BranchLevel(_) => todo!(),
-            ServerRequest::BranchRoot(_) => todo!(),
-            ServerRequest::ChildDepth(_) => todo!(),
+            ServerRequest::GivePrivileges(gift) => gift.write_to_buf(buffer).await,
+            ServerRequest::BranchLevel(level) => {
+                write_u32_msg(*level, MessageCode::BranchLevel, buffer).await
+            }
+            ServerRequest::BranchRoot(root) => {
+                write_str_msg(root, MessageCode::BranchRoot, buffer).await
+            }
+            ServerRequest::ChildDepth(depth) => {
+                write_u32_msg(*depth, MessageCode::ChildDepth, buffer).await
+            }
             ServerRequest::AddUserToPrivateRoom(_) => todo!(),
             ServerRequest::RemoveUserFromPrivateRoom(_) => todo!(),
             ServerRequest::PrivateRoomDropMemberShip(room) => {
@@ -432,7 +458,7 @@ impl ToBytes for ServerRequest {
             ServerRequest::NewPassWord(_) => todo!(),
             ServerRequest::PrivateRoomAddOperator(_) => todo!(),
             ServerRequest::PrivateRoomRemoveOperator(_) => todo!(),
-            ServerRequest::MessageUsers(_) => todo!(),
+            ServerRequest::MessageUsers(message) => message.write_to_buf(buffer).await,
             ServerRequest::CantConnectToPeer(ticket) => ticket.write_to_buf(buffer).await,
         }
     }
@@ -494,7 +520,12 @@ mod tests {
     use crate::{
         frame::ToBytes,
         server::{
-            chat::SayInChat, login::LoginRequest, request::ServerRequest, room::UserRoomEvent,
+            chat::{GroupMessage, MessageUser, SayInChat},
+            login::LoginRequest,
+            privilege::PrivilegesGift,
+            request::ServerRequest,
+            room::UserRoomEvent,
+            search::{RoomSearchQuery, SearchQuery},
             shares::SharedFolderAndFiles,
         },
     };
@@ -659,6 +690,87 @@ mod tests {
         assert_eq!(&data[8..], b"\x08\x00\x00\x00nicotine\x05\x00\x00\x00admin");
     }
 
+    #[test]
+    fn send_private_message() {
+        let private_message = ServerRequest::SendPrivateMessage(MessageUser {
+            username: "alice".to_string(),
+            message: "hi".to_string(),
+        });
+
+        let data = write_to_buff_blocking(private_message);
+
+        assert_eq!(&data[0..4], [19, 0, 0, 0]);
+        assert_eq!(&data[4..8], [22, 0, 0, 0]);
+        assert_eq!(&data[8..], b"\x05\x00\x00\x00alice\x02\x00\x00\x00hi");
+    }
+
+    #[test]
+    fn message_users() {
+        let message_users = ServerRequest::MessageUsers(GroupMessage {
+            users: vec!["alice".to_string(), "bob".to_string()],
+            message: "hi".to_string(),
+        });
+
+        let data = write_to_buff_blocking(message_users);
+
+        assert_eq!(&data[0..4], [30, 0, 0, 0]);
+        assert_eq!(&data[4..8], [149, 0, 0, 0]);
+        assert_eq!(
+            &data[8..],
+            b"\x02\x00\x00\x00\x05\x00\x00\x00alice\x03\x00\x00\x00bob\x02\x00\x00\x00hi"
+        );
+    }
+
+    #[test]
+    fn user_search() {
+        let user_search = ServerRequest::UserSearch(SearchQuery {
+            username: "alice".to_string(),
+            ticket: 1,
+            query: "nirvana".to_string(),
+        });
+
+        let data = write_to_buff_blocking(user_search);
+
+        assert_eq!(&data[0..4], [28, 0, 0, 0]);
+        assert_eq!(&data[4..8], [42, 0, 0, 0]);
+        assert_eq!(
+            &data[8..],
+            b"\x05\x00\x00\x00alice\x01\x00\x00\x00\x07\x00\x00\x00nirvana"
+        );
+    }
+
+    #[test]
+    fn room_search() {
+        let room_search = ServerRequest::RoomSearch(RoomSearchQuery {
+            room: "indie".to_string(),
+            ticket: 1,
+            query: "nirvana".to_string(),
+        });
+
+        let data = write_to_buff_blocking(room_search);
+
+        assert_eq!(&data[0..4], [28, 0, 0, 0]);
+        assert_eq!(&data[4..8], [120, 0, 0, 0]);
+        assert_eq!(
+            &data[8..],
+            b"\x05\x00\x00\x00indie\x01\x00\x00\x00\x07\x00\x00\x00nirvana"
+        );
+    }
+
+    #[test]
+    fn give_privileges() {
+        let give_privileges = ServerRequest::GivePrivileges(PrivilegesGift {
+            username: "alice".to_string(),
+            days: 3,
+        });
+
+        let data = write_to_buff_blocking(give_privileges);
+
+        assert_eq!(&data[0..4], [17, 0, 0, 0]);
+        assert_eq!(&data[4..8], [123, 0, 0, 0]);
+        assert_eq!(&data[8..], b"\x05\x00\x00\x00alice\x03\x00\x00\x00");
+    }
+
     #[test]
     fn check_privileges() {
         let check_privileges = ServerRequest::CheckPrivileges;
@@ -720,7 +832,8 @@ mod tests {
 
         let data = write_to_buff_blocking(shared_folders);
 
-        assert_eq!(&data[8..], [4, 0, 0, 0, 47, 0, 0, 0]);
+        assert_eq!(&data[0..4], [12, 0, 0, 0]);
+        assert_eq!(&data[8..], [2, 0, 0, 0, 3, 0, 0, 0]);
     }
 
     #[test]
@@ -741,6 +854,16 @@ mod tests {
         assert_eq!(&data[8..], [7, 0, 0, 0, 111, 107, 110, 111, 122, 111, 114]);
     }
 
+    #[test]
+    fn child_depth() {
+        let child_depth = ServerRequest::ChildDepth(2);
+
+        let data = write_to_buff_blocking(child_depth);
+
+        assert_eq!(&data[0..8], [8, 0, 0, 0, 129, 0, 0, 0]);
+        assert_eq!(&data[8..], [2, 0, 0, 0]);
+    }
+
     #[test]
     fn toggle_private_rooms() {
         let branch_root = ServerRequest::PrivateRoomToggle(true);
@@ -11,7 +11,7 @@ use crate::{
         login::*,
         peer::{Peer, PeerAddress, PeerConnectionRequest, PeerConnectionTicket},
         room::*,
-        search::SearchQuery,
+        search::{ExcludedSearchPhrases, SearchQuery},
         user::*,
         Header, MessageCode, HEADER_LEN,
     },
@@ -44,6 +44,7 @@ pub enum ServerResponse {
     RoomList(RoomList),
     AdminMessage(String),
     PrivilegedUsers(UserList),
+    PrivilegedUserAdded(String),
     ParentMinSpeed(u32),
     ParentSpeedRatio(u32),
     TimeLeft(u32),
@@ -70,6 +71,7 @@ pub enum ServerResponse {
     RoomOperatorRemoved(String),
     RoomOperators(RoomUsers),
     PublicChatMessage(ChatMessage),
+    ExcludedSearchPhrases(ExcludedSearchPhrases),
     CantConnectToPeer(PeerConnectionTicket),
     CantCreateRoom(String),
     Unknown(u32, u32, Vec<u8>), // length, code, raw bytes,
@@ -137,6 +139,9 @@ impl ServerResponse {
             }
             MessageCode::ParentMinSpeed => Ok(ServerResponse::ParentMinSpeed(src.get_u32_le())),
             MessageCode::ParentSpeedRatio => Ok(ServerResponse::ParentSpeedRatio(src.get_u32_le())),
+            MessageCode::AddPrivilegedUser => {
+                read_string(src).map(ServerResponse::PrivilegedUserAdded)
+            }
             MessageCode::CheckPrivileges => Ok(ServerResponse::TimeLeft(src.get_u32_le())),
             MessageCode::EmbeddedMessage => {
                 EmbeddedDistributedMessage::parse(src).map(ServerResponse::EmbeddedMessage)
@@ -197,6 +202,9 @@ impl ServerResponse {
             MessageCode::PublicChatMessage => {
                 ChatMessage::parse(src).map(ServerResponse::PublicChatMessage)
             }
+            MessageCode::ExcludedSearchPhrases => {
+                ExcludedSearchPhrases::parse(src).map(ServerResponse::ExcludedSearchPhrases)
+            }
             MessageCode::CantConnectToPeer => {
                 PeerConnectionTicket::parse(src).map(ServerResponse::CantConnectToPeer)
             }
@@ -1,5 +1,8 @@
 use crate::{
-    frame::{read_string, write_string, ParseBytes, ToBytes, STR_LENGTH_PREFIX},
+    frame::{
+        capacity, read_string, read_u32, write_string, ParseBytes, ToBytes, STR_LENGTH_PREFIX,
+    },
+    query::{normalize, Words},
     server::MessageCode,
 };
 use bytes::Buf;
@@ -30,9 +33,9 @@ impl SearchRequest {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SearchQuery {
-    username: String,
-    ticket: u32,
-    query: String,
+    pub username: String,
+    pub ticket: u32,
+    pub query: String,
 }
 
 #[async_trait]
@@ -41,21 +44,14 @@ impl ToBytes for SearchQuery {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        let len = STR_LENGTH_PREFIX
-            + self.username.bytes().len() as u32
-            + 4
-            + STR_LENGTH_PREFIX
-            + self.username.bytes().len() as u32;
-
-        buffer.write_u32_le(len).await?;
-        buffer
-            .write_u32_le(MessageCode::SharedFoldersAndFiles as u32)
-            .await?;
-        write_string(&self.username, buffer).await?;
-        buffer.write_u32_le(self.ticket).await?;
-        write_string(&self.query, buffer).await?;
-
-        Ok(())
+        write_targeted_search(
+            &self.username,
+            self.ticket,
+            &self.query,
+            MessageCode::UserSearch,
+            buffer,
+        )
+        .await
     }
 }
 
@@ -75,7 +71,124 @@ impl ParseBytes for SearchQuery {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RoomSearchQuery {
-    room: String,
+    pub room: String,
+    pub ticket: u32,
+    pub query: String,
+}
+
+#[async_trait]
+impl ToBytes for RoomSearchQuery {
+    async fn write_to_buf(
+        &self,
+        buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+    ) -> tokio::io::Result<()> {
+        write_targeted_search(
+            &self.room,
+            self.ticket,
+            &self.query,
+            MessageCode::RoomSearch,
+            buffer,
+        )
+        .await
+    }
+}
+
+/// User and room searches share the same layout : the user or room name, the ticket and the query.
+async fn write_targeted_search(
+    target: &str,
     ticket: u32,
-    query: String,
+    query: &str,
+    code: MessageCode,
+    buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
+) -> tokio::io::Result<()> {
+    let len =
+        4 + STR_LENGTH_PREFIX + target.len() as u32 + 4 + STR_LENGTH_PREFIX + query.len() as u32;
+
+    buffer.write_u32_le(len).await?;
+    buffer.write_u32_le(code as u32).await?;
+    write_string(target, buffer).await?;
+    buffer.write_u32_le(ticket).await?;
+    write_string(query, buffer).await?;
+
+    Ok(())
+}
+
+/// A search held back by the client side search limit, reported to the SSE clients until it is
+/// sent to the server.
+#[derive(Debug, Serialize)]
+pub struct QueuedSearch {
+    pub ticket: u32,
+    /// Searches to be sent before this one, `None` once it was sent
+    pub position: Option<usize>,
+}
+
+/// A list of phrases the server does not want to see in search results, files containing one of
+/// them must not be sent back to the searching peer.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExcludedSearchPhrases {
+    pub phrases: Vec<String>,
+}
+
+impl ExcludedSearchPhrases {
+    /// Returns true if the given path contains one of the excluded phrases.
+    pub fn excludes(&self, path: &str) -> bool {
+        let path = Words::from(path);
+        self.phrases
+            .iter()
+            .map(|phrase| normalize(phrase))
+            .filter(|phrase| !phrase.is_empty())
+            .any(|phrase| path.contains_sequence(&phrase))
+    }
+}
+
+impl ParseBytes for ExcludedSearchPhrases {
+    fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let phrase_nth = read_u32(src)?;
+        let mut phrases = Vec::with_capacity(capacity(phrase_nth, src));
+
+        for _ in 0..phrase_nth {
+            phrases.push(read_string(src)?);
+        }
+
+        Ok(Self { phrases })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{frame::ParseBytes, server::search::ExcludedSearchPhrases};
+
+    #[test]
+    fn should_parse_excluded_search_phrases() {
+        let data = b"\x02\x00\x00\x00\x08\x00\x00\x00bad word\x04\x00\x00\x00evil";
+        let mut cursor = Cursor::new(&data[..]);
+
+        let excluded = ExcludedSearchPhrases::parse(&mut cursor).unwrap();
+
+        assert_eq!(excluded.phrases, vec!["bad word", "evil"]);
+    }
+
+    #[test]
+    fn should_reject_truncated_excluded_search_phrases() {
+        // Too short for the phrase count
+        let mut cursor = Cursor::new(&b"\x02\x00"[..]);
+        assert!(ExcludedSearchPhrases::parse(&mut cursor).is_err());
+
+        // Announces far more phrases than the frame holds
+        let mut cursor = Cursor::new(&b"\xff\xff\xff\xff\x04\x00\x00\x00evil"[..]);
+        assert!(ExcludedSearchPhrases::parse(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn should_exclude_paths_containing_phrases() {
+        let excluded = ExcludedSearchPhrases {
+            phrases: vec!["Bad Word".to_string()],
+        };
+
+        assert!(excluded.excludes("music/bad_word/01 - track.mp3"));
+        assert!(!excluded.excludes("music/word bad/01 - track.mp3"));
+        assert!(!excluded.excludes("music/badword/01 - track.mp3"));
+    }
 }
@@ -3,8 +3,8 @@ use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SharedFolderAndFiles {
-    pub(crate) dirs: u32,
-    pub(crate) files: u32,
+    pub dirs: u32,
+    pub files: u32,
 }
 
 #[async_trait]
@@ -13,7 +13,7 @@ impl ToBytes for SharedFolderAndFiles {
         &self,
         buffer: &mut BufWriter<impl AsyncWrite + Unpin + Send>,
     ) -> tokio::io::Result<()> {
-        buffer.write_u32_le(8).await?;
+        buffer.write_u32_le(12).await?;
         buffer
             .write_u32_le(MessageCode::SharedFoldersAndFiles as u32)
             .await?;
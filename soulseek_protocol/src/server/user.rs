@@ -2,13 +2,13 @@ use std::io::Cursor;
 
 use bytes::Buf;
 
-use crate::frame::{read_string, ParseBytes};
+use crate::frame::{read_string, read_u32, read_u64, read_u8, ParseBytes};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserStatus {
-    username: String,
-    status: Status,
-    privileged: bool,
+    pub username: String,
+    pub status: Status,
+    pub privileged: bool,
 }
 
 impl ParseBytes for UserStatus {
@@ -26,7 +26,7 @@ impl ParseBytes for UserStatus {
 }
 
 #[repr(u32)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 pub enum Status {
     Offline = 0,
     Away = 1,
@@ -38,8 +38,8 @@ impl From<u32> for Status {
     fn from(value: u32) -> Self {
         match value {
             0 => Status::Offline,
-            1 => Status::Online,
-            2 => Status::Away,
+            1 => Status::Away,
+            2 => Status::Online,
             _ => Status::Unknown,
         }
     }
@@ -48,7 +48,7 @@ impl From<u32> for Status {
 type Users = Vec<String>;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UserList(Users);
+pub struct UserList(pub Users);
 
 impl ParseBytes for UserList {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
@@ -64,7 +64,7 @@ impl ParseBytes for UserList {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UserAdded {
     Ok {
         username: String,
@@ -73,7 +73,7 @@ pub enum UserAdded {
         download_number: u64,
         files: u32,
         dirs: u32,
-        country_code: String,
+        country_code: Option<String>,
     },
     NotFound {
         username: String,
@@ -82,17 +82,24 @@ pub enum UserAdded {
 
 impl ParseBytes for UserAdded {
     fn parse(src: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
-        let code = src.get_u8();
         let username = read_string(src)?;
-        match code {
+        let exists = read_u8(src)?;
+
+        match exists {
             0 => Ok(UserAdded::NotFound { username }),
             1 => {
-                let status = src.get_u32_le();
-                let average_speed = src.get_u32_le();
-                let download_number = src.get_u64_le();
-                let files = src.get_u32_le();
-                let dirs = src.get_u32_le();
-                let country_code = read_string(src)?;
+                let status = read_u32(src)?;
+                let average_speed = read_u32(src)?;
+                let download_number = read_u64(src)?;
+                let files = read_u32(src)?;
+                let dirs = read_u32(src)?;
+
+                // Only sent for online users
+                let country_code = if src.has_remaining() {
+                    Some(read_string(src)?)
+                } else {
+                    None
+                };
 
                 Ok(UserAdded::Ok {
                     username,
@@ -104,18 +111,21 @@ impl ParseBytes for UserAdded {
                     country_code,
                 })
             }
-            _ => unreachable!(),
+            other => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid user exists flag {}", other),
+            )),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserStats {
-    username: String,
-    average_speed: u32,
-    download_number: u64,
-    files: u32,
-    dirs: u32,
+    pub username: String,
+    pub average_speed: u32,
+    pub download_number: u64,
+    pub files: u32,
+    pub dirs: u32,
 }
 
 impl ParseBytes for UserStats {
@@ -161,6 +171,27 @@ impl ParseBytes for UserData {
     }
 }
 
+/// A change of a buddy watched with [`crate::server::request::ServerRequest::AddUser`], advertised
+/// to the SSE clients.
+#[derive(Debug, Clone, Serialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum BuddyEvent {
+    Online {
+        username: String,
+    },
+    Away {
+        username: String,
+    },
+    Offline {
+        username: String,
+    },
+    SharesChanged {
+        username: String,
+        files: u32,
+        dirs: u32,
+    },
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UsersWithStatus {
     users: Vec<UserWithStatus>,
@@ -212,3 +243,73 @@ impl ParseBytes for ItemSimilarUsers {
         Ok(Self { item, users })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        frame::ParseBytes,
+        server::user::{Status, UserAdded, UserStatus},
+    };
+
+    #[test]
+    fn should_parse_user_status() {
+        let data = b"\x05\x00\x00\x00alice\x02\x00\x00\x00\x01";
+
+        let status = UserStatus::parse(&mut Cursor::new(data)).unwrap();
+
+        assert_eq!(status.username, "alice");
+        assert_eq!(status.status, Status::Online);
+        assert!(status.privileged);
+        assert_eq!(Status::from(1), Status::Away);
+        assert_eq!(Status::from(0), Status::Offline);
+    }
+
+    #[test]
+    fn should_parse_user_added() {
+        let data = b"\x05\x00\x00\x00alice\x01\x02\x00\x00\x00\x64\x00\x00\x00\
+            \x03\x00\x00\x00\x00\x00\x00\x00\x0a\x00\x00\x00\x01\x00\x00\x00\
+            \x02\x00\x00\x00FR";
+
+        match UserAdded::parse(&mut Cursor::new(data)).unwrap() {
+            UserAdded::Ok {
+                username,
+                status,
+                average_speed,
+                download_number,
+                files,
+                dirs,
+                country_code,
+            } => {
+                assert_eq!(username, "alice");
+                assert_eq!(status, 2);
+                assert_eq!(average_speed, 100);
+                assert_eq!(download_number, 3);
+                assert_eq!((files, dirs), (10, 1));
+                assert_eq!(country_code.as_deref(), Some("FR"));
+            }
+            added => panic!("Unexpected reply {:?}", added),
+        }
+
+        // Offline users come without a country code
+        let offline = b"\x03\x00\x00\x00bob\x01\x00\x00\x00\x00\x00\x00\x00\x00\
+            \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        assert!(matches!(
+            UserAdded::parse(&mut Cursor::new(offline)).unwrap(),
+            UserAdded::Ok {
+                country_code: None,
+                ..
+            }
+        ));
+
+        let unknown = b"\x03\x00\x00\x00eve\x00";
+        assert!(matches!(
+            UserAdded::parse(&mut Cursor::new(unknown)).unwrap(),
+            UserAdded::NotFound { username } if username == "eve"
+        ));
+
+        let invalid = b"\x03\x00\x00\x00eve\x05";
+        assert!(UserAdded::parse(&mut Cursor::new(invalid)).is_err());
+    }
+}
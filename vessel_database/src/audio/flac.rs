@@ -0,0 +1,78 @@
+use crate::audio::{average_bitrate, id3v2_len, invalid_data, u32_be, AudioMetadata};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+const STREAMINFO: u8 = 0;
+const STREAMINFO_LEN: usize = 34;
+
+pub(super) fn read<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<AudioMetadata> {
+    // Some taggers put an ID3v2 tag in front of the FLAC stream
+    let start = id3v2_len(reader)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    // STREAMINFO is always the first metadata block
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != b"fLaC" || header[4] & 0x7F != STREAMINFO {
+        return Err(invalid_data("Missing FLAC STREAMINFO block"));
+    }
+
+    let mut streaminfo = [0; STREAMINFO_LEN];
+    reader.read_exact(&mut streaminfo)?;
+
+    // 20 bits sample rate, 3 bits channels, 5 bits bit depth and 36 bits total samples
+    let sample_rate =
+        (streaminfo[10] as u32) << 12 | (streaminfo[11] as u32) << 4 | (streaminfo[12] as u32) >> 4;
+    let bit_depth = ((streaminfo[12] as u32 & 0b1) << 4 | (streaminfo[13] as u32) >> 4) + 1;
+    let total_samples = (streaminfo[13] as u64 & 0x0F) << 32 | u32_be(&streaminfo[14..]) as u64;
+
+    if sample_rate == 0 {
+        return Err(invalid_data("Invalid FLAC sample rate"));
+    }
+
+    Ok(AudioMetadata {
+        bitrate: average_bitrate(file_size.saturating_sub(start), total_samples, sample_rate),
+        duration: Some((total_samples / sample_rate as u64) as u32),
+        vbr: None,
+        sample_rate: Some(sample_rate),
+        bit_depth: Some(bit_depth),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::flac::read;
+    use crate::audio::AudioMetadata;
+    use std::io::Cursor;
+
+    #[test]
+    fn should_read_streaminfo() {
+        let mut file = b"fLaC\x80\x00\x00\x22".to_vec();
+        let mut streaminfo = [0u8; 34];
+        // 44100Hz, stereo, 16 bits, 2_646_000 samples (60 seconds)
+        streaminfo[10..18].copy_from_slice(&[0x0A, 0xC4, 0x42, 0xF0, 0x00, 0x28, 0x5F, 0xF0]);
+        file.extend_from_slice(&streaminfo);
+        file.resize(10_584_000, 0);
+
+        let metadata = read(&mut Cursor::new(file), 10_584_000).unwrap();
+
+        assert_eq!(
+            metadata,
+            AudioMetadata {
+                bitrate: Some(1411),
+                duration: Some(60),
+                vbr: None,
+                sample_rate: Some(44100),
+                bit_depth: Some(16),
+            }
+        );
+    }
+
+    #[test]
+    fn should_fail_on_invalid_stream() {
+        let file = b"OggS\x00\x00\x00\x00".to_vec();
+
+        assert!(read(&mut Cursor::new(file), 8).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Minimal audio header readers used to fill Soulseek file attributes while scanning shares.
+//!
+//! Only the few bytes needed to get bitrate, duration, sample rate and bit depth are read, the
+//! audio data itself is never decoded.
+use soulseek_protocol::peers::p2p::shared_directories::Attribute;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+mod flac;
+mod mp3;
+mod mp4;
+mod ogg;
+mod wav;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct AudioMetadata {
+    /// Bitrate in kbps
+    pub bitrate: Option<u32>,
+    /// Duration in seconds
+    pub duration: Option<u32>,
+    pub vbr: Option<bool>,
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+}
+
+impl AudioMetadata {
+    pub(crate) fn into_attributes(self) -> Vec<Attribute> {
+        let mut attributes = vec![];
+
+        if let Some(bitrate) = self.bitrate {
+            attributes.push(Attribute::bitrate(bitrate));
+        }
+
+        if let Some(duration) = self.duration {
+            attributes.push(Attribute::duration(duration));
+        }
+
+        if let Some(vbr) = self.vbr {
+            attributes.push(Attribute::vbr(vbr));
+        }
+
+        if let Some(sample_rate) = self.sample_rate {
+            attributes.push(Attribute::sample_rate(sample_rate));
+        }
+
+        if let Some(bit_depth) = self.bit_depth {
+            attributes.push(Attribute::bit_depth(bit_depth));
+        }
+
+        attributes
+    }
+}
+
+/// Read the audio metadata of the file at `path`, returns `Ok(None)` if the extension is not a
+/// supported audio format.
+pub(crate) fn read_metadata(path: &Path) -> io::Result<Option<AudioMetadata>> {
+    let extension = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => extension.to_lowercase(),
+        None => return Ok(None),
+    };
+
+    let reader: fn(&mut fs::File, u64) -> io::Result<AudioMetadata> = match extension.as_str() {
+        "mp3" => mp3::read,
+        "flac" => flac::read,
+        "ogg" | "oga" | "opus" => ogg::read,
+        "m4a" | "m4b" | "mp4" => mp4::read,
+        "wav" => wav::read,
+        _ => return Ok(None),
+    };
+
+    let mut file = fs::File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    reader(&mut file, file_size).map(Some)
+}
+
+/// Average bitrate in kbps of `bytes` of audio data lasting `samples / sample_rate` seconds,
+/// `None` when the header values don't make sense.
+fn average_bitrate(bytes: u64, samples: u64, sample_rate: u32) -> Option<u32> {
+    if samples == 0 {
+        return None;
+    }
+
+    let bits = (bytes as u128).checked_mul(8 * sample_rate as u128)?;
+    u32::try_from(bits / samples as u128 / 1000).ok()
+}
+
+/// Returns the length of the ID3v2 tag at the beginning of the stream, 0 if there is none.
+fn id3v2_len<R: Read + Seek>(reader: &mut R) -> io::Result<u64> {
+    let mut header = [0; 10];
+    reader.seek(SeekFrom::Start(0))?;
+
+    if reader.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    // Tag size is stored as a 28 bits "syncsafe" integer
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7F) as u64);
+
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    Ok(10 + size + footer)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_be(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u64_be(bytes: &[u8]) -> u64 {
+    (u32_be(bytes) as u64) << 32 | u32_be(&bytes[4..]) as u64
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    (u32_le(&bytes[4..]) as u64) << 32 | u32_le(bytes) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::{average_bitrate, id3v2_len, AudioMetadata};
+    use soulseek_protocol::peers::p2p::shared_directories::Attribute;
+    use std::io::Cursor;
+
+    #[test]
+    fn should_get_id3v2_len() {
+        let mut tag = b"ID3\x04\x00\x00\x00\x00\x02\x01".to_vec();
+        tag.extend_from_slice(&[0; 257]);
+
+        assert_eq!(id3v2_len(&mut Cursor::new(tag)).unwrap(), 267);
+        assert_eq!(id3v2_len(&mut Cursor::new(b"fLaC")).unwrap(), 0);
+    }
+
+    #[test]
+    fn should_not_overflow_average_bitrate() {
+        assert_eq!(average_bitrate(10_584_000, 2_646_000, 44100), Some(1411));
+        assert_eq!(average_bitrate(u64::MAX, 1, u32::MAX), None);
+        assert_eq!(
+            average_bitrate(u64::MAX, u64::MAX, u32::MAX),
+            Some(34_359_738)
+        );
+        assert_eq!(average_bitrate(1024, 0, 44100), None);
+    }
+
+    #[test]
+    fn should_convert_to_attributes() {
+        let metadata = AudioMetadata {
+            bitrate: Some(1411),
+            duration: Some(60),
+            vbr: None,
+            sample_rate: Some(44100),
+            bit_depth: Some(16),
+        };
+
+        assert_eq!(
+            metadata.into_attributes(),
+            vec![
+                Attribute::bitrate(1411),
+                Attribute::duration(60),
+                Attribute::sample_rate(44100),
+                Attribute::bit_depth(16)
+            ]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::audio::{average_bitrate, id3v2_len, invalid_data, u16_be, u32_be, AudioMetadata};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// How far from the end of the ID3v2 tag we look for the first MPEG frame.
+const SYNC_SEARCH_LEN: u64 = 64 * 1024;
+const ID3V1_LEN: u64 = 128;
+
+#[rustfmt::skip]
+const BITRATES: [[u32; 15]; 5] = [
+    // MPEG 1 layer I, II and III
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    // MPEG 2 and 2.5 layer I, II and III
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug)]
+struct FrameHeader {
+    version: Version,
+    layer: u8,
+    /// Bitrate in kbps
+    bitrate: u32,
+    sample_rate: u32,
+    padding: bool,
+    mono: bool,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0 => Version::Mpeg25,
+            2 => Version::Mpeg2,
+            3 => Version::Mpeg1,
+            _ => return None,
+        };
+
+        let layer = match (bytes[1] >> 1) & 0b11 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+
+        // 0 is "free format" and 15 is invalid, we can't get a bitrate out of those
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 {
+            return None;
+        }
+
+        let sample_rate_index = ((bytes[2] >> 2) & 0b11) as usize;
+        if sample_rate_index == 3 {
+            return None;
+        }
+
+        let bitrate_table = match (version, layer) {
+            (Version::Mpeg1, layer) => layer as usize - 1,
+            (_, 1) => 3,
+            _ => 4,
+        };
+
+        let sample_rate = match version {
+            Version::Mpeg1 => SAMPLE_RATES[sample_rate_index],
+            Version::Mpeg2 => SAMPLE_RATES[sample_rate_index] / 2,
+            Version::Mpeg25 => SAMPLE_RATES[sample_rate_index] / 4,
+        };
+
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate: BITRATES[bitrate_table][bitrate_index],
+            sample_rate,
+            padding: (bytes[2] >> 1) & 1 == 1,
+            mono: bytes[3] >> 6 == 0b11,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (2, _) | (3, Version::Mpeg1) => 1152,
+            _ => 576,
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        let padding = self.padding as u32;
+
+        let len = if self.layer == 1 {
+            (12 * self.bitrate * 1000 / self.sample_rate + padding) * 4
+        } else {
+            self.samples_per_frame() / 8 * self.bitrate * 1000 / self.sample_rate + padding
+        };
+
+        len as usize
+    }
+
+    /// Offset of the Xing/Info header from the start of the frame.
+    fn xing_offset(&self) -> usize {
+        let side_info_len = match (self.version, self.mono) {
+            (Version::Mpeg1, false) => 32,
+            (Version::Mpeg1, true) | (_, false) => 17,
+            (_, true) => 9,
+        };
+
+        4 + side_info_len
+    }
+}
+
+pub(super) fn read<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<AudioMetadata> {
+    let audio_start = id3v2_len(reader)?;
+    reader.seek(SeekFrom::Start(audio_start))?;
+
+    let mut buffer = vec![];
+    reader.take(SYNC_SEARCH_LEN).read_to_end(&mut buffer)?;
+
+    let (offset, header) =
+        find_first_frame(&buffer).ok_or_else(|| invalid_data("No MPEG frame found"))?;
+    let frame = &buffer[offset..];
+
+    if let Some(metadata) = read_xing(frame, &header).or_else(|| read_vbri(frame, &header)) {
+        return Ok(metadata);
+    }
+
+    // No VBR header, this is a constant bitrate file
+    let mut audio_end = file_size;
+    if file_size >= ID3V1_LEN {
+        let mut tag = [0; 3];
+        reader.seek(SeekFrom::Start(file_size - ID3V1_LEN))?;
+        reader.read_exact(&mut tag)?;
+        if &tag == b"TAG" {
+            audio_end -= ID3V1_LEN;
+        }
+    }
+
+    let audio_len = audio_end.saturating_sub(audio_start + offset as u64);
+
+    Ok(AudioMetadata {
+        bitrate: Some(header.bitrate),
+        duration: Some((audio_len * 8 / (header.bitrate as u64 * 1000)) as u32),
+        vbr: Some(false),
+        sample_rate: Some(header.sample_rate),
+        bit_depth: None,
+    })
+}
+
+/// Find the first frame header followed by another valid frame header, to avoid false sync
+/// words in garbage data.
+fn find_first_frame(buffer: &[u8]) -> Option<(usize, FrameHeader)> {
+    (0..buffer.len().saturating_sub(4)).find_map(|offset| {
+        let header = FrameHeader::parse(&buffer[offset..])?;
+        let next = offset + header.frame_len();
+
+        if next + 4 > buffer.len() || FrameHeader::parse(&buffer[next..]).is_some() {
+            Some((offset, header))
+        } else {
+            None
+        }
+    })
+}
+
+/// Read the Xing header written by most VBR encoders, LAME writes an "Info" header instead for
+/// CBR files.
+fn read_xing(frame: &[u8], header: &FrameHeader) -> Option<AudioMetadata> {
+    let xing = frame.get(header.xing_offset()..)?;
+    if xing.len() < 16 {
+        return None;
+    }
+
+    let vbr = match &xing[0..4] {
+        b"Xing" => true,
+        b"Info" => false,
+        _ => return None,
+    };
+
+    let flags = u32_be(&xing[4..]);
+    if flags & 0b1 == 0 {
+        return None;
+    }
+
+    let frames = u32_be(&xing[8..]) as u64;
+    let samples = frames * header.samples_per_frame() as u64;
+
+    let bitrate = if vbr && flags & 0b10 != 0 {
+        let bytes = u32_be(&xing[12..]) as u64;
+        average_bitrate(bytes, samples, header.sample_rate)
+    } else {
+        Some(header.bitrate)
+    };
+
+    Some(AudioMetadata {
+        bitrate,
+        duration: Some((samples / header.sample_rate as u64) as u32),
+        vbr: Some(vbr),
+        sample_rate: Some(header.sample_rate),
+        bit_depth: None,
+    })
+}
+
+/// Read the VBRI header written by the Fraunhofer encoder, always located 32 bytes after the
+/// frame header.
+fn read_vbri(frame: &[u8], header: &FrameHeader) -> Option<AudioMetadata> {
+    let vbri = frame.get(36..54)?;
+    if &vbri[0..4] != b"VBRI" || u16_be(&vbri[4..]) != 1 {
+        return None;
+    }
+
+    let bytes = u32_be(&vbri[10..]) as u64;
+    let frames = u32_be(&vbri[14..]) as u64;
+    let samples = frames * header.samples_per_frame() as u64;
+
+    Some(AudioMetadata {
+        bitrate: average_bitrate(bytes, samples, header.sample_rate),
+        duration: Some((samples / header.sample_rate as u64) as u32),
+        vbr: Some(true),
+        sample_rate: Some(header.sample_rate),
+        bit_depth: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::mp3::read;
+    use crate::audio::AudioMetadata;
+    use std::io::Cursor;
+
+    // MPEG 1 layer III, 128kbps, 44100Hz, joint stereo
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x44];
+    const FRAME_LEN: usize = 417;
+
+    fn frames(count: usize) -> Vec<u8> {
+        let mut frame = HEADER.to_vec();
+        frame.resize(FRAME_LEN, 0);
+        frame.repeat(count)
+    }
+
+    #[test]
+    fn should_read_cbr_file() {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x0A".to_vec();
+        file.extend_from_slice(&[0; 10]);
+        // Garbage sync word before the first frame
+        file.extend_from_slice(&HEADER);
+        file.extend_from_slice(&[0; 10]);
+        file.extend(frames(1000));
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(
+            metadata,
+            AudioMetadata {
+                bitrate: Some(128),
+                duration: Some(26),
+                vbr: Some(false),
+                sample_rate: Some(44100),
+                bit_depth: None,
+            }
+        );
+    }
+
+    #[test]
+    fn should_read_xing_header() {
+        let mut file = frames(2);
+        let xing = &mut file[36..52];
+        xing[0..4].copy_from_slice(b"Xing");
+        xing[4..8].copy_from_slice(&3u32.to_be_bytes());
+        xing[8..12].copy_from_slice(&22969u32.to_be_bytes());
+        xing[12..16].copy_from_slice(&14_400_000u32.to_be_bytes());
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(metadata.bitrate, Some(191));
+        assert_eq!(metadata.duration, Some(600));
+        assert_eq!(metadata.vbr, Some(true));
+    }
+
+    #[test]
+    fn should_read_vbri_header() {
+        let mut file = frames(2);
+        let vbri = &mut file[36..54];
+        vbri[0..4].copy_from_slice(b"VBRI");
+        vbri[4..6].copy_from_slice(&1u16.to_be_bytes());
+        vbri[10..14].copy_from_slice(&4_800_000u32.to_be_bytes());
+        vbri[14..18].copy_from_slice(&11484u32.to_be_bytes());
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(metadata.bitrate, Some(128));
+        assert_eq!(metadata.duration, Some(299));
+        assert_eq!(metadata.vbr, Some(true));
+    }
+
+    #[test]
+    fn should_fail_without_frames() {
+        let file = vec![0; 2048];
+
+        assert!(read(&mut Cursor::new(file), 2048).is_err());
+    }
+}
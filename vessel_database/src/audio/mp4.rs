@@ -0,0 +1,211 @@
+use crate::audio::{average_bitrate, invalid_data, u16_be, u32_be, u64_be, AudioMetadata};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Upper bound for the `moov` atom we are willing to load in memory.
+const MAX_MOOV_LEN: u64 = 16 * 1024 * 1024;
+
+pub(super) fn read<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<AudioMetadata> {
+    let moov = read_moov(reader, file_size)?;
+
+    let track = atoms(&moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, trak)| trak)
+        .find(|trak| {
+            find_atom(trak, &[b"mdia", b"hdlr"])
+                .and_then(|hdlr| hdlr.get(8..12))
+                .map(|handler| handler == b"soun")
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| invalid_data("No audio track found"))?;
+
+    // Audio tracks use the sample rate as media time scale
+    let mdhd =
+        find_atom(track, &[b"mdia", b"mdhd"]).ok_or_else(|| invalid_data("Missing mdhd atom"))?;
+    let (sample_rate, samples) = match mdhd.first() {
+        Some(1) if mdhd.len() >= 32 => (u32_be(&mdhd[20..]), u64_be(&mdhd[24..])),
+        Some(0) if mdhd.len() >= 20 => (u32_be(&mdhd[12..]), u32_be(&mdhd[16..]) as u64),
+        _ => return Err(invalid_data("Invalid mdhd atom")),
+    };
+
+    if sample_rate == 0 {
+        return Err(invalid_data("Invalid mdhd time scale"));
+    }
+
+    // Only lossless codecs have a meaningful sample size
+    let bit_depth = find_atom(track, &[b"mdia", b"minf", b"stbl", b"stsd"])
+        .and_then(|stsd| stsd.get(8..36))
+        .filter(|sample_entry| &sample_entry[4..8] == b"alac")
+        .map(|sample_entry| u16_be(&sample_entry[26..]) as u32);
+
+    Ok(AudioMetadata {
+        bitrate: average_bitrate(file_size, samples, sample_rate),
+        duration: Some((samples / sample_rate as u64) as u32),
+        vbr: None,
+        sample_rate: Some(sample_rate),
+        bit_depth,
+    })
+}
+
+/// Walk the top level atoms until `moov` is found, it might be located after the media data.
+fn read_moov<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<Vec<u8>> {
+    let mut position = 0;
+
+    while file_size.saturating_sub(position) >= 8 {
+        let mut header = [0; 8];
+        reader.seek(SeekFrom::Start(position))?;
+        reader.read_exact(&mut header)?;
+
+        let (atom_len, header_len) = match u32_be(&header) {
+            0 => (file_size - position, 8),
+            1 => {
+                let mut large_size = [0; 8];
+                reader.read_exact(&mut large_size)?;
+                (u64_be(&large_size), 16)
+            }
+            len => (len as u64, 8),
+        };
+
+        if atom_len < header_len {
+            return Err(invalid_data("Invalid atom size"));
+        }
+
+        if &header[4..8] == b"moov" {
+            let moov_len = atom_len - header_len;
+            if moov_len > MAX_MOOV_LEN {
+                return Err(invalid_data("moov atom too large"));
+            }
+
+            let mut moov = vec![0; moov_len as usize];
+            reader.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+
+        position = position
+            .checked_add(atom_len)
+            .ok_or_else(|| invalid_data("Invalid atom size"))?;
+    }
+
+    Err(invalid_data("Missing moov atom"))
+}
+
+/// Iterate over the child atoms contained in `data`, yielding their type and content.
+fn atoms(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut remaining = data;
+
+    std::iter::from_fn(move || {
+        if remaining.len() < 8 {
+            return None;
+        }
+
+        let len = u32_be(remaining) as usize;
+        if len < 8 || len > remaining.len() {
+            return None;
+        }
+
+        let kind = [remaining[4], remaining[5], remaining[6], remaining[7]];
+        let content = &remaining[8..len];
+        remaining = &remaining[len..];
+
+        Some((kind, content))
+    })
+}
+
+fn find_atom<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |parent, kind| {
+        atoms(parent)
+            .find(|(child, _)| &child == kind)
+            .map(|(_, content)| content)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::mp4::read;
+    use crate::audio::AudioMetadata;
+    use std::io::Cursor;
+
+    fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut atom = (content.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(content);
+        atom
+    }
+
+    fn audio_track(format: &[u8; 4]) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 12]);
+
+        let mut mdhd = vec![0; 12];
+        mdhd.extend_from_slice(&44100u32.to_be_bytes());
+        mdhd.extend_from_slice(&(44100u32 * 240).to_be_bytes());
+        mdhd.extend_from_slice(&[0; 4]);
+
+        let mut sample_entry = vec![0; 16];
+        sample_entry.extend_from_slice(&2u16.to_be_bytes());
+        sample_entry.extend_from_slice(&16u16.to_be_bytes());
+        sample_entry.extend_from_slice(&[0; 8]);
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(atom(format, &sample_entry));
+
+        let stbl = atom(b"stbl", &atom(b"stsd", &stsd));
+        let minf = atom(b"minf", &stbl);
+        let mdia = atom(
+            b"mdia",
+            &[atom(b"mdhd", &mdhd), atom(b"hdlr", &hdlr), minf].concat(),
+        );
+
+        atom(b"trak", &mdia)
+    }
+
+    #[test]
+    fn should_read_alac_with_moov_at_the_end() {
+        let mut file = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        file.extend(atom(b"mdat", &vec![0; 1000]));
+        file.extend(atom(b"moov", &audio_track(b"alac")));
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(
+            metadata,
+            AudioMetadata {
+                bitrate: Some(0),
+                duration: Some(240),
+                vbr: None,
+                sample_rate: Some(44100),
+                bit_depth: Some(16),
+            }
+        );
+    }
+
+    #[test]
+    fn should_not_report_aac_bit_depth() {
+        let mut file = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        file.extend(atom(b"moov", &audio_track(b"mp4a")));
+
+        // 4 minutes at 256kbps
+        let metadata = read(&mut Cursor::new(file), 7_680_000).unwrap();
+
+        assert_eq!(metadata.bitrate, Some(256));
+        assert_eq!(metadata.bit_depth, None);
+    }
+
+    #[test]
+    fn should_fail_without_moov() {
+        let file = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+
+        assert!(read(&mut Cursor::new(file), 16).is_err());
+    }
+
+    #[test]
+    fn should_fail_on_overflowing_atom_size() {
+        let mut file = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+
+        assert!(read(&mut Cursor::new(file), u64::MAX).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::audio::{average_bitrate, invalid_data, u16_le, u32_le, u64_le, AudioMetadata};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+const PAGE_HEADER_LEN: usize = 27;
+/// The last page of the stream, holding the final granule position, is looked up in the last
+/// bytes of the file. An Ogg page can't be larger than 65307 bytes.
+const LAST_PAGE_SEARCH_LEN: u64 = 65307;
+/// Opus always decodes at 48kHz, granule positions are expressed at this rate.
+const OPUS_GRANULE_RATE: u32 = 48000;
+
+pub(super) fn read<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<AudioMetadata> {
+    let packet = read_first_packet(reader)?;
+    let last_granule = read_last_granule(reader, file_size)?;
+
+    if packet.len() >= 28 && &packet[0..7] == b"\x01vorbis" {
+        let sample_rate = u32_le(&packet[12..]);
+        let bitrate_max = u32_le(&packet[16..]) as i32;
+        let bitrate_nominal = u32_le(&packet[20..]) as i32;
+        let bitrate_min = u32_le(&packet[24..]) as i32;
+
+        if sample_rate == 0 {
+            return Err(invalid_data("Invalid Vorbis sample rate"));
+        }
+
+        let bitrate = if bitrate_nominal > 0 {
+            Some(bitrate_nominal as u32 / 1000)
+        } else {
+            last_granule.and_then(|samples| average_bitrate(file_size, samples, sample_rate))
+        };
+
+        Ok(AudioMetadata {
+            bitrate,
+            duration: last_granule.map(|samples| (samples / sample_rate as u64) as u32),
+            // Vorbis is only constant bitrate when upper and lower bounds are set to the same value
+            vbr: Some(!(bitrate_max > 0 && bitrate_max == bitrate_min)),
+            sample_rate: Some(sample_rate),
+            bit_depth: None,
+        })
+    } else if packet.len() >= 16 && &packet[0..8] == b"OpusHead" {
+        let pre_skip = u16_le(&packet[10..]) as u64;
+        let input_sample_rate = u32_le(&packet[12..]);
+        let samples = last_granule.map(|granule| granule.saturating_sub(pre_skip));
+
+        Ok(AudioMetadata {
+            bitrate: samples
+                .and_then(|samples| average_bitrate(file_size, samples, OPUS_GRANULE_RATE)),
+            duration: samples.map(|samples| (samples / OPUS_GRANULE_RATE as u64) as u32),
+            vbr: None,
+            sample_rate: Some(if input_sample_rate != 0 {
+                input_sample_rate
+            } else {
+                OPUS_GRANULE_RATE
+            }),
+            bit_depth: None,
+        })
+    } else {
+        Err(invalid_data("Unsupported Ogg codec"))
+    }
+}
+
+/// Read the first packet of the first page, the codec identification header always fits in it.
+fn read_first_packet<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut header = [0; PAGE_HEADER_LEN];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != b"OggS" {
+        return Err(invalid_data("Missing Ogg page header"));
+    }
+
+    let mut segments = vec![0; header[26] as usize];
+    reader.read_exact(&mut segments)?;
+
+    // A packet ends with the first segment shorter than 255 bytes
+    let packet_len = segments
+        .iter()
+        .position(|len| *len < 255)
+        .map(|last| segments[..=last].iter().map(|len| *len as usize).sum())
+        .unwrap_or(0);
+
+    let mut packet = vec![0; packet_len];
+    reader.read_exact(&mut packet)?;
+
+    Ok(packet)
+}
+
+/// Granule position of the last page, this is the total number of samples in the stream.
+fn read_last_granule<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<Option<u64>> {
+    reader.seek(SeekFrom::Start(
+        file_size.saturating_sub(LAST_PAGE_SEARCH_LEN),
+    ))?;
+
+    let mut buffer = vec![];
+    reader.read_to_end(&mut buffer)?;
+
+    let granule = (0..buffer.len().saturating_sub(PAGE_HEADER_LEN))
+        .rev()
+        .filter(|offset| &buffer[*offset..offset + 4] == b"OggS")
+        .map(|offset| u64_le(&buffer[offset + 6..]))
+        // -1 means no packet finishes on this page
+        .find(|granule| *granule != u64::MAX);
+
+    Ok(granule)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::ogg::read;
+    use crate::audio::AudioMetadata;
+    use std::io::Cursor;
+
+    fn page(granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\x00\x02".to_vec();
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    #[test]
+    fn should_read_vorbis_stream() {
+        let mut packet = b"\x01vorbis".to_vec();
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.push(2);
+        packet.extend_from_slice(&44100u32.to_le_bytes());
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&192_000u32.to_le_bytes());
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.push(0xB8);
+        packet.push(1);
+
+        let mut file = page(0, &packet);
+        file.resize(100_000, 0);
+        file.extend(page(13_230_000, &[0; 12]));
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(
+            metadata,
+            AudioMetadata {
+                bitrate: Some(192),
+                duration: Some(300),
+                vbr: Some(true),
+                sample_rate: Some(44100),
+                bit_depth: None,
+            }
+        );
+    }
+
+    #[test]
+    fn should_read_opus_stream() {
+        let mut packet = b"OpusHead\x01\x02".to_vec();
+        packet.extend_from_slice(&312u16.to_le_bytes());
+        packet.extend_from_slice(&44100u32.to_le_bytes());
+        packet.extend_from_slice(&[0; 3]);
+
+        let mut file = page(0, &packet);
+        file.resize(1_200_000, 0);
+        file.extend(page(14_400_312, &[0; 12]));
+        let len = file.len() as u64;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(metadata.duration, Some(300));
+        assert_eq!(metadata.bitrate, Some(32));
+        assert_eq!(metadata.sample_rate, Some(44100));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::audio::{invalid_data, u16_le, u32_le, AudioMetadata};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+pub(super) fn read<R: Read + Seek>(reader: &mut R, file_size: u64) -> io::Result<AudioMetadata> {
+    let mut header = [0; 12];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(invalid_data("Missing RIFF/WAVE header"));
+    }
+
+    let mut format = None;
+    let mut data_len = None;
+    let mut position = 12;
+
+    // Walk the RIFF chunks until both "fmt " and "data" are found
+    while format.is_none() || data_len.is_none() {
+        let mut chunk_header = [0; 8];
+        if reader.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+
+        let chunk_len = u32_le(&chunk_header[4..]) as u64;
+
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                let mut fmt = [0; 16];
+                reader.read_exact(&mut fmt)?;
+                format = Some(fmt);
+            }
+            // Streamed files may not know their length in advance
+            b"data" if chunk_len == u32::MAX as u64 => {
+                data_len = Some(file_size.saturating_sub(position + 8))
+            }
+            b"data" => data_len = Some(chunk_len),
+            _ => {}
+        }
+
+        // Chunks are word aligned
+        position = position
+            .checked_add(8 + chunk_len + (chunk_len & 1))
+            .ok_or_else(|| invalid_data("Invalid RIFF chunk size"))?;
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    let format = format.ok_or_else(|| invalid_data("Missing WAVE fmt chunk"))?;
+    let sample_rate = u32_le(&format[4..]);
+    let byte_rate = u32_le(&format[8..]);
+    let bit_depth = u16_le(&format[14..]) as u32;
+
+    if byte_rate == 0 {
+        return Err(invalid_data("Invalid WAVE byte rate"));
+    }
+
+    Ok(AudioMetadata {
+        bitrate: Some((byte_rate as u64 * 8 / 1000) as u32),
+        duration: data_len.map(|len| (len / byte_rate as u64) as u32),
+        vbr: None,
+        sample_rate: Some(sample_rate),
+        bit_depth: Some(bit_depth),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::wav::read;
+    use crate::audio::AudioMetadata;
+    use std::io::Cursor;
+
+    fn wave_file(byte_rate: u32, data_len: u32) -> Vec<u8> {
+        let mut file = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        file.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+        file.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&2u16.to_le_bytes());
+        file.extend_from_slice(&48000u32.to_le_bytes());
+        file.extend_from_slice(&byte_rate.to_le_bytes());
+        file.extend_from_slice(&6u16.to_le_bytes());
+        file.extend_from_slice(&24u16.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&data_len.to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn should_read_wave_file() {
+        let file = wave_file(288_000, 2_880_000);
+        let len = file.len() as u64 + 2_880_000;
+
+        let metadata = read(&mut Cursor::new(file), len).unwrap();
+
+        assert_eq!(
+            metadata,
+            AudioMetadata {
+                bitrate: Some(2304),
+                duration: Some(10),
+                vbr: None,
+                sample_rate: Some(48000),
+                bit_depth: Some(24),
+            }
+        );
+    }
+
+    #[test]
+    fn should_not_overflow_on_crafted_headers() {
+        // Streamed file shorter than its headers, with an absurd byte rate
+        let file = wave_file(u32::MAX, u32::MAX);
+
+        let metadata = read(&mut Cursor::new(file), 16).unwrap();
+
+        assert_eq!(metadata.bitrate, Some(34_359_738));
+        assert_eq!(metadata.duration, Some(0));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::entity::{now, Entity};
+use soulseek_protocol::server::user::{BuddyEvent, Status, UserAdded, UserStats, UserStatus};
+
+/// A user we keep an eye on, watched with `AddUser` once logged in. The server then sends us
+/// every change of their status and stats, the latest ones are cached here.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct BuddyEntity {
+    pub username: String,
+    #[serde(default)]
+    pub note: String,
+    pub added_at: u64,
+    /// `None` until the server told us about the buddy
+    #[serde(default)]
+    pub status: Option<Status>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub stats: Option<BuddyStats>,
+    #[serde(default)]
+    pub country_code: Option<String>,
+    /// The server does not know this user
+    #[serde(default)]
+    pub not_found: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BuddyStats {
+    pub average_speed: u32,
+    pub download_number: u64,
+    pub files: u32,
+    pub dirs: u32,
+}
+
+impl Entity for BuddyEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.username.as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "buddies";
+}
+
+impl BuddyEntity {
+    pub fn new(username: &str, note: &str) -> Self {
+        BuddyEntity {
+            username: username.to_string(),
+            note: note.to_string(),
+            added_at: now(),
+            status: None,
+            privileged: false,
+            stats: None,
+            country_code: None,
+            not_found: false,
+        }
+    }
+
+    /// Reply to `AddUser`, the buddy is now watched.
+    pub fn on_user_added(&mut self, added: &UserAdded) -> Vec<BuddyEvent> {
+        match added {
+            UserAdded::Ok {
+                status,
+                average_speed,
+                download_number,
+                files,
+                dirs,
+                country_code,
+                ..
+            } => {
+                self.not_found = false;
+                self.country_code = country_code.clone().filter(|code| !code.is_empty());
+
+                let mut events: Vec<BuddyEvent> =
+                    self.set_status(Status::from(*status)).into_iter().collect();
+                events.extend(self.set_stats(BuddyStats {
+                    average_speed: *average_speed,
+                    download_number: *download_number,
+                    files: *files,
+                    dirs: *dirs,
+                }));
+                events
+            }
+            UserAdded::NotFound { .. } => {
+                self.not_found = true;
+                vec![]
+            }
+        }
+    }
+
+    pub fn on_status(&mut self, status: &UserStatus) -> Vec<BuddyEvent> {
+        self.privileged = status.privileged;
+        self.set_status(status.status).into_iter().collect()
+    }
+
+    pub fn on_stats(&mut self, stats: &UserStats) -> Vec<BuddyEvent> {
+        self.set_stats(BuddyStats {
+            average_speed: stats.average_speed,
+            download_number: stats.download_number,
+            files: stats.files,
+            dirs: stats.dirs,
+        })
+    }
+
+    /// Cache the status, returns an event if it changed.
+    fn set_status(&mut self, status: Status) -> Option<BuddyEvent> {
+        let previous = self.status.replace(status);
+        if previous == Some(status) {
+            return None;
+        }
+
+        let username = self.username.clone();
+        match status {
+            Status::Online => Some(BuddyEvent::Online { username }),
+            Status::Away => Some(BuddyEvent::Away { username }),
+            Status::Offline => Some(BuddyEvent::Offline { username }),
+            Status::Unknown => None,
+        }
+    }
+
+    /// Cache the stats, returns an event if the share counts we knew about changed.
+    fn set_stats(&mut self, stats: BuddyStats) -> Vec<BuddyEvent> {
+        let previous = self.stats.replace(stats);
+        let shares_changed = previous
+            .is_some_and(|previous| previous.files != stats.files || previous.dirs != stats.dirs);
+
+        if shares_changed {
+            vec![BuddyEvent::SharesChanged {
+                username: self.username.clone(),
+                files: stats.files,
+                dirs: stats.dirs,
+            }]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use soulseek_protocol::server::user::{BuddyEvent, Status, UserAdded, UserStats, UserStatus};
+
+    use crate::entity::buddy::BuddyEntity;
+
+    fn stats(files: u32) -> UserStats {
+        UserStats {
+            username: "alice".to_string(),
+            average_speed: 100,
+            download_number: 1,
+            files,
+            dirs: 1,
+        }
+    }
+
+    fn status(status: Status) -> UserStatus {
+        UserStatus {
+            username: "alice".to_string(),
+            status,
+            privileged: false,
+        }
+    }
+
+    #[test]
+    fn should_report_status_and_share_changes() {
+        let mut buddy = BuddyEntity::new("alice", "flac collector");
+        let username = || "alice".to_string();
+
+        let added = UserAdded::Ok {
+            username: username(),
+            status: 2,
+            average_speed: 100,
+            download_number: 1,
+            files: 10,
+            dirs: 1,
+            country_code: Some("FR".to_string()),
+        };
+        assert_eq!(
+            buddy.on_user_added(&added),
+            vec![BuddyEvent::Online {
+                username: username()
+            }]
+        );
+        assert_eq!(buddy.country_code.as_deref(), Some("FR"));
+
+        // Same status and shares, nothing to report
+        assert!(buddy.on_status(&status(Status::Online)).is_empty());
+        assert!(buddy.on_stats(&stats(10)).is_empty());
+
+        assert_eq!(
+            buddy.on_status(&status(Status::Away)),
+            vec![BuddyEvent::Away {
+                username: username()
+            }]
+        );
+        assert_eq!(
+            buddy.on_stats(&stats(12)),
+            vec![BuddyEvent::SharesChanged {
+                username: username(),
+                files: 12,
+                dirs: 1
+            }]
+        );
+        assert_eq!(buddy.stats.map(|stats| stats.files), Some(12));
+    }
+}
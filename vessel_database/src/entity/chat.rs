@@ -0,0 +1,333 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::entity::{now, Entity};
+use crate::Database;
+use soulseek_protocol::server::chat::{ChatMessage, PrivateMessage};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Room messages are received twice when public chat is enabled for a room we joined, copies
+/// sent within this many seconds are ignored.
+const DUPLICATE_WINDOW_SECS: u64 = 2;
+
+/// Orders messages stored within the same second.
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// A chat room, or a private conversation with another user.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum Conversation {
+    Room(String),
+    Private(String),
+}
+
+/// A message of a room or private conversation, stored in the order it was received.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ChatMessageEntity {
+    pub conversation: Conversation,
+    pub username: String,
+    pub message: String,
+    /// Unix timestamp, given by the server for private messages
+    pub timestamp: u64,
+    /// Id of a private message, acknowledged once stored
+    pub message_id: Option<u32>,
+    sequence: u32,
+}
+
+impl Entity for ChatMessageEntity {
+    fn get_key(&self) -> Vec<u8> {
+        format!(
+            "{}{:020}-{:010}",
+            self.conversation.key_prefix(),
+            self.timestamp,
+            self.sequence
+        )
+        .into_bytes()
+    }
+
+    const COLLECTION: &'static str = "chat_messages";
+}
+
+impl Conversation {
+    /// Prefix of the message keys, names can't contain a nul byte so a room history never
+    /// includes the messages of another room starting with the same name.
+    fn key_prefix(&self) -> String {
+        match self {
+            Conversation::Room(room) => format!("room:{}\0", room),
+            Conversation::Private(username) => format!("private:{}\0", username),
+        }
+    }
+
+    /// Every message of the conversation, oldest first.
+    pub fn messages(&self, db: &Database) -> Vec<ChatMessageEntity> {
+        db.get_by_prefix(&self.key_prefix())
+    }
+
+    /// The conversation as a plain text log, one `[date] username: message` line per message.
+    pub fn export(&self, db: &Database) -> String {
+        self.messages(db)
+            .iter()
+            .map(|message| {
+                format!(
+                    "[{}] {}: {}\n",
+                    format_utc(message.timestamp),
+                    message.username,
+                    message.message
+                )
+            })
+            .collect()
+    }
+}
+
+impl ChatMessageEntity {
+    pub fn new(conversation: Conversation, username: &str, message: &str, timestamp: u64) -> Self {
+        ChatMessageEntity {
+            conversation,
+            username: username.to_string(),
+            message: message.to_string(),
+            timestamp,
+            message_id: None,
+            sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// A private message we sent to `recipient`, stored with the received ones so the
+    /// conversation shows both sides.
+    pub fn sent_to(recipient: &str, our_username: &str, message: &str) -> Self {
+        ChatMessageEntity::new(
+            Conversation::Private(recipient.to_string()),
+            our_username,
+            message,
+            now(),
+        )
+    }
+
+    /// A message said in a chat room, either joined or received with public chat.
+    pub fn from_room(message: &ChatMessage) -> Self {
+        ChatMessageEntity::new(
+            Conversation::Room(message.room.clone()),
+            &message.username,
+            &message.message,
+            now(),
+        )
+    }
+
+    pub fn from_private(message: &PrivateMessage) -> Self {
+        ChatMessageEntity {
+            message_id: Some(message.id),
+            ..ChatMessageEntity::new(
+                Conversation::Private(message.username.clone()),
+                &message.username,
+                &message.message,
+                u64::from(message.timestamp),
+            )
+        }
+    }
+
+    /// Store the message unless it was already stored, returns false for duplicates.
+    ///
+    /// Private messages are delivered again on login until acknowledged, room messages come
+    /// twice when public chat is enabled.
+    pub fn store(&self, db: &Database) -> sled::Result<bool> {
+        // Redelivered private messages keep their server timestamp, only the latest messages
+        // are read back
+        let window = match self.message_id {
+            Some(_) => 0,
+            None => DUPLICATE_WINDOW_SECS,
+        };
+
+        let duplicate = db
+            .get_by_prefix_rev::<ChatMessageEntity>(&self.conversation.key_prefix(), 0)
+            .take_while(|stored| stored.timestamp + window >= self.timestamp)
+            .any(|stored| self.is_copy_of(&stored));
+
+        if duplicate {
+            Ok(false)
+        } else {
+            db.insert(self).map(|_| true)
+        }
+    }
+
+    fn is_copy_of(&self, other: &ChatMessageEntity) -> bool {
+        match (self.message_id, other.message_id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            (None, None) => self.username == other.username && self.message == other.message,
+            _ => false,
+        }
+    }
+}
+
+/// Paging of a conversation history, the first page holds the latest messages.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+pub struct ChatHistoryQuery {
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl Default for ChatHistoryQuery {
+    fn default() -> Self {
+        ChatHistoryQuery {
+            page: 0,
+            per_page: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatHistoryPage {
+    pub conversation: Conversation,
+    /// Messages in the conversation, across all pages
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    /// Oldest first
+    pub messages: Vec<ChatMessageEntity>,
+}
+
+impl ChatHistoryQuery {
+    pub fn execute(&self, db: &Database, conversation: Conversation) -> ChatHistoryPage {
+        let prefix = conversation.key_prefix();
+        let per_page = self.per_page.clamp(1, MAX_PAGE_SIZE);
+        let total = db.count_by_prefix::<ChatMessageEntity>(&prefix);
+
+        // Pages are read from the latest message, skipped ones are not deserialized
+        let mut messages: Vec<ChatMessageEntity> = db
+            .get_by_prefix_rev(&prefix, self.page.saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+        messages.reverse();
+
+        ChatHistoryPage {
+            conversation,
+            total,
+            page: self.page,
+            per_page,
+            messages,
+        }
+    }
+}
+
+/// `YYYY-MM-DD hh:mm:ss` date of a unix timestamp, in UTC.
+fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::chat::{format_utc, ChatHistoryQuery, ChatMessageEntity, Conversation};
+    use crate::Database;
+
+    fn room(name: &str) -> Conversation {
+        Conversation::Room(name.to_string())
+    }
+
+    #[test]
+    fn should_format_utc_dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_825_600), "2000-02-29 12:00:00");
+        assert_eq!(format_utc(1_623_456_789), "2021-06-12 00:13:09");
+    }
+
+    #[test]
+    fn should_page_history_from_the_latest_messages() {
+        let db = Database::temporary();
+
+        for timestamp in 1..=5 {
+            let message = timestamp.to_string();
+            ChatMessageEntity::new(room("indie"), "alice", &message, timestamp)
+                .store(&db)
+                .unwrap();
+        }
+
+        // Rooms starting with the same name are not part of the history
+        ChatMessageEntity::new(room("indie rock"), "bob", "hi", 1)
+            .store(&db)
+            .unwrap();
+
+        let query = |page| ChatHistoryQuery { page, per_page: 2 };
+        let messages = |page| -> Vec<String> {
+            query(page)
+                .execute(&db, room("indie"))
+                .messages
+                .into_iter()
+                .map(|message| message.message)
+                .collect()
+        };
+
+        assert_eq!(query(0).execute(&db, room("indie")).total, 5);
+        assert_eq!(messages(0), vec!["4", "5"]);
+        assert_eq!(messages(1), vec!["2", "3"]);
+        assert_eq!(messages(2), vec!["1"]);
+        assert!(messages(3).is_empty());
+
+        assert_eq!(
+            room("indie rock").export(&db),
+            "[1970-01-01 00:00:01] bob: hi\n"
+        );
+    }
+
+    #[test]
+    fn should_skip_duplicated_messages() {
+        let db = Database::temporary();
+        let private = || Conversation::Private("alice".to_string());
+
+        let message = ChatMessageEntity {
+            message_id: Some(42),
+            ..ChatMessageEntity::new(private(), "alice", "hello", 10)
+        };
+        assert!(message.store(&db).unwrap());
+
+        let next = ChatMessageEntity {
+            message_id: Some(43),
+            ..ChatMessageEntity::new(private(), "alice", "still there?", 20)
+        };
+        assert!(next.store(&db).unwrap());
+
+        // Delivered again on login
+        let redelivered = ChatMessageEntity {
+            message_id: Some(42),
+            ..ChatMessageEntity::new(private(), "alice", "hello", 10)
+        };
+        assert!(!redelivered.store(&db).unwrap());
+
+        // Received from the room and from public chat
+        let said = ChatMessageEntity::new(room("indie"), "bob", "hi", 100);
+        let public_copy = ChatMessageEntity::new(room("indie"), "bob", "hi", 101);
+        let said_again = ChatMessageEntity::new(room("indie"), "bob", "hi", 200);
+        assert!(said.store(&db).unwrap());
+        assert!(!public_copy.store(&db).unwrap());
+        assert!(said_again.store(&db).unwrap());
+
+        assert_eq!(private().messages(&db).len(), 2);
+        assert_eq!(room("indie").messages(&db).len(), 2);
+    }
+}
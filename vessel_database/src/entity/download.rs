@@ -1,6 +1,9 @@
 use crate::entity::Entity;
 use soulseek_protocol::peers::p2p::transfer::TransferRequest;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+
+/// Separator used in the file names sent by peers.
+const PEER_PATH_SEPARATOR: char = '\\';
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DownloadEntity {
@@ -9,6 +12,9 @@ pub struct DownloadEntity {
     pub ticket: u32,
     pub file_size: u64,
     pub progress: u64,
+    /// Ticket of the [`FolderDownloadEntity`] this download belongs to, if any.
+    #[serde(default)]
+    pub folder_ticket: Option<u32>,
 }
 
 impl DownloadEntity {
@@ -31,9 +37,8 @@ impl From<(String, &TransferRequest)> for DownloadEntity {
         let file_name = request.filename.replace("\\", "/");
         let file_name = Path::new(&file_name)
             .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(file_name);
 
         DownloadEntity {
             file_name,
@@ -43,6 +48,132 @@ impl From<(String, &TransferRequest)> for DownloadEntity {
                 .file_size
                 .expect("Accepted transfer request should have a file size"),
             progress: 0,
+            folder_ticket: None,
         }
     }
 }
+
+/// A whole folder requested from a peer. Files are queued once the peer replied with the folder
+/// contents and are downloaded as a single group.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FolderDownloadEntity {
+    pub user: String,
+    pub ticket: u32,
+    pub folder: String,
+    pub files: Vec<FolderFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct FolderFile {
+    /// Full file name, as sent by the peer
+    pub file_name: String,
+    pub file_size: u64,
+    pub progress: u64,
+    /// Ticket of the transfer, set once the peer starts uploading this file
+    pub ticket: Option<u32>,
+}
+
+impl FolderDownloadEntity {
+    pub fn new(user: &str, ticket: u32, folder: &str) -> Self {
+        FolderDownloadEntity {
+            user: user.to_string(),
+            ticket,
+            folder: folder.to_string(),
+            files: vec![],
+        }
+    }
+
+    pub fn key_from(username: &str, ticket: u32) -> String {
+        format!("{}@{}", username, ticket)
+    }
+
+    pub fn file_by_name(&mut self, file_name: &str) -> Option<&mut FolderFile> {
+        self.files
+            .iter_mut()
+            .find(|file| file.file_name == file_name)
+    }
+
+    pub fn file_by_ticket(&mut self, ticket: u32) -> Option<&mut FolderFile> {
+        self.files
+            .iter_mut()
+            .find(|file| file.ticket == Some(ticket))
+    }
+
+    /// Aggregate progress of the whole folder
+    pub fn percent(&self) -> usize {
+        let total: u64 = self.files.iter().map(|file| file.file_size).sum();
+        let progress: u64 = self.files.iter().map(|file| file.progress).sum();
+
+        (100 * progress).checked_div(total).unwrap_or(0) as usize
+    }
+
+    /// Where a file of this folder is stored, relative to the download folder. The requested
+    /// folder is kept as the top level directory so sub-directories do not collide.
+    pub fn local_path(&self, file_name: &str) -> Option<PathBuf> {
+        let folder = self.folder.trim_end_matches(PEER_PATH_SEPARATOR);
+        let parent_len = folder.rfind(PEER_PATH_SEPARATOR).map_or(0, |idx| idx + 1);
+        let relative = file_name.get(parent_len..)?;
+
+        let path: PathBuf = relative.split(PEER_PATH_SEPARATOR).collect();
+        let is_safe = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+        if is_safe && path.components().count() > 0 {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+impl Entity for FolderDownloadEntity {
+    fn get_key(&self) -> Vec<u8> {
+        let key = FolderDownloadEntity::key_from(&self.user, self.ticket);
+        key.as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "folder_downloads";
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::download::{FolderDownloadEntity, FolderFile};
+    use std::path::PathBuf;
+
+    fn folder_file(file_name: &str, file_size: u64, progress: u64) -> FolderFile {
+        FolderFile {
+            file_name: file_name.to_string(),
+            file_size,
+            progress,
+            ticket: None,
+        }
+    }
+
+    #[test]
+    fn should_compute_aggregate_progress() {
+        let mut folder = FolderDownloadEntity::new("alice", 1, "@@music\\album");
+        assert_eq!(folder.percent(), 0);
+
+        folder.files = vec![
+            folder_file("@@music\\album\\01.flac", 300, 300),
+            folder_file("@@music\\album\\02.flac", 100, 0),
+        ];
+
+        assert_eq!(folder.percent(), 75);
+    }
+
+    #[test]
+    fn should_keep_folder_structure() {
+        let folder = FolderDownloadEntity::new("alice", 1, "@@music\\artist\\album\\");
+
+        assert_eq!(
+            folder.local_path("@@music\\artist\\album\\cd1\\01.flac"),
+            Some(PathBuf::from("album/cd1/01.flac"))
+        );
+        assert_eq!(
+            folder.local_path("@@music\\artist\\album\\..\\..\\passwd"),
+            None
+        );
+    }
+}
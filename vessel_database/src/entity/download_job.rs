@@ -0,0 +1,338 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use crate::entity::search::{search_results, SearchEntity, SearchResultEntity};
+use crate::entity::{now, Entity};
+use crate::Database;
+
+/// Separator used in the file names sent by peers.
+const PEER_PATH_SEPARATOR: char = '\\';
+
+/// A file downloaded from whichever peer of a search session is the most likely to send it. The
+/// best source is queued first, the next one is tried when it refuses, fails or times out.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DownloadJobEntity {
+    pub id: u32,
+    /// Ticket of the search the sources were picked from
+    pub search_ticket: u32,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Candidate sources, best first
+    pub sources: Vec<DownloadSource>,
+    /// Index of the source currently queued or uploading
+    pub current: Option<usize>,
+    /// Unix timestamp of the last time we queued the file
+    pub queued_at: Option<u64>,
+    pub state: DownloadJobState,
+}
+
+/// A peer sharing the file of a download job.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DownloadSource {
+    pub username: String,
+    /// Full file name, as shared by the peer
+    pub file_name: String,
+    pub slot_free: bool,
+    pub average_speed: u32,
+    pub queue_length: u32,
+    /// Why this source was given up, if it was
+    pub failure: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadJobState {
+    /// Waiting for the next source to be queued
+    Pending,
+    /// Queued, waiting for the peer to start uploading
+    Queued,
+    /// The current source accepted to upload the file
+    Transferring,
+    /// Every source failed
+    Failed,
+}
+
+impl DownloadJobEntity {
+    /// A job downloading `file_name` shared by `username`, from any peer of the search sharing a
+    /// file with the same name and size. `None` if the search or the file is unknown.
+    pub fn from_search(
+        db: &Database,
+        id: u32,
+        search_ticket: u32,
+        username: &str,
+        file_name: &str,
+    ) -> Option<Self> {
+        db.get_by_key::<SearchEntity>(&search_ticket.to_string())?;
+        DownloadJobEntity::from_results(
+            id,
+            search_ticket,
+            username,
+            file_name,
+            search_results(db, search_ticket),
+        )
+    }
+
+    fn from_results(
+        id: u32,
+        search_ticket: u32,
+        username: &str,
+        file_name: &str,
+        results: Vec<SearchResultEntity>,
+    ) -> Option<Self> {
+        let file_size = results
+            .iter()
+            .filter(|result| result.username == username)
+            .flat_map(|result| result.files.iter())
+            .find(|file| file.name == file_name)?
+            .size;
+
+        let mut sources: Vec<DownloadSource> = results
+            .iter()
+            .flat_map(|result| {
+                result
+                    .files
+                    .iter()
+                    .filter(|file| file.size == file_size && same_base_name(&file.name, file_name))
+                    .map(move |file| DownloadSource {
+                        username: result.username.clone(),
+                        file_name: file.name.clone(),
+                        slot_free: result.slot_free,
+                        average_speed: result.average_speed,
+                        queue_length: result.queue_length,
+                        failure: None,
+                    })
+            })
+            .collect();
+
+        sources.sort_by_key(|source| {
+            (
+                !source.slot_free,
+                source.queue_length,
+                Reverse(source.average_speed),
+            )
+        });
+
+        Some(DownloadJobEntity {
+            id,
+            search_ticket,
+            file_name: file_name.to_string(),
+            file_size,
+            sources,
+            current: None,
+            queued_at: None,
+            state: DownloadJobState::Pending,
+        })
+    }
+
+    pub fn current_source(&self) -> Option<&DownloadSource> {
+        self.current.and_then(|current| self.sources.get(current))
+    }
+
+    /// `username` sharing `file_name` is the source we are waiting for or downloading from.
+    pub fn is_current_source(&self, username: &str, file_name: &str) -> bool {
+        let active = matches!(
+            self.state,
+            DownloadJobState::Queued | DownloadJobState::Transferring
+        );
+
+        active
+            && self
+                .current_source()
+                .is_some_and(|source| source.username == username && source.file_name == file_name)
+    }
+
+    /// We gave up waiting for `username` to send `file_name` and did not queue it again since,
+    /// an upload it starts late must be refused.
+    pub fn is_given_up(db: &Database, username: &str, file_name: &str) -> bool {
+        let jobs = db.get_all::<DownloadJobEntity>();
+        let given_up = jobs.iter().any(|job| {
+            job.sources.iter().any(|source| {
+                source.failure.is_some()
+                    && source.username == username
+                    && source.file_name == file_name
+            })
+        });
+
+        given_up
+            && !jobs
+                .iter()
+                .any(|job| job.is_current_source(username, file_name))
+    }
+
+    /// The current source was queued more than `timeout` ago and did not start uploading.
+    pub fn is_timed_out(&self, timeout: Duration) -> bool {
+        let queued_before = now().saturating_sub(timeout.as_secs());
+
+        self.state == DownloadJobState::Queued
+            && self
+                .queued_at
+                .is_some_and(|queued_at| queued_at <= queued_before)
+    }
+
+    pub fn transferring(&mut self) {
+        self.state = DownloadJobState::Transferring;
+    }
+
+    /// Give up the current source, the next one is queued on the next check.
+    pub fn fail_current(&mut self, reason: &str) {
+        if let Some(current) = self
+            .current
+            .and_then(|current| self.sources.get_mut(current))
+        {
+            current.failure = Some(reason.to_string());
+        }
+
+        self.state = DownloadJobState::Pending;
+    }
+
+    /// Move to the next source not tried yet, the job fails once they are all exhausted.
+    pub fn queue_next(&mut self) -> Option<&DownloadSource> {
+        let next = self.current.map_or(0, |current| current + 1);
+
+        if next >= self.sources.len() {
+            self.state = DownloadJobState::Failed;
+            return None;
+        }
+
+        self.current = Some(next);
+        self.queued_at = Some(now());
+        self.state = DownloadJobState::Queued;
+        self.sources.get(next)
+    }
+}
+
+impl Entity for DownloadJobEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.id.to_string().as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "download_jobs";
+}
+
+fn same_base_name(file_name: &str, other: &str) -> bool {
+    base_name(file_name).eq_ignore_ascii_case(base_name(other))
+}
+
+fn base_name(file_name: &str) -> &str {
+    file_name
+        .rsplit(PEER_PATH_SEPARATOR)
+        .next()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use soulseek_protocol::peers::p2p::shared_directories::File;
+
+    use crate::entity::download_job::{DownloadJobEntity, DownloadJobState};
+    use crate::entity::search::SearchResultEntity;
+    use crate::Database;
+
+    fn result(
+        username: &str,
+        slot_free: bool,
+        queue_length: u32,
+        files: &[(&str, u64)],
+    ) -> SearchResultEntity {
+        SearchResultEntity {
+            ticket: 1,
+            username: username.to_string(),
+            slot_free,
+            average_speed: 1000,
+            queue_length,
+            files: files
+                .iter()
+                .map(|(name, size)| File {
+                    name: name.to_string(),
+                    size: *size,
+                    extension: "flac".to_string(),
+                    attributes: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    fn job() -> DownloadJobEntity {
+        let results = vec![
+            result("busy", false, 0, &[("Music\\Nirvana\\Lithium.flac", 1024)]),
+            result("queued", true, 12, &[("nirvana\\lithium.FLAC", 1024)]),
+            result(
+                "free",
+                true,
+                0,
+                &[
+                    ("Nirvana\\Lithium.flac", 1024),
+                    ("Nirvana\\Polly.flac", 1024),
+                ],
+            ),
+            result("other", true, 0, &[("Nirvana\\Lithium.flac", 2048)]),
+        ];
+
+        DownloadJobEntity::from_results(42, 1, "busy", "Music\\Nirvana\\Lithium.flac", results)
+            .unwrap()
+    }
+
+    #[test]
+    fn should_rank_sources() {
+        let job = job();
+        let sources: Vec<&str> = job
+            .sources
+            .iter()
+            .map(|source| source.username.as_str())
+            .collect();
+
+        assert_eq!(job.file_size, 1024);
+        assert_eq!(sources, vec!["free", "queued", "busy"]);
+        assert_eq!(job.state, DownloadJobState::Pending);
+        assert!(DownloadJobEntity::from_results(1, 1, "busy", "unknown.flac", vec![]).is_none());
+    }
+
+    #[test]
+    fn should_fall_over_to_next_source() {
+        let mut job = job();
+
+        assert_eq!(job.queue_next().unwrap().username, "free");
+        assert!(job.is_current_source("free", "Nirvana\\Lithium.flac"));
+        assert!(job.is_timed_out(Duration::from_secs(0)));
+        assert!(!job.is_timed_out(Duration::from_secs(60)));
+
+        job.fail_current("File not shared.");
+        assert_eq!(job.state, DownloadJobState::Pending);
+        assert!(!job.is_current_source("free", "Nirvana\\Lithium.flac"));
+        assert_eq!(job.sources[0].failure.as_deref(), Some("File not shared."));
+
+        assert_eq!(job.queue_next().unwrap().username, "queued");
+        job.transferring();
+        assert!(!job.is_timed_out(Duration::from_secs(0)));
+
+        job.fail_current("Upload failed");
+        assert_eq!(job.queue_next().unwrap().username, "busy");
+        job.fail_current("Timed out");
+        assert!(job.queue_next().is_none());
+        assert_eq!(job.state, DownloadJobState::Failed);
+    }
+
+    #[test]
+    fn should_remember_given_up_sources() {
+        let db = Database::temporary();
+        let mut timed_out = job();
+        let lithium = "Nirvana\\Lithium.flac";
+
+        timed_out.queue_next();
+        db.insert(&timed_out).unwrap();
+        assert!(!DownloadJobEntity::is_given_up(&db, "free", lithium));
+
+        timed_out.fail_current("Timed out");
+        db.insert(&timed_out).unwrap();
+        assert!(DownloadJobEntity::is_given_up(&db, "free", lithium));
+        assert!(!DownloadJobEntity::is_given_up(&db, "queued", lithium));
+
+        // Queued again by another job
+        let mut other = DownloadJobEntity { id: 43, ..job() };
+        other.queue_next();
+        db.insert(&other).unwrap();
+        assert!(!DownloadJobEntity::is_given_up(&db, "free", lithium));
+    }
+}
@@ -0,0 +1,426 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use crate::entity::download_job::DownloadJobEntity;
+use crate::entity::search::{search_results, SearchResultEntity};
+use crate::entity::{now, Entity};
+use crate::playlist::{PlaylistFormat, Track};
+use crate::Database;
+
+/// Separator used in the file names sent by peers.
+const PEER_PATH_SEPARATOR: char = '\\';
+
+/// Matches scoring at least this much have the whole title and artist in their file name, they
+/// can be queued without review.
+pub const MIN_QUEUE_SCORE: u32 = 85;
+
+const TITLE_WEIGHT: u32 = 60;
+const ARTIST_WEIGHT: u32 = 25;
+const ALBUM_WEIGHT: u32 = 15;
+
+/// A playlist imported as a batch of searches, its entries are stored separately so their status
+/// can be updated one at a time.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImportEntity {
+    pub id: u32,
+    pub format: PlaylistFormat,
+    /// Queue the best match of each entry without review
+    pub auto_queue: bool,
+    /// Unix timestamp of the import
+    pub created_at: u64,
+    pub total: usize,
+}
+
+/// A track of an import and where we are at finding it.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImportEntryEntity {
+    pub import_id: u32,
+    pub index: usize,
+    #[serde(flatten)]
+    pub track: Track,
+    pub status: ImportStatus,
+    /// Ticket of the search for this entry
+    pub ticket: Option<u32>,
+    /// Unix timestamp of the search for this entry
+    pub searched_at: Option<u64>,
+    pub best_match: Option<ImportMatch>,
+    /// Download job queuing the best match, see [`crate::entity::download_job`]
+    pub download_job: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// Waiting for its turn to be searched
+    Pending,
+    /// Searched, waiting for the replies
+    Searching,
+    /// A match was found and is waiting for the user to queue it
+    Review,
+    /// The best match was queued for download
+    Queued,
+    NotFound,
+}
+
+/// The file we would rather download for an import entry.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImportMatch {
+    pub username: String,
+    pub file_name: String,
+    pub size: u64,
+    /// How well the file name matches the entry, out of 100
+    pub score: u32,
+}
+
+impl ImportEntity {
+    pub fn new(id: u32, format: PlaylistFormat, auto_queue: bool, total: usize) -> Self {
+        ImportEntity {
+            id,
+            format,
+            auto_queue,
+            created_at: now(),
+            total,
+        }
+    }
+
+    pub fn entries(&self, db: &Database) -> Vec<ImportEntryEntity> {
+        db.get_by_prefix(&ImportEntryEntity::key_prefix(self.id))
+    }
+
+    /// Remove the import and all its entries, downloads already queued are kept.
+    pub fn remove(&self, db: &Database) -> sled::Result<()> {
+        for entry in self.entries(db) {
+            db.remove(&entry)?;
+        }
+
+        db.remove(self)
+    }
+}
+
+impl Entity for ImportEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.id.to_string().as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "imports";
+}
+
+impl ImportEntryEntity {
+    pub fn new(import_id: u32, index: usize, track: Track) -> Self {
+        ImportEntryEntity {
+            import_id,
+            index,
+            track,
+            status: ImportStatus::Pending,
+            ticket: None,
+            searched_at: None,
+            best_match: None,
+            download_job: None,
+        }
+    }
+
+    fn key_prefix(import_id: u32) -> String {
+        format!("{}@", import_id)
+    }
+
+    /// Entry key, the index is padded so entries are listed in playlist order.
+    pub fn key_from(import_id: u32, index: usize) -> String {
+        format!("{}{:06}", ImportEntryEntity::key_prefix(import_id), index)
+    }
+
+    /// Search query for this entry, symbols are dropped so they are not taken as search operators.
+    pub fn query(&self) -> String {
+        let artist = self.track.artist.as_deref().unwrap_or_default();
+
+        words(artist)
+            .into_iter()
+            .chain(words(&self.track.title))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn searched(&mut self, ticket: u32) {
+        self.ticket = Some(ticket);
+        self.searched_at = Some(now());
+        self.status = ImportStatus::Searching;
+    }
+
+    /// The entry was searched more than `delay` ago and its replies can be scored.
+    pub fn awaits_scoring(&self, delay: Duration) -> bool {
+        let searched_before = now().saturating_sub(delay.as_secs());
+
+        self.status == ImportStatus::Searching
+            && self
+                .searched_at
+                .is_some_and(|searched_at| searched_at <= searched_before)
+    }
+
+    /// Pick the best of the files found by the search of this entry and move it to review, or
+    /// to not found if no file is worth downloading.
+    pub fn score_results(&mut self, db: &Database) {
+        let results = self
+            .ticket
+            .map(|ticket| search_results(db, ticket))
+            .unwrap_or_default();
+
+        self.best_match = self.best_of(&results);
+        self.status = if self.best_match.is_some() {
+            ImportStatus::Review
+        } else {
+            ImportStatus::NotFound
+        };
+    }
+
+    fn best_of(&self, results: &[SearchResultEntity]) -> Option<ImportMatch> {
+        results
+            .iter()
+            .flat_map(|result| {
+                result
+                    .files
+                    .iter()
+                    .map(move |file| (result, file, self.score(&file.name)))
+            })
+            .filter(|(_, _, score)| *score > 0)
+            .min_by_key(|(result, _, score)| {
+                (
+                    Reverse(*score),
+                    !result.slot_free,
+                    result.queue_length,
+                    Reverse(result.average_speed),
+                )
+            })
+            .map(|(result, file, score)| ImportMatch {
+                username: result.username.clone(),
+                file_name: file.name.clone(),
+                size: file.size,
+                score,
+            })
+    }
+
+    /// How well a shared file matches the entry, out of 100. Title words are looked up in the
+    /// file name only, artist and album words anywhere in its path. Missing artist or album
+    /// count as matched.
+    pub fn score(&self, file_name: &str) -> u32 {
+        let path = words(file_name);
+        let base_name = words(
+            file_name
+                .rsplit(PEER_PATH_SEPARATOR)
+                .next()
+                .unwrap_or_default(),
+        );
+
+        let title = matched(&words(&self.track.title), &base_name);
+        let artist = self
+            .track
+            .artist
+            .as_deref()
+            .map_or(1.0, |artist| matched(&words(artist), &path));
+        let album = self
+            .track
+            .album
+            .as_deref()
+            .map_or(1.0, |album| matched(&words(album), &path));
+
+        if title == 0.0 {
+            return 0;
+        }
+
+        let score = title * TITLE_WEIGHT as f32
+            + artist * ARTIST_WEIGHT as f32
+            + album * ALBUM_WEIGHT as f32;
+
+        score.round() as u32
+    }
+
+    /// Download the best match from whichever peer of the search is the most likely to send it.
+    /// Returns `false` if there is no match or the search expired.
+    pub fn queue_best_match(&mut self, db: &Database, job_id: u32) -> sled::Result<bool> {
+        let (ticket, best_match) = match (self.ticket, &self.best_match) {
+            (Some(ticket), Some(best_match)) => (ticket, best_match),
+            _ => return Ok(false),
+        };
+
+        let job = DownloadJobEntity::from_search(
+            db,
+            job_id,
+            ticket,
+            &best_match.username,
+            &best_match.file_name,
+        );
+
+        match job {
+            Some(job) => {
+                db.insert(&job)?;
+                self.queued(Some(job.id));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn queued(&mut self, download_job: Option<u32>) {
+        self.download_job = download_job;
+        self.status = ImportStatus::Queued;
+    }
+}
+
+impl Entity for ImportEntryEntity {
+    fn get_key(&self) -> Vec<u8> {
+        ImportEntryEntity::key_from(self.import_id, self.index)
+            .as_bytes()
+            .to_vec()
+    }
+
+    const COLLECTION: &'static str = "import_entries";
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Pending => "pending",
+            ImportStatus::Searching => "searching",
+            ImportStatus::Review => "review",
+            ImportStatus::Queued => "queued",
+            ImportStatus::NotFound => "not_found",
+        }
+    }
+}
+
+/// Store a new import and its entries.
+pub fn store_import(db: &Database, import: &ImportEntity, tracks: Vec<Track>) -> sled::Result<()> {
+    for (index, track) in tracks.into_iter().enumerate() {
+        db.insert(&ImportEntryEntity::new(import.id, index, track))?;
+    }
+
+    db.insert(import)
+}
+
+/// Lowercase alphanumeric words of a text.
+fn words(text: &str) -> Vec<String> {
+    text.split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Share of `expected` words found in `words`.
+fn matched(expected: &[String], words: &[String]) -> f32 {
+    if expected.is_empty() {
+        return 1.0;
+    }
+
+    let found = expected.iter().filter(|word| words.contains(word)).count();
+    found as f32 / expected.len() as f32
+}
+
+#[cfg(test)]
+mod test {
+    use soulseek_protocol::peers::p2p::{search::SearchReply, shared_directories::File};
+
+    use crate::entity::download_job::DownloadJobEntity;
+    use crate::entity::import::{
+        store_import, ImportEntity, ImportEntryEntity, ImportStatus, MIN_QUEUE_SCORE,
+    };
+    use crate::entity::search::{SearchEntity, SearchResultEntity};
+    use crate::playlist::{PlaylistFormat, Track};
+    use crate::Database;
+
+    fn entry(artist: Option<&str>, title: &str, album: Option<&str>) -> ImportEntryEntity {
+        let track = Track {
+            artist: artist.map(str::to_string),
+            title: title.to_string(),
+            album: album.map(str::to_string),
+        };
+
+        ImportEntryEntity::new(1, 0, track)
+    }
+
+    fn reply(username: &str, slot_free: bool, files: &[&str]) -> SearchReply {
+        SearchReply {
+            username: username.to_string(),
+            ticket: 7,
+            files: files
+                .iter()
+                .map(|name| File {
+                    name: name.to_string(),
+                    size: 1024,
+                    extension: "flac".to_string(),
+                    attributes: vec![],
+                })
+                .collect(),
+            slot_free,
+            average_speed: 1000,
+            queue_length: 0,
+            locked_results: vec![],
+        }
+    }
+
+    #[test]
+    fn should_score_file_names() {
+        let entry = entry(Some("Nirvana"), "Lithium", Some("Nevermind"));
+
+        assert_eq!(entry.query(), "nirvana lithium");
+        assert_eq!(
+            entry.score("@@music\\Nirvana\\Nevermind\\05 - Lithium.flac"),
+            100
+        );
+        assert_eq!(entry.score("@@music\\Nirvana\\Live\\Lithium.mp3"), 85);
+        assert_eq!(entry.score("@@music\\Lithium\\Nirvana - Polly.mp3"), 0);
+        assert!(entry.score("@@music\\Other\\Lithium.mp3") < MIN_QUEUE_SCORE);
+    }
+
+    #[test]
+    fn should_pick_best_match() {
+        let db = Database::temporary();
+        let mut entry = entry(Some("Nirvana"), "Lithium", Some("Nevermind"));
+        entry.searched(7);
+
+        let replies = vec![
+            reply("live", true, &["Nirvana\\Live\\Lithium.flac"]),
+            reply("busy", false, &["Nirvana\\Nevermind\\Lithium.flac"]),
+            reply("free", true, &["Nirvana\\Nevermind\\05 Lithium.flac"]),
+        ];
+        for reply in &replies {
+            db.insert(&SearchResultEntity::from(reply)).unwrap();
+        }
+
+        entry.score_results(&db);
+        assert_eq!(entry.status, ImportStatus::Review);
+        let best = entry.best_match.as_ref().unwrap();
+        assert_eq!(best.username, "free");
+        assert_eq!(best.score, 100);
+
+        assert!(!entry.queue_best_match(&db, 3).unwrap());
+        db.insert(&SearchEntity::new(7, "nirvana lithium")).unwrap();
+        assert!(entry.queue_best_match(&db, 3).unwrap());
+        assert_eq!(entry.status, ImportStatus::Queued);
+        assert_eq!(entry.download_job, Some(3));
+        assert!(db.get_by_key::<DownloadJobEntity>("3").is_some());
+
+        entry.ticket = Some(8);
+        entry.score_results(&db);
+        assert_eq!(entry.status, ImportStatus::NotFound);
+    }
+
+    #[test]
+    fn should_store_import_entries_in_order() {
+        let db = Database::temporary();
+        let tracks = PlaylistFormat::Text.parse(&"Nirvana - Lithium\n".repeat(12));
+        let import = ImportEntity::new(1, PlaylistFormat::Text, false, tracks.len());
+        store_import(&db, &import, tracks).unwrap();
+        let other = ImportEntity::new(12, PlaylistFormat::Text, false, 1);
+        store_import(&db, &other, PlaylistFormat::Text.parse("Polly")).unwrap();
+
+        let indexes: Vec<usize> = import
+            .entries(&db)
+            .iter()
+            .map(|entry| entry.index)
+            .collect();
+        assert_eq!(indexes, (0..12).collect::<Vec<usize>>());
+
+        import.remove(&db).unwrap();
+        assert!(import.entries(&db).is_empty());
+        assert!(db.get_by_key::<ImportEntity>("1").is_none());
+        assert_eq!(other.entries(&db).len(), 1);
+    }
+}
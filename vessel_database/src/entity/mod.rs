@@ -1,12 +1,29 @@
 use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod buddy;
+pub mod chat;
 pub mod download;
+pub mod download_job;
+pub mod import;
 pub mod peer;
+pub mod privileges;
+pub mod search;
 pub mod shared_dirs;
 pub mod upload;
+pub mod user_info;
+pub mod wishlist;
 
 /// A generic insertable entity
 pub trait Entity {
     fn get_key(&self) -> Vec<u8>;
     const COLLECTION: &'static str;
 }
+
+/// Current unix timestamp in seconds.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -1,12 +1,17 @@
 use crate::entity::Entity;
-use crate::entity::IpAddr;
+use crate::entity::{now, IpAddr};
 use soulseek_protocol::server::peer::{Peer, PeerAddress, PeerConnectionRequest};
 use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PeerEntity {
     pub username: String,
     pub ip: Ipv4Addr,
     pub(crate) port: u32,
+    /// Seconds since the unix epoch when this address was received, addresses stored before
+    /// this field existed are considered stale.
+    #[serde(default)]
+    pub(crate) last_seen: u64,
 }
 
 impl Entity for PeerEntity {
@@ -23,37 +28,26 @@ impl PeerEntity {
             username: username.to_string(),
             ip,
             port,
+            last_seen: now(),
         }
     }
 }
 
 impl From<PeerConnectionRequest> for PeerEntity {
     fn from(request: PeerConnectionRequest) -> Self {
-        PeerEntity {
-            username: request.username,
-            ip: request.ip,
-            port: request.port,
-        }
+        PeerEntity::new(&request.username, request.ip, request.port)
     }
 }
 
 impl From<PeerAddress> for PeerEntity {
     fn from(peer: PeerAddress) -> Self {
-        PeerEntity {
-            username: peer.username,
-            ip: peer.ip,
-            port: peer.port,
-        }
+        PeerEntity::new(&peer.username, peer.ip, peer.port)
     }
 }
 
 impl From<Peer> for PeerEntity {
     fn from(peer: Peer) -> Self {
-        PeerEntity {
-            username: peer.username,
-            ip: peer.ip,
-            port: peer.port,
-        }
+        PeerEntity::new(&peer.username, peer.ip, peer.port)
     }
 }
 
@@ -61,4 +55,41 @@ impl PeerEntity {
     pub fn get_address(&self) -> SocketAddr {
         SocketAddr::new(IpAddr::from(self.ip), self.port as u16)
     }
+
+    /// The server answers address requests for offline users with `0.0.0.0:0`.
+    pub fn is_offline(&self) -> bool {
+        self.ip.is_unspecified() || self.port == 0
+    }
+
+    /// True when the address was received more than `ttl` ago and should be requested again.
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.last_seen) >= ttl.as_secs()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use crate::entity::peer::PeerEntity;
+
+    #[test]
+    fn should_detect_offline_peer() {
+        assert!(PeerEntity::new("alice", Ipv4Addr::UNSPECIFIED, 0).is_offline());
+        assert!(PeerEntity::new("alice", Ipv4Addr::new(78, 28, 37, 115), 0).is_offline());
+        assert!(!PeerEntity::new("alice", Ipv4Addr::new(78, 28, 37, 115), 2234).is_offline());
+    }
+
+    #[test]
+    fn should_expire_old_address() {
+        let peer = PeerEntity::new("alice", Ipv4Addr::new(78, 28, 37, 115), 2234);
+        assert!(!peer.is_expired(Duration::from_secs(60)));
+        assert!(peer.is_expired(Duration::from_secs(0)));
+
+        let legacy: PeerEntity =
+            serde_json::from_str(r#"{"username":"alice","ip":"78.28.37.115","port":2234}"#)
+                .unwrap();
+        assert!(legacy.is_expired(Duration::from_secs(60)));
+    }
 }
@@ -0,0 +1,119 @@
+use crate::entity::{now, Entity};
+use crate::Database;
+use std::convert::TryFrom;
+
+/// A user of the privileged users list sent by the server at login and kept up to date as users
+/// are added, privileged users get a higher priority in our upload queue.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct PrivilegedUserEntity {
+    pub username: String,
+}
+
+impl Entity for PrivilegedUserEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.username.as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "privileged_users";
+}
+
+impl PrivilegedUserEntity {
+    /// Replace the whole list, the server sends it after each login.
+    pub fn replace_all(db: &Database, users: Vec<String>) -> sled::Result<()> {
+        db.clear::<PrivilegedUserEntity>()?;
+        users
+            .into_iter()
+            .try_for_each(|username| PrivilegedUserEntity::add(db, username))
+    }
+
+    pub fn add(db: &Database, username: String) -> sled::Result<()> {
+        db.insert(&PrivilegedUserEntity { username })
+    }
+
+    pub fn is_privileged(db: &Database, username: &str) -> bool {
+        db.get_by_key::<PrivilegedUserEntity>(username).is_some()
+    }
+}
+
+/// Our own privileges, as last reported by the server.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct PrivilegesEntity {
+    /// Seconds of privileges left when the server answered
+    pub time_left: u32,
+    /// Unix timestamp of the server answer
+    pub checked_at: u64,
+}
+
+impl PrivilegesEntity {
+    const KEY: &'static str = "me";
+
+    pub fn new(time_left: u32) -> Self {
+        PrivilegesEntity {
+            time_left,
+            checked_at: now(),
+        }
+    }
+
+    pub fn get(db: &Database) -> Option<Self> {
+        db.get_by_key(PrivilegesEntity::KEY)
+    }
+
+    /// Seconds of privileges left now, assuming nothing changed since the last check.
+    pub fn remaining(&self) -> u32 {
+        let elapsed = now().saturating_sub(self.checked_at);
+        let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+        self.time_left.saturating_sub(elapsed)
+    }
+}
+
+impl Entity for PrivilegesEntity {
+    fn get_key(&self) -> Vec<u8> {
+        PrivilegesEntity::KEY.as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "privileges";
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::now;
+    use crate::entity::privileges::{PrivilegedUserEntity, PrivilegesEntity};
+    use crate::Database;
+
+    #[test]
+    fn should_keep_privileged_users_current() {
+        let db = Database::temporary();
+        let users = vec!["alice".to_string(), "bob".to_string()];
+        PrivilegedUserEntity::replace_all(&db, users).unwrap();
+        PrivilegedUserEntity::add(&db, "carol".to_string()).unwrap();
+
+        assert!(PrivilegedUserEntity::is_privileged(&db, "alice"));
+        assert!(PrivilegedUserEntity::is_privileged(&db, "carol"));
+        assert!(!PrivilegedUserEntity::is_privileged(&db, "dave"));
+
+        PrivilegedUserEntity::replace_all(&db, vec!["dave".to_string()]).unwrap();
+        assert!(!PrivilegedUserEntity::is_privileged(&db, "alice"));
+        assert_eq!(db.get_all::<PrivilegedUserEntity>().len(), 1);
+    }
+
+    #[test]
+    fn should_count_down_remaining_privileges() {
+        let db = Database::temporary();
+        assert_eq!(PrivilegesEntity::get(&db), None);
+
+        let privileges = PrivilegesEntity {
+            time_left: 3600,
+            checked_at: now() - 600,
+        };
+        db.insert(&privileges).unwrap();
+
+        let remaining = PrivilegesEntity::get(&db).unwrap().remaining();
+        assert!((2990..=3000).contains(&remaining));
+
+        let expired = PrivilegesEntity {
+            time_left: 60,
+            checked_at: now() - 600,
+        };
+        assert_eq!(expired.remaining(), 0);
+    }
+}
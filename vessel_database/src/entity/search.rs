@@ -0,0 +1,417 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use soulseek_protocol::peers::p2p::{
+    search::SearchReply,
+    shared_directories::{Attribute, File},
+};
+
+use crate::entity::{now, Entity};
+use crate::Database;
+
+/// Results returned per page when not specified.
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// A search we sent to the Soulseek server, used to filter out peer replies not matching the
+/// original query.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchEntity {
+    pub ticket: u32,
+    pub query: String,
+    /// Set when the search was sent for a wishlist entry
+    #[serde(default)]
+    pub wishlist_id: Option<u32>,
+    /// Set when the search was sent for an entry of a playlist import
+    #[serde(default)]
+    pub import_id: Option<u32>,
+    /// Unix timestamp of the search
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+impl SearchEntity {
+    pub fn new(ticket: u32, query: &str) -> Self {
+        SearchEntity {
+            ticket,
+            query: query.to_string(),
+            wishlist_id: None,
+            import_id: None,
+            created_at: now(),
+        }
+    }
+
+    pub fn for_wishlist(ticket: u32, query: &str, wishlist_id: u32) -> Self {
+        SearchEntity {
+            wishlist_id: Some(wishlist_id),
+            ..SearchEntity::new(ticket, query)
+        }
+    }
+
+    pub fn for_import(ticket: u32, query: &str, import_id: u32) -> Self {
+        SearchEntity {
+            import_id: Some(import_id),
+            ..SearchEntity::new(ticket, query)
+        }
+    }
+
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.created_at) >= ttl.as_secs()
+    }
+}
+
+impl Entity for SearchEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.ticket.to_string().as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "searches";
+}
+
+/// A peer reply to one of our searches, kept until the search expires. Locked results can't be
+/// downloaded and are not stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResultEntity {
+    pub ticket: u32,
+    pub username: String,
+    pub slot_free: bool,
+    pub average_speed: u32,
+    pub queue_length: u32,
+    pub files: Vec<File>,
+}
+
+impl SearchResultEntity {
+    fn key_prefix(ticket: u32) -> String {
+        format!("{}@", ticket)
+    }
+}
+
+impl From<&SearchReply> for SearchResultEntity {
+    fn from(reply: &SearchReply) -> Self {
+        SearchResultEntity {
+            ticket: reply.ticket,
+            username: reply.username.clone(),
+            slot_free: reply.slot_free,
+            average_speed: reply.average_speed,
+            queue_length: reply.queue_length,
+            files: reply.files.clone(),
+        }
+    }
+}
+
+impl Entity for SearchResultEntity {
+    fn get_key(&self) -> Vec<u8> {
+        format!(
+            "{}{}",
+            SearchResultEntity::key_prefix(self.ticket),
+            self.username
+        )
+        .as_bytes()
+        .to_vec()
+    }
+
+    const COLLECTION: &'static str = "search_results";
+}
+
+/// Remove the searches older than `ttl` and their results, wishlist searches are removed by the
+/// wishlist itself. Returns the number of searches removed.
+pub fn expire_searches(db: &Database, ttl: Duration) -> sled::Result<usize> {
+    let expired: Vec<SearchEntity> = db
+        .get_all::<SearchEntity>()
+        .into_iter()
+        .filter(|search| search.wishlist_id.is_none() && search.is_expired(ttl))
+        .collect();
+
+    for search in &expired {
+        for result in search_results(db, search.ticket) {
+            db.remove(&result)?;
+        }
+
+        db.remove(search)?;
+    }
+
+    Ok(expired.len())
+}
+
+pub(crate) fn search_results(db: &Database, ticket: u32) -> Vec<SearchResultEntity> {
+    db.get_by_prefix(&SearchResultEntity::key_prefix(ticket))
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchResultSort {
+    /// Fastest peers first
+    Speed,
+    /// Peers with a free upload slot first
+    FreeSlot,
+    /// Shortest upload queues first
+    QueueLength,
+    /// Biggest files first
+    Size,
+    /// Highest bitrates first
+    Bitrate,
+}
+
+/// Paging, sorting and filtering of the stored results of a search.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+pub struct SearchResultQuery {
+    pub page: usize,
+    pub per_page: usize,
+    pub sort: Option<SearchResultSort>,
+    /// Only keep files with this extension, case insensitive
+    pub extension: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+impl Default for SearchResultQuery {
+    fn default() -> Self {
+        SearchResultQuery {
+            page: 0,
+            per_page: DEFAULT_PAGE_SIZE,
+            sort: None,
+            extension: None,
+            min_size: None,
+            max_size: None,
+        }
+    }
+}
+
+/// A file found by a search, with the state of the peer sharing it.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SearchResult {
+    pub username: String,
+    pub slot_free: bool,
+    pub average_speed: u32,
+    pub queue_length: u32,
+    pub file: File,
+}
+
+impl SearchResult {
+    fn bitrate(&self) -> u32 {
+        self.file
+            .attributes
+            .iter()
+            .find(|attribute| attribute.place == Attribute::BITRATE)
+            .map(|attribute| attribute.attribute)
+            .unwrap_or_default()
+    }
+
+    fn extension(&self) -> &str {
+        if self.file.extension.is_empty() {
+            self.file.name.rsplit('.').next().unwrap_or_default()
+        } else {
+            &self.file.extension
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResultPage {
+    pub ticket: u32,
+    pub query: String,
+    pub created_at: u64,
+    /// Results matching the filters, across all pages
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub results: Vec<SearchResult>,
+}
+
+impl SearchResultQuery {
+    /// A page of the results stored for this search, `None` if the search is unknown or expired.
+    pub fn execute(&self, db: &Database, ticket: u32) -> Option<SearchResultPage> {
+        let search = db.get_by_key::<SearchEntity>(&ticket.to_string())?;
+        Some(self.page_of(&search, search_results(db, ticket)))
+    }
+
+    fn page_of(&self, search: &SearchEntity, replies: Vec<SearchResultEntity>) -> SearchResultPage {
+        let mut results: Vec<SearchResult> = replies
+            .into_iter()
+            .flat_map(|reply| {
+                let SearchResultEntity {
+                    username,
+                    slot_free,
+                    average_speed,
+                    queue_length,
+                    files,
+                    ..
+                } = reply;
+
+                files.into_iter().map(move |file| SearchResult {
+                    username: username.clone(),
+                    slot_free,
+                    average_speed,
+                    queue_length,
+                    file,
+                })
+            })
+            .filter(|result| self.matches(result))
+            .collect();
+
+        match self.sort {
+            Some(SearchResultSort::Speed) => {
+                results.sort_by_key(|result| Reverse(result.average_speed))
+            }
+            Some(SearchResultSort::FreeSlot) => results.sort_by_key(|result| !result.slot_free),
+            Some(SearchResultSort::QueueLength) => {
+                results.sort_by_key(|result| result.queue_length)
+            }
+            Some(SearchResultSort::Size) => results.sort_by_key(|result| Reverse(result.file.size)),
+            Some(SearchResultSort::Bitrate) => {
+                results.sort_by_key(|result| Reverse(result.bitrate()))
+            }
+            None => {}
+        }
+
+        let per_page = self.per_page.clamp(1, MAX_PAGE_SIZE);
+        let total = results.len();
+        let results = results
+            .into_iter()
+            .skip(self.page.saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+
+        SearchResultPage {
+            ticket: search.ticket,
+            query: search.query.clone(),
+            created_at: search.created_at,
+            total,
+            page: self.page,
+            per_page,
+            results,
+        }
+    }
+
+    fn matches(&self, result: &SearchResult) -> bool {
+        let extension_matches = self
+            .extension
+            .as_ref()
+            .is_none_or(|extension| result.extension().eq_ignore_ascii_case(extension));
+
+        extension_matches
+            && self.min_size.is_none_or(|min| result.file.size >= min)
+            && self.max_size.is_none_or(|max| result.file.size <= max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use soulseek_protocol::peers::p2p::{
+        search::SearchReply,
+        shared_directories::{Attribute, File},
+    };
+
+    use crate::entity::search::{
+        expire_searches, SearchEntity, SearchResultEntity, SearchResultQuery, SearchResultSort,
+    };
+    use crate::Database;
+
+    fn file(name: &str, size: u64, bitrate: u32) -> File {
+        File {
+            name: name.to_string(),
+            size,
+            extension: "".to_string(),
+            attributes: vec![Attribute::bitrate(bitrate)],
+        }
+    }
+
+    fn reply(ticket: u32, username: &str, average_speed: u32, files: Vec<File>) -> SearchReply {
+        SearchReply {
+            username: username.to_string(),
+            ticket,
+            files,
+            slot_free: average_speed > 100,
+            average_speed,
+            queue_length: 0,
+            locked_results: vec![],
+        }
+    }
+
+    fn store_search(db: &Database, ticket: u32) {
+        db.insert(&SearchEntity::new(ticket, "nirvana")).unwrap();
+        let replies = vec![
+            reply(
+                ticket,
+                "slow",
+                10,
+                vec![file("a.mp3", 4_000, 320), file("b.flac", 30_000, 900)],
+            ),
+            reply(ticket, "fast", 1000, vec![file("c.mp3", 3_000, 128)]),
+        ];
+
+        for reply in &replies {
+            db.insert(&SearchResultEntity::from(reply)).unwrap();
+        }
+    }
+
+    #[test]
+    fn should_page_and_sort_results() {
+        let db = Database::temporary();
+        store_search(&db, 1);
+        store_search(&db, 12);
+
+        let query = SearchResultQuery {
+            per_page: 2,
+            sort: Some(SearchResultSort::Bitrate),
+            ..SearchResultQuery::default()
+        };
+
+        let page = query.execute(&db, 1).unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.results.len(), 2);
+        assert_eq!(page.results[0].file.name, "b.flac");
+        assert_eq!(page.results[1].file.name, "a.mp3");
+
+        let page = SearchResultQuery { page: 1, ..query }
+            .execute(&db, 1)
+            .unwrap();
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.results[0].username, "fast");
+
+        let page = SearchResultQuery {
+            sort: Some(SearchResultSort::Speed),
+            ..SearchResultQuery::default()
+        }
+        .execute(&db, 1)
+        .unwrap();
+        assert_eq!(page.results[0].username, "fast");
+
+        assert!(SearchResultQuery::default().execute(&db, 2).is_none());
+    }
+
+    #[test]
+    fn should_filter_results() {
+        let db = Database::temporary();
+        store_search(&db, 1);
+
+        let page = SearchResultQuery {
+            extension: Some("MP3".to_string()),
+            min_size: Some(3_500),
+            ..SearchResultQuery::default()
+        }
+        .execute(&db, 1)
+        .unwrap();
+
+        assert_eq!(page.total, 1);
+        assert_eq!(page.results[0].file.name, "a.mp3");
+    }
+
+    #[test]
+    fn should_expire_searches() {
+        let db = Database::temporary();
+        store_search(&db, 1);
+        db.insert(&SearchEntity::for_wishlist(2, "nirvana", 7))
+            .unwrap();
+
+        assert_eq!(expire_searches(&db, Duration::from_secs(60)).unwrap(), 0);
+        assert_eq!(expire_searches(&db, Duration::from_secs(0)).unwrap(), 1);
+
+        assert!(SearchResultQuery::default().execute(&db, 1).is_none());
+        assert!(db.get_all::<SearchResultEntity>().is_empty());
+        assert!(db.get_by_key::<SearchEntity>("2").is_some());
+    }
+}
@@ -1,53 +1,666 @@
-use crate::settings::CONFIG;
+use crate::audio;
+use crate::entity::Entity;
+use crate::settings::{Settings, SharedDirectory, Visibility, CONFIG};
+use crate::{Database, SHARED_DIRS};
 use soulseek_protocol::peers::p2p::shared_directories::{Directory, File, SharedDirectories};
+use soulseek_protocol::query::Query;
+use soulseek_protocol::server::search::ExcludedSearchPhrases;
+use soulseek_protocol::server::shares::SharedFolderAndFiles;
+use std::collections::{BTreeMap, HashSet};
 use std::io;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-pub fn get_shared_directories() -> io::Result<SharedDirectories> {
-    let paths = &CONFIG.shared_directories;
-    let mut shared_directories = SharedDirectories { dirs: vec![] };
+/// A shared file as seen during the last scan, `modified` and `file.size` are used to decide
+/// whether the audio metadata needs to be read again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SharedFileEntity {
+    pub path: PathBuf,
+    pub modified: u64,
+    pub file: File,
+}
+
+impl Entity for SharedFileEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.path.to_string_lossy().as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "shared_files";
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ScanStats {
+    /// Number of files visited
+    pub scanned: usize,
+    /// Number of new or modified files
+    pub updated: usize,
+    /// Number of files removed from the index
+    pub removed: usize,
+}
+
+/// Separator used by Soulseek clients in shared paths, whatever the platform is.
+const VIRTUAL_PATH_SEPARATOR: char = '\\';
+
+/// Returns at most `limit` shared files matching the given query, file names contain the full
+/// virtual path. Files `username` is not allowed to download are returned in the second list, to
+/// be sent as locked results.
+pub fn search_shared_files(
+    db: &Database,
+    settings: &Settings,
+    query: &Query,
+    excluded_phrases: &ExcludedSearchPhrases,
+    username: &str,
+    limit: usize,
+) -> (Vec<File>, Vec<File>) {
+    let access_level = settings.access_level(db, username);
+    let shared_dirs = SHARED_DIRS.lock().unwrap();
+
+    shared_dirs
+        .dirs
+        .iter()
+        .flat_map(|dir| {
+            dir.files.iter().map(move |file| File {
+                name: format!("{}{}{}", dir.name, VIRTUAL_PATH_SEPARATOR, file.name),
+                ..file.clone()
+            })
+        })
+        .filter(|file| query.matches_file(file) && !excluded_phrases.excludes(&file.name))
+        .take(limit)
+        .partition(|file| is_accessible(&settings.shared_directories, access_level, &file.name))
+}
+
+/// The shared directories `username` is allowed to browse.
+pub fn shared_directories_for(
+    db: &Database,
+    settings: &Settings,
+    username: &str,
+) -> SharedDirectories {
+    let access_level = settings.access_level(db, username);
+    let shared_dirs = SHARED_DIRS.lock().unwrap();
+
+    filter_shared_dirs(&shared_dirs, &settings.shared_directories, access_level)
+}
+
+/// The directories `username` is allowed to browse under the given virtual folder, the folder
+/// itself first.
+pub fn folder_contents(
+    db: &Database,
+    settings: &Settings,
+    username: &str,
+    folder: &str,
+) -> Vec<Directory> {
+    let shared_dirs = shared_directories_for(db, settings, username);
+    directories_in(&shared_dirs, folder)
+}
+
+/// Returns true if `username` is allowed to download the file or browse the directory at the
+/// given virtual path.
+pub fn can_access(db: &Database, settings: &Settings, username: &str, virtual_path: &str) -> bool {
+    is_accessible(
+        &settings.shared_directories,
+        settings.access_level(db, username),
+        virtual_path,
+    )
+}
+
+fn filter_shared_dirs(
+    shared_dirs: &SharedDirectories,
+    shares: &[SharedDirectory],
+    access_level: Visibility,
+) -> SharedDirectories {
+    SharedDirectories {
+        dirs: shared_dirs
+            .dirs
+            .iter()
+            .filter(|dir| is_accessible(shares, access_level, &dir.name))
+            .cloned()
+            .collect(),
+    }
+}
+
+fn directories_in(shared_dirs: &SharedDirectories, folder: &str) -> Vec<Directory> {
+    let folder = folder.trim_end_matches(VIRTUAL_PATH_SEPARATOR);
+    let mut dirs: Vec<Directory> = shared_dirs
+        .dirs
+        .iter()
+        .filter(|dir| {
+            dir.name == folder
+                || dir
+                    .name
+                    .strip_prefix(folder)
+                    .map(|rest| rest.starts_with(VIRTUAL_PATH_SEPARATOR))
+                    .unwrap_or(false)
+        })
+        .cloned()
+        .collect();
+
+    dirs.sort_by(|a, b| a.name.cmp(&b.name));
+    dirs
+}
+
+fn is_accessible(shares: &[SharedDirectory], access_level: Visibility, virtual_path: &str) -> bool {
+    let root = virtual_path
+        .split(VIRTUAL_PATH_SEPARATOR)
+        .next()
+        .unwrap_or_default();
+
+    shares
+        .iter()
+        .find(|share| share.virtual_root() == root)
+        .map(|share| share.visibility() <= access_level)
+        .unwrap_or(false)
+}
+
+/// Folder and file count of the current share index, as expected by the Soulseek server.
+pub fn shared_folders_and_files() -> SharedFolderAndFiles {
+    let shared_dirs = SHARED_DIRS.lock().unwrap();
+
+    SharedFolderAndFiles {
+        dirs: shared_dirs.dirs.len() as u32,
+        files: shared_dirs
+            .dirs
+            .iter()
+            .map(|dir| dir.files.len() as u32)
+            .sum(),
+    }
+}
+
+/// Soulseek virtual path of a shared file or directory, ex: `@@music\\Artist\\Album`, returns
+/// `None` if the path is not inside a shared directory.
+pub fn to_virtual_path(path: &Path) -> Option<String> {
+    virtual_path(&CONFIG.shared_directories, path)
+}
+
+/// Resolve a virtual path requested by a peer to a shared file on disk. Returns `None` unless
+/// the path resolves to an existing file inside a shared directory.
+pub fn resolve_virtual_path(settings: &Settings, virtual_path: &str) -> Option<PathBuf> {
+    real_path(&settings.shared_directories, virtual_path)
+}
+
+fn virtual_path(shares: &[SharedDirectory], path: &Path) -> Option<String> {
+    shares.iter().find_map(|share| {
+        let relative = path.strip_prefix(share.path()).ok()?;
+
+        let virtual_path = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .fold(share.virtual_root(), |virtual_path, component| {
+                format!("{}{}{}", virtual_path, VIRTUAL_PATH_SEPARATOR, component)
+            });
+
+        Some(virtual_path)
+    })
+}
+
+fn real_path(shares: &[SharedDirectory], virtual_path: &str) -> Option<PathBuf> {
+    let mut components = virtual_path.split(VIRTUAL_PATH_SEPARATOR);
+    let root = components.next()?;
+    let share = shares.iter().find(|share| share.virtual_root() == root)?;
+
+    let mut path = share.path().to_path_buf();
+    for component in components {
+        // Each component must be a plain file or directory name, this rejects "..", absolute
+        // paths and embedded separators.
+        let mut parsed = Path::new(component).components();
+        match (parsed.next(), parsed.next()) {
+            (Some(Component::Normal(name)), None) if name == component => path.push(name),
+            _ => return None,
+        }
+    }
+
+    // Symbolic links could still point outside of the share
+    let path = path.canonicalize().ok()?;
+    let root = share.path().canonicalize().ok()?;
+
+    if path.starts_with(root) && path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Walk every shared directory and update the share index. Audio metadata is only read for
+/// files that are new or changed since the previous scan, files that disappeared are removed.
+pub fn scan_shared_directories(db: &Database) -> io::Result<ScanStats> {
+    scan_roots(db, &CONFIG.shared_directories)
+}
+
+/// Update the share index for the given paths only, typically reported by a filesystem watcher.
+/// Paths that no longer exist are removed from the index along with their content.
+pub fn update_shared_paths(db: &Database, paths: &[PathBuf]) -> io::Result<ScanStats> {
+    update_paths(db, &CONFIG.shared_directories, paths)
+}
+
+fn scan_roots(db: &Database, shares: &[SharedDirectory]) -> io::Result<ScanStats> {
+    let mut stats = ScanStats::default();
+    let mut seen = HashSet::new();
+
+    for share in shares {
+        visit_dir(db, share.path(), &mut seen, &mut stats)?;
+    }
+
+    for entity in db.get_all::<SharedFileEntity>() {
+        if !seen.contains(&entity.path) {
+            db.remove(&entity)?;
+            stats.removed += 1;
+        }
+    }
+
+    refresh_shared_dirs(db, shares);
+    Ok(stats)
+}
+
+fn update_paths(
+    db: &Database,
+    shares: &[SharedDirectory],
+    paths: &[PathBuf],
+) -> io::Result<ScanStats> {
+    let mut stats = ScanStats::default();
+
+    let paths = paths
+        .iter()
+        .filter(|path| shares.iter().any(|share| path.starts_with(share.path())));
 
     for path in paths {
-        visit_dir(path, &mut shared_directories.dirs)?;
+        let mut seen = HashSet::new();
+
+        if path.is_dir() {
+            visit_dir(db, path, &mut seen, &mut stats)?;
+        } else if path.is_file() {
+            visit_file(db, path, &mut stats)?;
+            seen.insert(path.clone());
+        }
+
+        // Anything under this path we did not see anymore has been deleted or moved away, the
+        // prefix also matches siblings like "album 2" for "album" hence the path check.
+        for entity in db.get_by_prefix::<SharedFileEntity>(&path.to_string_lossy()) {
+            if entity.path.starts_with(path) && !seen.contains(&entity.path) {
+                db.remove(&entity)?;
+                stats.removed += 1;
+            }
+        }
     }
 
-    Ok(shared_directories)
+    refresh_shared_dirs(db, shares);
+    Ok(stats)
 }
 
-fn visit_dir(path: &Path, dirs: &mut Vec<Directory>) -> io::Result<()> {
-    if path.is_dir() {
-        let mut dir = Directory {
-            name: path.to_str().unwrap().to_string(),
-            files: vec![],
-        };
+fn visit_dir(
+    db: &Database,
+    path: &Path,
+    seen: &mut HashSet<PathBuf>,
+    stats: &mut ScanStats,
+) -> io::Result<()> {
+    if !path.is_dir() {
+        error!("{:?} is not a directory", path);
+        return Ok(());
+    }
 
-        for entry in std::fs::read_dir(path)? {
-            let entry = entry?;
-            if entry.path().is_dir() {
-                visit_dir(&entry.path(), dirs)?;
-            } else {
-                let metadata = entry.metadata()?;
-                dir.files.push(File {
-                    name: entry.file_name().to_str().unwrap().to_string(),
-                    size: metadata.len(),
-                    extension: entry
-                        .file_name()
-                        .to_str()
-                        .unwrap()
-                        .split('.')
-                        .last()
-                        .unwrap()
-                        .to_string(),
-                    // TODO
-                    attributes: vec![],
-                })
+    // Unreadable entries are skipped, they should not prevent indexing the rest of the share
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Unable to read shared directory {:?}: {}", path, err);
+            return Ok(());
+        }
+    };
+
+    for entry in entries {
+        let entry_path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                warn!("Unable to read an entry of {:?}: {}", path, err);
+                continue;
             }
+        };
+
+        if entry_path.is_dir() {
+            visit_dir(db, &entry_path, seen, stats)?;
+        } else {
+            visit_file(db, &entry_path, stats)?;
+            seen.insert(entry_path);
+        }
+    }
+
+    Ok(())
+}
+
+fn visit_file(db: &Database, path: &Path, stats: &mut ScanStats) -> io::Result<()> {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            warn!("Unable to read metadata of shared file {:?}: {}", path, err);
+            return Ok(());
         }
+    };
 
-        dirs.push(dir);
-    } else {
-        error!("{:?} is not a directory", path)
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    stats.scanned += 1;
+
+    let cached = db.get_by_key::<SharedFileEntity>(&path.to_string_lossy());
+    if let Some(cached) = cached {
+        if cached.modified == modified && cached.file.size == size {
+            return Ok(());
+        }
     }
 
+    let attributes = match audio::read_metadata(path) {
+        Ok(Some(metadata)) => metadata.into_attributes(),
+        Ok(None) => vec![],
+        Err(err) => {
+            debug!("Unable to read audio metadata for {:?}: {}", path, err);
+            vec![]
+        }
+    };
+
+    let file = File {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size,
+        extension: path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_string(),
+        attributes,
+    };
+
+    db.insert(&SharedFileEntity {
+        path: path.to_path_buf(),
+        modified,
+        file,
+    })?;
+
+    stats.updated += 1;
     Ok(())
 }
+
+/// Rebuild the in memory share list from the share index.
+fn refresh_shared_dirs(db: &Database, shares: &[SharedDirectory]) {
+    *SHARED_DIRS.lock().unwrap() = build_shared_dirs(db, shares);
+}
+
+/// Directories are named after their virtual path so we never expose local paths to other peers.
+fn build_shared_dirs(db: &Database, shares: &[SharedDirectory]) -> SharedDirectories {
+    let mut dirs: BTreeMap<String, Vec<File>> = BTreeMap::new();
+
+    for entity in db.get_all::<SharedFileEntity>() {
+        let dir = entity
+            .path
+            .parent()
+            .and_then(|parent| virtual_path(shares, parent));
+
+        if let Some(dir) = dir {
+            dirs.entry(dir).or_default().push(entity.file);
+        }
+    }
+
+    let dirs = dirs
+        .into_iter()
+        .map(|(name, files)| Directory { name, files })
+        .collect();
+
+    SharedDirectories { dirs }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::shared_dirs::{
+        build_shared_dirs, directories_in, filter_shared_dirs, is_accessible, real_path,
+        scan_roots, update_paths, virtual_path, ScanStats, SharedFileEntity,
+    };
+    use crate::settings::{ConfiguredShare, SharedDirectory, Visibility};
+    use crate::Database;
+    use soulseek_protocol::peers::p2p::shared_directories::{Directory, SharedDirectories};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn share_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("vessel_share_{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album")).unwrap();
+        fs::write(
+            root.join("album").join("01 - Breed.flac"),
+            b"not really flac",
+        )
+        .unwrap();
+        fs::write(root.join("album").join("cover"), b"not really a cover").unwrap();
+        root
+    }
+
+    #[test]
+    fn should_only_update_changed_files() {
+        let db = Database::temporary();
+        let root = share_root("incremental");
+        let roots = vec![SharedDirectory::Path(root.clone())];
+
+        let stats = scan_roots(&db, &roots).unwrap();
+        assert_eq!(
+            stats,
+            ScanStats {
+                scanned: 2,
+                updated: 2,
+                removed: 0
+            }
+        );
+
+        fs::write(
+            root.join("album").join("cover"),
+            b"a bigger cover than before",
+        )
+        .unwrap();
+        fs::remove_file(root.join("album").join("01 - Breed.flac")).unwrap();
+
+        let stats = scan_roots(&db, &roots).unwrap();
+        assert_eq!(
+            stats,
+            ScanStats {
+                scanned: 1,
+                updated: 1,
+                removed: 1
+            }
+        );
+
+        let files = db.get_all::<SharedFileEntity>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file.extension, "");
+        assert_eq!(files[0].file.size, 26);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_skip_unreadable_entries() {
+        let db = Database::temporary();
+        let root = share_root("unreadable");
+        let roots = vec![SharedDirectory::Path(root.clone())];
+        std::os::unix::fs::symlink(root.join("missing"), root.join("album").join("dangling"))
+            .unwrap();
+
+        let stats = scan_roots(&db, &roots).unwrap();
+
+        assert_eq!(stats.scanned, 2);
+        assert_eq!(db.get_all::<SharedFileEntity>().len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_remove_deleted_directories() {
+        let db = Database::temporary();
+        let root = share_root("watch");
+        let roots = vec![SharedDirectory::Path(root.clone())];
+        scan_roots(&db, &roots).unwrap();
+
+        fs::remove_dir_all(root.join("album")).unwrap();
+        let stats = update_paths(&db, &roots, &[root.join("album")]).unwrap();
+
+        assert_eq!(stats.removed, 2);
+        assert!(db.get_all::<SharedFileEntity>().is_empty());
+
+        // Paths outside of the shared directories are ignored
+        let stats = update_paths(&db, &roots, &[std::env::temp_dir()]).unwrap();
+        assert_eq!(stats, ScanStats::default());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_publish_virtual_directories() {
+        let db = Database::temporary();
+        let root = share_root("virtual");
+        let roots = vec![SharedDirectory::Configured(ConfiguredShare {
+            path: root.clone(),
+            alias: Some("music".to_string()),
+            visibility: Visibility::Public,
+        })];
+
+        scan_roots(&db, &roots).unwrap();
+
+        let shared_dirs = build_shared_dirs(&db, &roots);
+        assert_eq!(shared_dirs.dirs.len(), 1);
+        assert_eq!(shared_dirs.dirs[0].name, "@@music\\album");
+        assert_eq!(shared_dirs.dirs[0].files.len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_map_virtual_paths() {
+        let root = share_root("mapping");
+        let shares = vec![SharedDirectory::Configured(ConfiguredShare {
+            path: root.clone(),
+            alias: Some("music".to_string()),
+            visibility: Visibility::Public,
+        })];
+        let file = root.join("album").join("01 - Breed.flac");
+
+        assert_eq!(
+            virtual_path(&shares, &file),
+            Some("@@music\\album\\01 - Breed.flac".to_string())
+        );
+        assert_eq!(virtual_path(&shares, Path::new("/etc/passwd")), None);
+        assert_eq!(
+            real_path(&shares, "@@music\\album\\01 - Breed.flac"),
+            Some(file.canonicalize().unwrap())
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_not_resolve_paths_outside_of_shares() {
+        let root = share_root("traversal");
+        let shares = vec![SharedDirectory::Configured(ConfiguredShare {
+            path: root.join("album"),
+            alias: Some("music".to_string()),
+            visibility: Visibility::Public,
+        })];
+        fs::write(root.join("secret"), b"not shared").unwrap();
+
+        assert_eq!(real_path(&shares, "@@music\\..\\secret"), None);
+        assert_eq!(real_path(&shares, "@@music\\/etc/passwd"), None);
+        assert_eq!(real_path(&shares, "@@music\\"), None);
+        assert_eq!(real_path(&shares, "@@other\\01 - Breed.flac"), None);
+        assert_eq!(real_path(&shares, "@@music\\missing.flac"), None);
+        // Directories can't be queued
+        assert_eq!(real_path(&shares, "@@music"), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn should_restrict_shares_per_access_level() {
+        let share = |alias: &str, visibility| {
+            SharedDirectory::Configured(ConfiguredShare {
+                path: PathBuf::from("/music").join(alias),
+                alias: Some(alias.to_string()),
+                visibility,
+            })
+        };
+        let shares = vec![
+            share("public", Visibility::Public),
+            share("buddies", Visibility::Buddies),
+            share("trusted", Visibility::Trusted),
+        ];
+        let directory = |name: &str| Directory {
+            name: name.to_string(),
+            files: vec![],
+        };
+        let shared_dirs = SharedDirectories {
+            dirs: vec![
+                directory("@@public\\album"),
+                directory("@@buddies\\album"),
+                directory("@@trusted\\album"),
+            ],
+        };
+
+        let visible = filter_shared_dirs(&shared_dirs, &shares, Visibility::Buddies);
+        let names: Vec<&str> = visible.dirs.iter().map(|dir| dir.name.as_str()).collect();
+        assert_eq!(names, vec!["@@public\\album", "@@buddies\\album"]);
+
+        assert!(is_accessible(
+            &shares,
+            Visibility::Public,
+            "@@public\\a.mp3"
+        ));
+        assert!(!is_accessible(
+            &shares,
+            Visibility::Public,
+            "@@buddies\\a.mp3"
+        ));
+        assert!(is_accessible(
+            &shares,
+            Visibility::Trusted,
+            "@@buddies\\a.mp3"
+        ));
+        assert!(!is_accessible(
+            &shares,
+            Visibility::Buddies,
+            "@@trusted\\a.mp3"
+        ));
+        assert!(!is_accessible(
+            &shares,
+            Visibility::Trusted,
+            "@@unknown\\a.mp3"
+        ));
+    }
+
+    #[test]
+    fn should_list_folder_contents() {
+        let directory = |name: &str| Directory {
+            name: name.to_string(),
+            files: vec![],
+        };
+        let shared_dirs = SharedDirectories {
+            dirs: vec![
+                directory("@@music\\album\\cd2"),
+                directory("@@music\\album"),
+                directory("@@music\\album 2"),
+                directory("@@music\\album\\cd1"),
+            ],
+        };
+
+        let dirs = directories_in(&shared_dirs, "@@music\\album\\");
+        let names: Vec<&str> = dirs.iter().map(|dir| dir.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "@@music\\album",
+                "@@music\\album\\cd1",
+                "@@music\\album\\cd2"
+            ]
+        );
+        assert!(directories_in(&shared_dirs, "@@music\\unknown").is_empty());
+    }
+}
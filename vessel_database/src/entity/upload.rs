@@ -1,5 +1,6 @@
+use crate::entity::privileges::PrivilegedUserEntity;
 use crate::entity::Entity;
-use crate::UPLOAD_QUEUE;
+use crate::{Database, UPLOAD_QUEUE};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UploadEntity {
@@ -22,6 +23,23 @@ impl UploadEntity {
             place_in_queue,
         }
     }
+
+    /// Place of the upload in our queue, uploads of privileged users come first and are
+    /// otherwise served in the order they were queued.
+    pub fn queue_position(&self, db: &Database) -> u32 {
+        let privileged =
+            |upload: &UploadEntity| PrivilegedUserEntity::is_privileged(db, &upload.user_name);
+        let priority = |upload: &UploadEntity| (!privileged(upload), upload.place_in_queue);
+
+        let own_priority = priority(self);
+        let ahead = db
+            .get_all::<UploadEntity>()
+            .iter()
+            .filter(|upload| priority(upload) < own_priority)
+            .count();
+
+        ahead as u32 + 1
+    }
 }
 
 impl Entity for UploadEntity {
@@ -33,3 +51,59 @@ impl Entity for UploadEntity {
 
     const COLLECTION: &'static str = "uploads";
 }
+
+/// Live state of our upload queue, advertised to peers in user info and search replies.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadStats {
+    pub upload_slots: u32,
+    pub queue_size: u32,
+    pub slots_free: bool,
+}
+
+impl UploadStats {
+    pub fn current(upload_slots: u32) -> Self {
+        let queue_size = *UPLOAD_QUEUE.lock().unwrap();
+
+        UploadStats {
+            upload_slots,
+            queue_size,
+            slots_free: queue_size < upload_slots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::privileges::PrivilegedUserEntity;
+    use crate::entity::upload::UploadEntity;
+    use crate::Database;
+
+    fn upload(user_name: &str, place_in_queue: u32) -> UploadEntity {
+        UploadEntity {
+            file_name: format!("track{}.flac", place_in_queue),
+            user_name: user_name.to_string(),
+            ticket: place_in_queue,
+            place_in_queue,
+        }
+    }
+
+    #[test]
+    fn should_serve_privileged_users_first() {
+        let db = Database::temporary();
+        let uploads = vec![upload("alice", 1), upload("bob", 2), upload("alice", 3)];
+        for upload in &uploads {
+            db.insert(upload).unwrap();
+        }
+
+        let positions = |db: &Database| -> Vec<u32> {
+            uploads
+                .iter()
+                .map(|upload| upload.queue_position(db))
+                .collect()
+        };
+        assert_eq!(positions(&db), vec![1, 2, 3]);
+
+        PrivilegedUserEntity::add(&db, "bob".to_string()).unwrap();
+        assert_eq!(positions(&db), vec![2, 1, 3]);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::entity::search::SearchEntity;
+use crate::entity::{now, Entity};
+use crate::Database;
+use soulseek_protocol::peers::p2p::search::SearchReply;
+use std::cmp::Reverse;
+use std::time::Duration;
+
+/// Matches kept per wishlist entry, popular queries would grow forever otherwise.
+const MAX_MATCHES: usize = 1000;
+
+/// A query searched periodically on behalf of the user with `WishlistSearch`, until it is removed.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct WishlistEntity {
+    pub id: u32,
+    pub query: String,
+    /// Queue the best match once the first results are in
+    pub auto_queue: bool,
+    /// Ticket of the last search for this entry
+    #[serde(default)]
+    pub ticket: Option<u32>,
+    /// Unix timestamp of the last search for this entry
+    #[serde(default)]
+    pub last_search: Option<u64>,
+    #[serde(default)]
+    pub matches: Vec<WishlistMatch>,
+}
+
+/// A file found for a wishlist entry.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct WishlistMatch {
+    pub username: String,
+    pub file_name: String,
+    pub size: u64,
+    pub slot_free: bool,
+    pub average_speed: u32,
+    pub queue_length: u32,
+    /// Unix timestamp of the search reply
+    pub found_at: u64,
+    /// The file was queued for download by the wishlist
+    pub queued: bool,
+}
+
+impl WishlistEntity {
+    pub fn new(id: u32, query: &str, auto_queue: bool) -> Self {
+        WishlistEntity {
+            id,
+            query: query.to_string(),
+            auto_queue,
+            ticket: None,
+            last_search: None,
+            matches: vec![],
+        }
+    }
+
+    /// Remember the ticket of a new search for this entry.
+    pub fn searched(&mut self, ticket: u32) {
+        self.ticket = Some(ticket);
+        self.last_search = Some(now());
+    }
+
+    /// Forget the last search for this entry, late replies to it are not recorded anymore.
+    pub fn forget_search(&mut self, db: &Database) -> sled::Result<()> {
+        match self.ticket.take() {
+            Some(ticket) => db.remove(&SearchEntity::new(ticket, &self.query)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the files we did not know about yet, returns how many were added.
+    pub fn record_matches(&mut self, reply: &SearchReply) -> usize {
+        let found_at = now();
+        let mut added = 0;
+
+        for file in &reply.files {
+            if self.matches.len() >= MAX_MATCHES {
+                break;
+            }
+
+            let known = self
+                .matches
+                .iter()
+                .any(|known| known.username == reply.username && known.file_name == file.name);
+
+            if !known {
+                self.matches.push(WishlistMatch {
+                    username: reply.username.clone(),
+                    file_name: file.name.clone(),
+                    size: file.size,
+                    slot_free: reply.slot_free,
+                    average_speed: reply.average_speed,
+                    queue_length: reply.queue_length,
+                    found_at,
+                    queued: false,
+                });
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    pub fn has_queued_match(&self) -> bool {
+        self.matches.iter().any(|found| found.queued)
+    }
+
+    /// Nothing was queued for this auto queue entry yet and its last search is older than
+    /// `delay`.
+    pub fn awaits_auto_queue(&self, delay: Duration) -> bool {
+        let searched_before = now().saturating_sub(delay.as_secs());
+
+        self.auto_queue
+            && !self.has_queued_match()
+            && self
+                .last_search
+                .is_some_and(|last_search| last_search <= searched_before)
+    }
+
+    /// The match we would rather download : a free slot first, then the shortest queue, the
+    /// fastest peer and the biggest file.
+    pub fn best_match(&self) -> Option<&WishlistMatch> {
+        self.matches
+            .iter()
+            .filter(|found| !found.queued)
+            .min_by_key(|found| {
+                (
+                    !found.slot_free,
+                    found.queue_length,
+                    Reverse(found.average_speed),
+                    Reverse(found.size),
+                )
+            })
+    }
+
+    pub fn mark_queued(&mut self, username: &str, file_name: &str) {
+        if let Some(found) = self
+            .matches
+            .iter_mut()
+            .find(|found| found.username == username && found.file_name == file_name)
+        {
+            found.queued = true;
+        }
+    }
+}
+
+impl Entity for WishlistEntity {
+    fn get_key(&self) -> Vec<u8> {
+        self.id.to_string().as_bytes().to_vec()
+    }
+
+    const COLLECTION: &'static str = "wishlist";
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use soulseek_protocol::peers::p2p::{search::SearchReply, shared_directories::File};
+
+    use crate::entity::search::SearchEntity;
+    use crate::entity::wishlist::WishlistEntity;
+    use crate::Database;
+
+    fn reply(username: &str, slot_free: bool, queue_length: u32, files: &[&str]) -> SearchReply {
+        SearchReply {
+            username: username.to_string(),
+            ticket: 1,
+            files: files
+                .iter()
+                .map(|name| File {
+                    name: name.to_string(),
+                    size: 1024,
+                    extension: "flac".to_string(),
+                    attributes: vec![],
+                })
+                .collect(),
+            slot_free,
+            average_speed: 1000,
+            queue_length,
+            locked_results: vec![],
+        }
+    }
+
+    #[test]
+    fn should_record_new_matches_only() {
+        let mut wishlist = WishlistEntity::new(1, "nirvana nevermind", false);
+
+        let added = wishlist.record_matches(&reply("alice", true, 0, &["a.flac", "b.flac"]));
+        assert_eq!(added, 2);
+
+        let added = wishlist.record_matches(&reply("alice", true, 0, &["b.flac", "c.flac"]));
+        assert_eq!(added, 1);
+        assert_eq!(wishlist.matches.len(), 3);
+    }
+
+    #[test]
+    fn should_pick_best_match() {
+        let mut wishlist = WishlistEntity::new(1, "nirvana nevermind", true);
+        wishlist.record_matches(&reply("busy", false, 0, &["busy.flac"]));
+        wishlist.record_matches(&reply("queued", true, 12, &["queued.flac"]));
+        wishlist.record_matches(&reply("free", true, 0, &["free.flac"]));
+
+        assert_eq!(wishlist.best_match().unwrap().username, "free");
+
+        assert!(!wishlist.awaits_auto_queue(Duration::from_secs(0)));
+        wishlist.searched(1);
+        assert!(wishlist.awaits_auto_queue(Duration::from_secs(0)));
+        assert!(!wishlist.awaits_auto_queue(Duration::from_secs(60)));
+
+        wishlist.mark_queued("free", "free.flac");
+        assert!(wishlist.has_queued_match());
+        assert!(!wishlist.awaits_auto_queue(Duration::from_secs(0)));
+        assert_eq!(wishlist.best_match().unwrap().username, "queued");
+    }
+
+    #[test]
+    fn should_store_wishlist() {
+        let db = Database::temporary();
+        let mut wishlist = WishlistEntity::new(42, "nirvana nevermind", true);
+        wishlist.searched(7);
+        db.insert(&wishlist).unwrap();
+
+        let stored = db.get_by_key::<WishlistEntity>("42").unwrap();
+        assert_eq!(stored, wishlist);
+        assert_eq!(stored.ticket, Some(7));
+    }
+
+    #[test]
+    fn should_forget_previous_search() {
+        let db = Database::temporary();
+        let mut wishlist = WishlistEntity::new(42, "nirvana nevermind", true);
+        db.insert(&SearchEntity::for_wishlist(7, &wishlist.query, wishlist.id))
+            .unwrap();
+        wishlist.searched(7);
+
+        wishlist.forget_search(&db).unwrap();
+        assert_eq!(wishlist.ticket, None);
+        assert!(db.get_by_key::<SearchEntity>("7").is_none());
+    }
+}
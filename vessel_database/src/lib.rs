@@ -11,11 +11,13 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use crate::entity::upload::UploadEntity;
-use entity::{shared_dirs::get_shared_directories, Entity};
+use entity::Entity;
 use soulseek_protocol::peers::p2p::shared_directories::SharedDirectories;
 use std::sync::atomic::AtomicU32;
 
+mod audio;
 pub mod entity;
+pub mod playlist;
 pub mod settings;
 
 #[derive(Clone, Debug)]
@@ -24,8 +26,9 @@ pub struct Database {
 }
 
 lazy_static! {
+    /// In memory view of the share index, filled by [`entity::shared_dirs::scan_shared_directories`].
     pub static ref SHARED_DIRS: Arc<Mutex<SharedDirectories>> =
-        Arc::new(Mutex::new(get_shared_directories().unwrap()));
+        Arc::new(Mutex::new(SharedDirectories { dirs: vec![] }));
     pub static ref UPLOAD_QUEUE: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
 }
 
@@ -42,6 +45,13 @@ impl Default for Database {
 }
 
 impl Database {
+    /// Open a throwaway database, deleted once dropped.
+    pub fn temporary() -> Self {
+        Database {
+            inner: sled::Config::new().temporary(true).open().unwrap(),
+        }
+    }
+
     pub fn insert<T>(&self, entity: &T) -> sled::Result<()>
     where
         T: Sized + Entity + Serialize,
@@ -56,6 +66,24 @@ impl Database {
             .map(|_res| ())
     }
 
+    pub fn remove<T>(&self, entity: &T) -> sled::Result<()>
+    where
+        T: Entity,
+    {
+        self.inner
+            .open_tree(T::COLLECTION)?
+            .remove(entity.get_key())
+            .map(|_res| ())
+    }
+
+    /// Remove every entity of the collection.
+    pub fn clear<T>(&self) -> sled::Result<()>
+    where
+        T: Entity,
+    {
+        self.inner.open_tree(T::COLLECTION)?.clear()
+    }
+
     pub fn get_all<T>(&self) -> Vec<T>
     where
         T: Entity + DeserializeOwned,
@@ -71,6 +99,53 @@ impl Database {
             .collect()
     }
 
+    /// Every entity with a key starting with `prefix`.
+    pub fn get_by_prefix<T>(&self, prefix: &str) -> Vec<T>
+    where
+        T: Entity + DeserializeOwned,
+    {
+        self.inner
+            .open_tree(T::COLLECTION)
+            .unwrap()
+            .scan_prefix(prefix)
+            .map(|res| res.expect("database error"))
+            .map(|(_k, v)| String::from_utf8(v.to_vec()).unwrap())
+            .map(|entity_string| serde_json::from_str(entity_string.as_str()))
+            .flat_map(Result::ok)
+            .collect()
+    }
+
+    /// Entities with a key starting with `prefix`, last key first, after skipping the `skip` last
+    /// ones. Entities are read lazily so callers can stop as soon as they are done.
+    pub fn get_by_prefix_rev<T>(&self, prefix: &str, skip: usize) -> impl Iterator<Item = T>
+    where
+        T: Entity + DeserializeOwned,
+    {
+        self.inner
+            .open_tree(T::COLLECTION)
+            .unwrap()
+            .scan_prefix(prefix)
+            .rev()
+            .skip(skip)
+            .map(|res| res.expect("database error"))
+            .map(|(_k, v)| String::from_utf8(v.to_vec()).unwrap())
+            .map(|entity_string| serde_json::from_str(entity_string.as_str()))
+            .flat_map(Result::ok)
+    }
+
+    /// Number of entities with a key starting with `prefix`, without reading them.
+    pub fn count_by_prefix<T>(&self, prefix: &str) -> usize
+    where
+        T: Entity,
+    {
+        self.inner
+            .open_tree(T::COLLECTION)
+            .unwrap()
+            .scan_prefix(prefix)
+            .keys()
+            .count()
+    }
+
     pub fn get_by_key<T>(&self, key: &str) -> Option<T>
     where
         T: Entity + DeserializeOwned,
@@ -104,6 +179,7 @@ mod test {
                 username: "toto".to_string(),
                 ip: Ipv4Addr::new(127, 0, 0, 1),
                 port: 0,
+                last_seen: 0,
             })
             .is_ok())
     }
@@ -115,6 +191,7 @@ mod test {
             username: "toto".to_string(),
             ip: Ipv4Addr::new(127, 0, 0, 1),
             port: 0,
+            last_seen: 0,
         })
         .unwrap();
         let peer = db.get_by_key::<PeerEntity>("toto").unwrap();
@@ -131,6 +208,7 @@ mod test {
             username: "alfred".to_string(),
             ip: Ipv4Addr::new(127, 0, 0, 1),
             port: 2222,
+            last_seen: 0,
         })
         .unwrap();
         let all_peers = db.get_all::<PeerEntity>();
@@ -0,0 +1,314 @@
+//! Track lists imported as batches of searches, see [`crate::entity::import`].
+
+/// Separator between the artist and the title in plain text lists and M3U titles.
+const ARTIST_TITLE_SEPARATOR: &str = " - ";
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistFormat {
+    /// One `artist - title` or bare title per line
+    Text,
+    /// `artist,title,album` rows, the album is optional
+    Csv,
+    M3u,
+    Xspf,
+}
+
+/// A track to look for on the network.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Track {
+    pub artist: Option<String>,
+    pub title: String,
+    pub album: Option<String>,
+}
+
+impl PlaylistFormat {
+    /// Guess the format of a playlist, CSV lists can't be told apart from plain text ones.
+    pub fn detect(content: &str) -> Self {
+        let content = content.trim_start_matches('\u{feff}').trim_start();
+
+        if content.starts_with('<') {
+            PlaylistFormat::Xspf
+        } else if content.starts_with("#EXTM3U") {
+            PlaylistFormat::M3u
+        } else {
+            PlaylistFormat::Text
+        }
+    }
+
+    /// Tracks of the playlist, entries without a title are skipped.
+    pub fn parse(&self, content: &str) -> Vec<Track> {
+        let content = content.trim_start_matches('\u{feff}');
+
+        match self {
+            PlaylistFormat::Text => parse_text(content),
+            PlaylistFormat::Csv => parse_csv(content),
+            PlaylistFormat::M3u => parse_m3u(content),
+            PlaylistFormat::Xspf => parse_xspf(content),
+        }
+    }
+}
+
+impl Track {
+    fn new(artist: Option<&str>, title: &str, album: Option<&str>) -> Option<Self> {
+        let non_empty = |value: &str| {
+            let value = value.trim();
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            }
+        };
+
+        Some(Track {
+            artist: artist.and_then(non_empty),
+            title: non_empty(title)?,
+            album: album.and_then(non_empty),
+        })
+    }
+
+    /// `artist - title`, or a bare title.
+    fn from_display_name(name: &str) -> Option<Self> {
+        match name.split_once(ARTIST_TITLE_SEPARATOR) {
+            Some((artist, title)) => Track::new(Some(artist), title, None),
+            None => Track::new(None, name, None),
+        }
+    }
+}
+
+fn parse_text(content: &str) -> Vec<Track> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(Track::from_display_name)
+        .collect()
+}
+
+fn parse_csv(content: &str) -> Vec<Track> {
+    content
+        .lines()
+        .map(csv_fields)
+        .enumerate()
+        .filter(|(row, fields)| {
+            let is_header = fields
+                .first()
+                .is_some_and(|field| field.trim().eq_ignore_ascii_case("artist"));
+            !(*row == 0 && is_header)
+        })
+        .filter_map(|(_, fields)| {
+            let field = |idx: usize| fields.get(idx).map(String::as_str);
+            Track::new(field(0), field(1)?, field(2))
+        })
+        .collect()
+}
+
+/// Split a CSV row, fields may be quoted and quotes are escaped by doubling them.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(char),
+        }
+    }
+
+    fields.push(field);
+    fields
+}
+
+fn parse_m3u(content: &str) -> Vec<Track> {
+    let mut tracks = vec![];
+    let mut display_name = None;
+
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:duration,Artist - Title
+            display_name = info.split_once(',').map(|(_, name)| name.to_string());
+        } else if !line.starts_with('#') {
+            let track = match display_name.take() {
+                Some(name) => Track::from_display_name(&name),
+                None => Track::from_display_name(file_stem(line)),
+            };
+
+            tracks.extend(track);
+        }
+    }
+
+    tracks
+}
+
+fn file_stem(location: &str) -> &str {
+    let file_name = location.rsplit(['/', '\\']).next().unwrap_or(location);
+
+    match file_name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => file_name,
+    }
+}
+
+/// XSPF playlists are parsed with plain string matching rather than an XML reader, which covers
+/// the files written by common players but has known limits: `<track>` elements with attributes
+/// (such as `xml:id`) are not found, CDATA sections and numeric character references are kept
+/// as is and only the first `title`, `creator`, `album` and `location` of a track are read.
+fn parse_xspf(content: &str) -> Vec<Track> {
+    content
+        .split("<track>")
+        .skip(1)
+        .filter_map(|track| {
+            let track = track.split("</track>").next().unwrap_or_default();
+            let title = match xml_element(track, "title") {
+                Some(title) => title,
+                // Like M3U entries, tracks only known by their file are named after it
+                None => {
+                    let location = percent_decode(&xml_element(track, "location")?);
+                    return Track::from_display_name(file_stem(&location));
+                }
+            };
+
+            Track::new(
+                xml_element(track, "creator").as_deref(),
+                &title,
+                xml_element(track, "album").as_deref(),
+            )
+        })
+        .collect()
+}
+
+/// Decode the `%XX` escapes of a location URI, invalid escapes are kept as is.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        let escaped = bytes
+            .get(idx + 1..idx + 3)
+            .filter(|_| bytes[idx] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Text content of the first `tag` element, XSPF track fields have no nested elements.
+fn xml_element(xml: &str, tag: &str) -> Option<String> {
+    let start = format!("<{}>", tag);
+    let end = format!("</{}>", tag);
+    let content = xml.split_once(&start)?.1.split_once(&end)?.0;
+
+    Some(
+        content
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::playlist::{PlaylistFormat, Track};
+
+    fn track(artist: Option<&str>, title: &str, album: Option<&str>) -> Track {
+        Track {
+            artist: artist.map(str::to_string),
+            title: title.to_string(),
+            album: album.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn should_parse_text_and_csv() {
+        let text = "Nirvana - Lithium\n\n# comment\nPolly\n";
+        assert_eq!(
+            PlaylistFormat::Text.parse(text),
+            vec![
+                track(Some("Nirvana"), "Lithium", None),
+                track(None, "Polly", None)
+            ]
+        );
+
+        let csv = "artist,title,album\nNirvana,Lithium,Nevermind\n\"Simon & Garfunkel\",\"The Boxer, Live\"\n,,\n";
+        assert_eq!(
+            PlaylistFormat::Csv.parse(csv),
+            vec![
+                track(Some("Nirvana"), "Lithium", Some("Nevermind")),
+                track(Some("Simon & Garfunkel"), "The Boxer, Live", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_m3u() {
+        let m3u = "#EXTM3U\n#EXTINF:257,Nirvana - Lithium\nMusic/Nirvana/05 Lithium.flac\nC:\\Music\\Polly.mp3\n";
+
+        assert_eq!(PlaylistFormat::detect(m3u), PlaylistFormat::M3u);
+        assert_eq!(
+            PlaylistFormat::M3u.parse(m3u),
+            vec![
+                track(Some("Nirvana"), "Lithium", None),
+                track(None, "Polly", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_xspf() {
+        let xspf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track>
+      <creator>Simon &amp; Garfunkel</creator>
+      <title>The Boxer</title>
+      <album>Bridge over Troubled Water</album>
+    </track>
+    <track>
+      <location>file:///music/Nirvana%20-%20Lithium.flac</location>
+    </track>
+    <track>
+      <annotation>No title nor location</annotation>
+    </track>
+  </trackList>
+</playlist>"#;
+
+        assert_eq!(PlaylistFormat::detect(xspf), PlaylistFormat::Xspf);
+        assert_eq!(
+            PlaylistFormat::Xspf.parse(xspf),
+            vec![
+                track(
+                    Some("Simon & Garfunkel"),
+                    "The Boxer",
+                    Some("Bridge over Troubled Water")
+                ),
+                track(Some("Nirvana"), "Lithium", None)
+            ]
+        );
+    }
+}
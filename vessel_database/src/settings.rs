@@ -1,5 +1,11 @@
+use crate::entity::buddy::BuddyEntity;
+use crate::Database;
 use config::{Config, ConfigError, File};
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 lazy_static! {
     pub static ref CONFIG: Settings = Settings::get().unwrap();
@@ -8,18 +14,468 @@ lazy_static! {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Settings {
-    pub(crate) shared_directories: Vec<PathBuf>,
+    pub shared_directories: Vec<SharedDirectory>,
     pub download_folder: PathBuf,
     pub username: String,
     pub password: String,
+    /// Users allowed to browse and download buddies only shares, along with the buddy list
+    #[serde(default)]
+    pub buddies: Vec<String>,
+    /// Users allowed to browse and download any share
+    #[serde(default)]
+    pub trusted_users: Vec<String>,
+    /// Description and picture sent to peers asking for our user info
+    #[serde(default)]
+    pub user_info: UserInfoSettings,
+    /// Number of files uploaded at the same time
+    #[serde(default = "default_upload_slots")]
+    pub upload_slots: u32,
+    /// Delays after which stalled or idle peer connections are closed
+    #[serde(default)]
+    pub timeouts: TimeoutSettings,
+    /// How many peer connections can be open at the same time
+    #[serde(default)]
+    pub connections: ConnectionSettings,
+    /// Our participation in the distributed search network
+    #[serde(default)]
+    pub distributed: DistributedSettings,
+    /// How often we search the Soulseek server
+    #[serde(default)]
+    pub searches: SearchSettings,
+}
+
+fn default_upload_slots() -> u32 {
+    2
+}
+
+/// ```toml
+/// [user_info]
+/// description = "Hello from vessel"
+/// picture = "/home/okno/Pictures/avatar.png"
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UserInfoSettings {
+    #[serde(default = "default_description")]
+    pub description: String,
+    #[serde(default)]
+    pub picture: Option<PathBuf>,
+}
+
+fn default_description() -> String {
+    "Hello from vessel".to_string()
+}
+
+impl Default for UserInfoSettings {
+    fn default() -> Self {
+        UserInfoSettings {
+            description: default_description(),
+            picture: None,
+        }
+    }
+}
+
+/// Peer connection timeouts in seconds, missing values fall back to their default.
+/// ```toml
+/// [timeouts]
+/// handshake_secs = 30
+/// idle_peer_secs = 300
+/// idle_distributed_secs = 600
+/// transfer_stall_secs = 60
+/// pending_connection_secs = 60
+/// queued_request_secs = 120
+/// peer_address_secs = 1800
+/// search_session_secs = 3600
+/// download_source_secs = 600
+/// ```
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TimeoutSettings {
+    /// Time given to an incoming connection to send PeerInit or PierceFirewall
+    pub handshake_secs: u64,
+    /// Peer to peer connections without any message in between are closed
+    pub idle_peer_secs: u64,
+    /// Distributed connections without any message in between are closed
+    pub idle_distributed_secs: u64,
+    /// File transfers not receiving any byte in between are aborted
+    pub transfer_stall_secs: u64,
+    /// Time given to a peer to answer our indirect connection request with PierceFirewall
+    pub pending_connection_secs: u64,
+    /// Requests waiting for a peer connection are dropped after this delay
+    pub queued_request_secs: u64,
+    /// Cached peer addresses older than this are requested again to the server
+    pub peer_address_secs: u64,
+    /// Searches and their stored results are removed after this delay
+    pub search_session_secs: u64,
+    /// Downloads started from a search fall over to the next source if the queued one did not
+    /// start uploading after this delay
+    pub download_source_secs: u64,
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        TimeoutSettings {
+            handshake_secs: 30,
+            idle_peer_secs: 300,
+            idle_distributed_secs: 600,
+            transfer_stall_secs: 60,
+            pending_connection_secs: 60,
+            queued_request_secs: 120,
+            peer_address_secs: 1800,
+            search_session_secs: 3600,
+            download_source_secs: 600,
+        }
+    }
+}
+
+impl TimeoutSettings {
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake_secs)
+    }
+
+    pub fn idle_peer(&self) -> Duration {
+        Duration::from_secs(self.idle_peer_secs)
+    }
+
+    pub fn idle_distributed(&self) -> Duration {
+        Duration::from_secs(self.idle_distributed_secs)
+    }
+
+    pub fn transfer_stall(&self) -> Duration {
+        Duration::from_secs(self.transfer_stall_secs)
+    }
+
+    pub fn pending_connection(&self) -> Duration {
+        Duration::from_secs(self.pending_connection_secs)
+    }
+
+    pub fn queued_request(&self) -> Duration {
+        Duration::from_secs(self.queued_request_secs)
+    }
+
+    pub fn peer_address(&self) -> Duration {
+        Duration::from_secs(self.peer_address_secs)
+    }
+
+    pub fn search_session(&self) -> Duration {
+        Duration::from_secs(self.search_session_secs)
+    }
+
+    pub fn download_source(&self) -> Duration {
+        Duration::from_secs(self.download_source_secs)
+    }
+}
+
+/// Peer connection limits, the least recently used peer connection is closed when the global
+/// limit is reached.
+/// ```toml
+/// [connections]
+/// max_connections = 4096
+/// max_connections_per_peer = 8
+/// ```
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionSettings {
+    pub max_connections: usize,
+    pub max_connections_per_peer: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        ConnectionSettings {
+            max_connections: 4096,
+            max_connections_per_peer: 8,
+        }
+    }
+}
+
+/// Distributed children we accept at most, the server speed requirements can lower this limit.
+/// ```toml
+/// [distributed]
+/// max_children = 10
+/// ```
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DistributedSettings {
+    pub max_children: u32,
+}
+
+impl Default for DistributedSettings {
+    fn default() -> Self {
+        DistributedSettings { max_children: 10 }
+    }
+}
+
+/// Client side limit of the searches sent to the Soulseek server, which kicks clients searching
+/// too often. Searches over the limit are queued until they can be sent.
+/// ```toml
+/// [searches]
+/// per_minute = 10
+/// burst = 5
+/// ```
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    /// Searches sent per minute on average
+    pub per_minute: u32,
+    /// Searches sent at once before the limit applies
+    pub burst: u32,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings {
+            per_minute: 10,
+            burst: 5,
+        }
+    }
 }
 
 impl Settings {
     pub fn get() -> Result<Self, ConfigError> {
         let mut s = Config::new();
         s.merge(File::from(PathBuf::from("vessel.toml")))?;
-        let settings: Result<Settings, ConfigError> = s.try_into();
+        let settings: Settings = s.try_into()?;
+
+        settings.validate()
+    }
+
+    /// Peers only see virtual paths, two shares under the same virtual root could not be told
+    /// apart.
+    fn validate(self) -> Result<Self, ConfigError> {
+        let mut virtual_roots = HashSet::new();
+
+        for share in &self.shared_directories {
+            if !virtual_roots.insert(share.virtual_root()) {
+                return Err(ConfigError::Message(format!(
+                    "{:?} is shared as {} more than once, set a unique alias for it",
+                    share.path(),
+                    share.virtual_root()
+                )));
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Highest share visibility the given user has access to, users of our buddy list are
+    /// buddies as well.
+    pub fn access_level(&self, db: &Database, username: &str) -> Visibility {
+        if self.trusted_users.iter().any(|user| user == username) {
+            Visibility::Trusted
+        } else if self.buddies.iter().any(|user| user == username)
+            || db.get_by_key::<BuddyEntity>(username).is_some()
+        {
+            Visibility::Buddies
+        } else {
+            Visibility::Public
+        }
+    }
+}
+
+/// Who can browse and download a shared directory, trusted users can access buddies only shares.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Buddies,
+    Trusted,
+}
+
+/// A shared directory, either a plain path or a path with the alias peers will see it under and
+/// its visibility. Each share must have a unique alias.
+/// ```toml
+/// shared_directories = [
+///   "/home/okno/Music",
+///   { path = "/home/okno/Downloads/vessel", alias = "downloads", visibility = "buddies" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SharedDirectory {
+    Path(PathBuf),
+    Configured(ConfiguredShare),
+}
+
+/// A shared directory with its options, unknown keys are rejected: a misspelled `visibility`
+/// would silently make the share public otherwise.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfiguredShare {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+impl SharedDirectory {
+    pub fn path(&self) -> &Path {
+        match self {
+            SharedDirectory::Path(path)
+            | SharedDirectory::Configured(ConfiguredShare { path, .. }) => path,
+        }
+    }
+
+    /// The alias defaults to the shared directory name.
+    pub fn alias(&self) -> String {
+        match self {
+            SharedDirectory::Configured(ConfiguredShare {
+                alias: Some(alias), ..
+            }) => alias.clone(),
+            _ => self
+                .path()
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "share".to_string()),
+        }
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        match self {
+            SharedDirectory::Path(_) => Visibility::Public,
+            SharedDirectory::Configured(share) => share.visibility,
+        }
+    }
+
+    /// Root of the Soulseek virtual path for this directory, ex: `@@music`.
+    pub fn virtual_root(&self) -> String {
+        format!("@@{}", self.alias())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entity::buddy::BuddyEntity;
+    use crate::settings::{Settings, SharedDirectory, Visibility};
+    use crate::Database;
+    use config::{Config, File, FileFormat};
+    use std::{path::Path, time::Duration};
+
+    #[test]
+    fn should_parse_shared_directories() {
+        let mut config = Config::new();
+        config
+            .merge(File::from_str(
+                r#"
+                shared_directories = [
+                    "/home/okno/Music",
+                    { path = "/home/okno/Downloads/vessel", alias = "downloads" },
+                    { path = "/home/okno/Private", visibility = "trusted" },
+                ]
+                download_folder = "/home/okno/Downloads/vessel"
+                username = "vessel"
+                password = "lessev"
+                buddies = ["alice"]
+                trusted_users = ["bob"]
+
+                [user_info]
+                picture = "/home/okno/avatar.png"
+
+                [timeouts]
+                handshake_secs = 10
+                "#,
+                FileFormat::Toml,
+            ))
+            .unwrap();
+
+        let settings: Settings = config.try_into().unwrap();
+        let shares: &[SharedDirectory] = &settings.shared_directories;
+
+        assert_eq!(shares[0].path(), Path::new("/home/okno/Music"));
+        assert_eq!(shares[0].virtual_root(), "@@Music");
+        assert_eq!(shares[1].path(), Path::new("/home/okno/Downloads/vessel"));
+        assert_eq!(shares[1].virtual_root(), "@@downloads");
+        assert_eq!(shares[1].visibility(), Visibility::Public);
+        assert_eq!(shares[2].virtual_root(), "@@Private");
+        assert_eq!(shares[2].visibility(), Visibility::Trusted);
+
+        let db = Database::temporary();
+        db.insert(&BuddyEntity::new("carol", "")).unwrap();
+        assert_eq!(settings.access_level(&db, "alice"), Visibility::Buddies);
+        assert_eq!(settings.access_level(&db, "bob"), Visibility::Trusted);
+        assert_eq!(settings.access_level(&db, "carol"), Visibility::Buddies);
+        assert_eq!(settings.access_level(&db, "eve"), Visibility::Public);
+
+        assert_eq!(settings.user_info.description, "Hello from vessel");
+        assert_eq!(
+            settings.user_info.picture.as_deref(),
+            Some(Path::new("/home/okno/avatar.png"))
+        );
+        assert_eq!(settings.upload_slots, 2);
+        assert_eq!(settings.timeouts.handshake(), Duration::from_secs(10));
+        assert_eq!(settings.timeouts.idle_peer(), Duration::from_secs(300));
+        assert_eq!(settings.timeouts.peer_address(), Duration::from_secs(1800));
+        assert_eq!(
+            settings.timeouts.search_session(),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            settings.timeouts.download_source(),
+            Duration::from_secs(600)
+        );
+        assert_eq!(settings.connections.max_connections, 4096);
+        assert_eq!(settings.distributed.max_children, 10);
+        assert_eq!(settings.searches.per_minute, 10);
+        assert_eq!(settings.searches.burst, 5);
+    }
+
+    #[test]
+    fn should_reject_duplicated_virtual_roots() {
+        let settings = |shared_directories: &str| -> Settings {
+            let mut config = Config::new();
+            config
+                .merge(File::from_str(
+                    &format!(
+                        r#"
+                        shared_directories = {}
+                        download_folder = "/home/okno/Downloads/vessel"
+                        username = "vessel"
+                        password = "lessev"
+                        "#,
+                        shared_directories
+                    ),
+                    FileFormat::Toml,
+                ))
+                .unwrap();
+            config.try_into().unwrap()
+        };
+
+        let same_name = settings(r#"["/home/okno/Music", "/mnt/backup/Music"]"#);
+        assert!(same_name.validate().is_err());
+
+        let same_alias = settings(
+            r#"[
+                { path = "/home/okno/Music", alias = "music" },
+                { path = "/home/okno/Mixes", alias = "music" },
+            ]"#,
+        );
+        assert!(same_alias.validate().is_err());
+
+        let aliased = settings(
+            r#"[
+                "/home/okno/Music",
+                { path = "/mnt/backup/Music", alias = "backup" },
+            ]"#,
+        );
+        assert!(aliased.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_unknown_share_options() {
+        let mut config = Config::new();
+        config
+            .merge(File::from_str(
+                r#"
+                shared_directories = [{ path = "/home/okno/Private", visiblity = "trusted" }]
+                download_folder = "/home/okno/Downloads/vessel"
+                username = "vessel"
+                password = "lessev"
+                "#,
+                FileFormat::Toml,
+            ))
+            .unwrap();
 
-        settings
+        assert!(config.try_into::<Settings>().is_err());
     }
 }
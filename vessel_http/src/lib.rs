@@ -1,10 +1,13 @@
 #[macro_use]
 extern crate log;
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use sender::VesselSender;
-use soulseek_protocol::{peers::PeerRequestPacket, server::request::ServerRequest};
+use soulseek_protocol::{
+    peers::PeerRequestPacket,
+    server::{login::SessionState, request::ServerRequest},
+};
 use vessel_database::Database;
 use warp::{http::Method, Filter};
 
@@ -15,17 +18,20 @@ mod sender;
 pub async fn start(
     slsk_sender: mpsc::Sender<ServerRequest>,
     peer_message_sender: mpsc::Sender<(String, PeerRequestPacket)>,
+    rescan_sender: mpsc::Sender<()>,
+    session_rx: watch::Receiver<SessionState>,
     db: Database,
 ) {
     let sender = VesselSender::new(slsk_sender);
     let peer_sender = VesselSender::new(peer_message_sender);
+    let rescan_sender = VesselSender::new(rescan_sender);
 
     info!("Starting vessel http ...");
     warp::serve(
-        routes::routes(db, sender, peer_sender).with(
+        routes::routes(db, sender, peer_sender, rescan_sender, session_rx).with(
             warp::cors()
                 .allow_any_origin()
-                .allow_methods(&[Method::POST, Method::GET])
+                .allow_methods(&[Method::POST, Method::GET, Method::PUT, Method::DELETE])
                 .allow_header("Content-Type"),
         ),
     )
@@ -1,4 +1,8 @@
 use serde_derive::{Deserialize, Serialize};
+use vessel_database::{
+    entity::import::{ImportEntity, ImportEntryEntity},
+    playlist::PlaylistFormat,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct SearchQuery {
@@ -20,7 +24,76 @@ pub struct QueueRequest {
     pub(crate) file_name: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct FolderRequest {
+    pub(crate) folder: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderDownloadTicket {
+    pub ticket: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Privileges {
+    pub time_left: u32,
+    pub checked_at: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PrivilegesGift {
+    pub(crate) username: String,
+    pub(crate) days: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct WishlistRequest {
+    pub(crate) query: String,
+    #[serde(default)]
+    pub(crate) auto_queue: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BuddyRequest {
+    pub(crate) username: String,
+    #[serde(default)]
+    pub(crate) note: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BuddyNote {
+    pub(crate) note: String,
+}
+
+/// A file picked from the results of a search, downloaded from the best peer sharing it.
+#[derive(Deserialize, Serialize)]
+pub struct SearchDownloadRequest {
+    pub(crate) username: String,
+    pub(crate) file_name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImportQuery {
+    /// Guessed from the playlist content when missing
+    pub(crate) format: Option<PlaylistFormat>,
+    #[serde(default)]
+    pub(crate) auto_queue: bool,
+}
+
+#[derive(Serialize)]
+pub struct Import {
+    #[serde(flatten)]
+    pub(crate) import: ImportEntity,
+    pub(crate) entries: Vec<ImportEntryEntity>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct ChatMessage {
     pub(crate) message: String,
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct BroadcastMessage {
+    pub(crate) users: Vec<String>,
+    pub(crate) message: String,
+}
@@ -0,0 +1,114 @@
+use warp::Filter;
+
+use soulseek_protocol::server::request::ServerRequest;
+use vessel_database::{entity::buddy::BuddyEntity, Database};
+
+use crate::{
+    model,
+    model::{BuddyNote, BuddyRequest},
+    routes::decode_path_param,
+    sender::VesselSender,
+};
+
+pub fn get_buddies(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("buddies"))
+        .map(move || warp::reply::json(&db.get_all::<BuddyEntity>()))
+}
+
+pub fn get_buddy(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("buddies" / String))
+        .map(move |username: String| {
+            let username = decode_path_param(&username);
+            match db.get_by_key::<BuddyEntity>(&username) {
+                Some(buddy) => warp::reply::json(&buddy),
+                None => not_found(&username),
+            }
+        })
+}
+
+pub fn add_buddy(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("buddies"))
+        .and(warp::body::json())
+        .map(move |request: BuddyRequest| {
+            if db.get_by_key::<BuddyEntity>(&request.username).is_some() {
+                return warp::reply::json(&model::Error {
+                    cause: format!("{} is already a buddy", request.username),
+                });
+            }
+
+            let buddy = BuddyEntity::new(&request.username, &request.note);
+            if let Err(err) = db.insert(&buddy) {
+                return warp::reply::json(&model::Error {
+                    cause: format!("Failed to store buddy: {}", err),
+                });
+            }
+
+            // The server replies with the buddy status and stats, then keeps us updated
+            sender.send(ServerRequest::AddUser(buddy.username.clone()));
+            warp::reply::json(&buddy)
+        })
+}
+
+pub fn update_buddy(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::put()
+        .and(warp::path!("buddies" / String))
+        .and(warp::body::json())
+        .map(move |username: String, request: BuddyNote| {
+            let username = decode_path_param(&username);
+            let mut buddy = match db.get_by_key::<BuddyEntity>(&username) {
+                Some(buddy) => buddy,
+                None => return not_found(&username),
+            };
+
+            buddy.note = request.note;
+            match db.insert(&buddy) {
+                Ok(()) => warp::reply::json(&buddy),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to store buddy: {}", err),
+                }),
+            }
+        })
+}
+
+pub fn remove_buddy(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::delete()
+        .and(warp::path!("buddies" / String))
+        .map(move |username: String| {
+            let username = decode_path_param(&username);
+            let buddy = match db.get_by_key::<BuddyEntity>(&username) {
+                Some(buddy) => buddy,
+                None => return not_found(&username),
+            };
+
+            match db.remove(&buddy) {
+                Ok(()) => {
+                    sender.send(ServerRequest::RemoveUser(buddy.username.clone()));
+                    warp::reply::json(&buddy)
+                }
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to remove buddy: {}", err),
+                }),
+            }
+        })
+}
+
+fn not_found(username: &str) -> warp::reply::Json {
+    warp::reply::json(&model::Error {
+        cause: format!("{} is not a buddy", username),
+    })
+}
@@ -1,8 +1,21 @@
 use warp::Filter;
 
-use soulseek_protocol::server::request::ServerRequest;
+use soulseek_protocol::server::{
+    chat::{GroupMessage, MessageUser},
+    request::ServerRequest,
+};
+use vessel_database::{
+    entity::chat::{ChatHistoryQuery, ChatMessageEntity, Conversation},
+    settings::CONFIG,
+    Database,
+};
 
-use crate::sender::VesselSender;
+use crate::{
+    model,
+    model::{BroadcastMessage, ChatMessage},
+    routes::decode_path_param,
+    sender::VesselSender,
+};
 
 pub fn start_public_chat(
     sender_copy: VesselSender<ServerRequest>,
@@ -21,3 +34,98 @@ pub fn stop_public_chat(
         "ok"
     })
 }
+
+pub fn send_private_message(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("users" / String / "messages"))
+        .and(warp::body::json())
+        .map(move |username: String, chat_message: ChatMessage| {
+            let username = decode_path_param(&username);
+            let sent =
+                ChatMessageEntity::sent_to(&username, &CONFIG.username, &chat_message.message);
+
+            sender.send(ServerRequest::SendPrivateMessage(MessageUser {
+                username,
+                message: chat_message.message,
+            }));
+
+            match db.insert(&sent) {
+                Ok(()) => warp::reply::json(&sent),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Message sent but not stored: {}", err),
+                }),
+            }
+        })
+}
+
+pub fn broadcast_message(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("messages" / "broadcast"))
+        .and(warp::body::json())
+        .map(move |broadcast: BroadcastMessage| {
+            if broadcast.users.is_empty() {
+                return warp::reply::json(&model::Error {
+                    cause: "No user to send the message to".to_string(),
+                });
+            }
+
+            // Each recipient gets the message in its own conversation
+            let sent: Vec<ChatMessageEntity> = broadcast
+                .users
+                .iter()
+                .map(|user| ChatMessageEntity::sent_to(user, &CONFIG.username, &broadcast.message))
+                .collect();
+
+            sender.send(ServerRequest::MessageUsers(GroupMessage {
+                users: broadcast.users,
+                message: broadcast.message,
+            }));
+
+            match sent.iter().try_for_each(|message| db.insert(message)) {
+                Ok(()) => warp::reply::json(&sent),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Message sent but not stored: {}", err),
+                }),
+            }
+        })
+}
+
+pub fn get_history(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(conversation())
+        .and(warp::path!("history"))
+        .and(warp::query::<ChatHistoryQuery>())
+        .map(move |conversation: Conversation, query: ChatHistoryQuery| {
+            warp::reply::json(&query.execute(&db, conversation))
+        })
+}
+
+pub fn export_history(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(conversation())
+        .and(warp::path!("export"))
+        .map(move |conversation: Conversation| conversation.export(&db))
+}
+
+/// `/chat/rooms/{room}` or `/chat/users/{username}` for private conversations.
+fn conversation() -> impl Filter<Extract = (Conversation,), Error = warp::Rejection> + Clone {
+    let room = warp::path("rooms")
+        .and(warp::path::param())
+        .map(|room: String| Conversation::Room(decode_path_param(&room)));
+
+    let private = warp::path("users")
+        .and(warp::path::param())
+        .map(|username: String| Conversation::Private(decode_path_param(&username)));
+
+    warp::path("chat").and(room.or(private).unify())
+}
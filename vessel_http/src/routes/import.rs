@@ -0,0 +1,156 @@
+use warp::Filter;
+
+use crate::{
+    model,
+    model::{Import, ImportQuery},
+    sender::VesselSender,
+};
+use soulseek_protocol::peers::p2p::transfer::QueueUpload;
+use soulseek_protocol::peers::{p2p::request::PeerRequest, PeerRequestPacket};
+use vessel_database::{
+    entity::import::{store_import, ImportEntity, ImportEntryEntity, ImportStatus},
+    playlist::PlaylistFormat,
+    Database,
+};
+
+/// Playlists bigger than this are rejected.
+const MAX_PLAYLIST_SIZE: u64 = 1024 * 1024;
+
+pub fn get_imports(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("imports"))
+        .map(move || warp::reply::json(&db.get_all::<ImportEntity>()))
+}
+
+pub fn get_import(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("imports" / u32))
+        .map(
+            move |id: u32| match db.get_by_key::<ImportEntity>(&id.to_string()) {
+                Some(import) => warp::reply::json(&Import {
+                    entries: import.entries(&db),
+                    import,
+                }),
+                None => not_found(id),
+            },
+        )
+}
+
+pub fn create_import(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("imports"))
+        .and(warp::query::<ImportQuery>())
+        .and(warp::body::content_length_limit(MAX_PLAYLIST_SIZE))
+        .and(warp::body::bytes())
+        .map(move |query: ImportQuery, body: warp::hyper::body::Bytes| {
+            let content = String::from_utf8_lossy(&body);
+            let format = query
+                .format
+                .unwrap_or_else(|| PlaylistFormat::detect(&content));
+            let tracks = format.parse(&content);
+
+            if tracks.is_empty() {
+                return warp::reply::json(&model::Error {
+                    cause: format!("No track found in {:?} playlist", format),
+                });
+            }
+
+            // Entries are searched one at a time by the import scheduler
+            let import = ImportEntity::new(rand::random(), format, query.auto_queue, tracks.len());
+            match store_import(&db, &import, tracks) {
+                Ok(()) => warp::reply::json(&Import {
+                    entries: import.entries(&db),
+                    import,
+                }),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to store import: {}", err),
+                }),
+            }
+        })
+}
+
+pub fn remove_import(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::delete()
+        .and(warp::path!("imports" / u32))
+        .map(move |id: u32| {
+            let import = match db.get_by_key::<ImportEntity>(&id.to_string()) {
+                Some(import) => import,
+                None => return not_found(id),
+            };
+
+            match import.remove(&db) {
+                Ok(()) => warp::reply::json(&import),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to remove import: {}", err),
+                }),
+            }
+        })
+}
+
+pub fn queue_import_entry(
+    db: Database,
+    peer_sender: VesselSender<(String, PeerRequestPacket)>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("imports" / u32 / "entries" / usize / "queue"))
+        .map(move |id: u32, index: usize| {
+            let key = ImportEntryEntity::key_from(id, index);
+            let mut entry = match db.get_by_key::<ImportEntryEntity>(&key) {
+                Some(entry) => entry,
+                None => {
+                    return warp::reply::json(&model::Error {
+                        cause: format!("Entry {} of import {} not found", index, id),
+                    })
+                }
+            };
+
+            let best_match = match (&entry.best_match, entry.status) {
+                (Some(best_match), ImportStatus::Review) => best_match.clone(),
+                _ => {
+                    return warp::reply::json(&model::Error {
+                        cause: format!("Entry {} of import {} has no match to queue", index, id),
+                    })
+                }
+            };
+
+            match entry.queue_best_match(&db, rand::random()) {
+                Ok(true) => {}
+                // The search expired, the match is queued without falling over to other peers
+                Ok(false) => {
+                    peer_sender.send((
+                        best_match.username,
+                        PeerRequestPacket::Message(PeerRequest::QueueUpload(QueueUpload {
+                            file_name: best_match.file_name,
+                        })),
+                    ));
+                    entry.queued(None);
+                }
+                Err(err) => {
+                    return warp::reply::json(&model::Error {
+                        cause: format!("Failed to queue import entry: {}", err),
+                    })
+                }
+            }
+
+            match db.insert(&entry) {
+                Ok(()) => warp::reply::json(&entry),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to store import entry: {}", err),
+                }),
+            }
+        })
+}
+
+fn not_found(id: u32) -> warp::reply::Json {
+    warp::reply::json(&model::Error {
+        cause: format!("Import {} not found", id),
+    })
+}
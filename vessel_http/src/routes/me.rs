@@ -0,0 +1,35 @@
+use warp::Filter;
+
+use crate::model;
+use vessel_database::entity::user_info::UserInfoEntity;
+use vessel_database::Database;
+
+pub fn get_user_info(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("me" / "info"))
+        .map(move || warp::reply::json(&UserInfoEntity::get(&db)))
+}
+
+pub fn update_user_info(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("me" / "info"))
+        .and(warp::body::json())
+        .map(move |user_info: UserInfoEntity| {
+            if let Err(err) = user_info.try_read_picture() {
+                return warp::reply::json(&model::Error {
+                    cause: format!("Invalid picture {:?}: {}", user_info.picture, err),
+                });
+            }
+
+            match db.insert(&user_info) {
+                Ok(()) => warp::reply::json(&user_info),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to store user info: {}", err),
+                }),
+            }
+        })
+}
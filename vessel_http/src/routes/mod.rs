@@ -1,29 +1,47 @@
+use percent_encoding::percent_decode;
 use warp::Filter;
 
-use soulseek_protocol::server::request::ServerRequest;
+use soulseek_protocol::server::{login::SessionState, request::ServerRequest};
+use tokio::sync::watch;
 
 use crate::sender::VesselSender;
 use soulseek_protocol::peers::PeerRequestPacket;
 use vessel_database::Database;
 
+pub(crate) mod buddies;
 pub(crate) mod chat;
+pub(crate) mod import;
+pub(crate) mod me;
 pub(crate) mod peers;
+pub(crate) mod privileges;
 pub(crate) mod rooms;
 pub(crate) mod search;
+pub(crate) mod session;
+pub(crate) mod shares;
 pub(crate) mod transfer;
 pub(crate) mod users;
+pub(crate) mod wishlist;
 
 pub fn routes(
     db: Database,
     sender: VesselSender<ServerRequest>,
     peer_sender: VesselSender<(String, PeerRequestPacket)>,
+    rescan_sender: VesselSender<()>,
+    session_rx: watch::Receiver<SessionState>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     rooms_routes(sender.clone())
-        .or(peers_routes(peer_sender))
-        .or(chat_routes(sender.clone()))
+        .or(peers_routes(peer_sender.clone(), db.clone()))
+        .or(chat_routes(sender.clone(), db.clone()))
         .or(users_routes(sender.clone(), db.clone()))
-        .or(search_routes(sender.clone()))
-        .or(transfer_routes(db))
+        .or(search_routes(sender.clone(), db.clone()))
+        .or(transfer_routes(db.clone()))
+        .or(shares_routes(rescan_sender))
+        .or(privileges_routes(sender.clone(), db.clone()))
+        .or(wishlist_routes(db.clone()))
+        .or(buddies_routes(sender.clone(), db.clone()))
+        .or(import_routes(db.clone(), peer_sender.clone()))
+        .or(me_routes(db))
+        .or(session::get_session(session_rx))
         .or(rooms_routes(sender))
 }
 
@@ -35,14 +53,22 @@ pub(crate) fn rooms_routes(
 
 pub(crate) fn chat_routes(
     sender: VesselSender<ServerRequest>,
+    db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    chat::start_public_chat(sender.clone()).or(chat::stop_public_chat(sender))
+    chat::start_public_chat(sender.clone())
+        .or(chat::stop_public_chat(sender.clone()))
+        .or(chat::send_private_message(sender.clone(), db.clone()))
+        .or(chat::broadcast_message(sender, db.clone()))
+        .or(chat::get_history(db.clone()))
+        .or(chat::export_history(db))
 }
 
 pub(crate) fn peers_routes(
     peer_sender: VesselSender<(String, PeerRequestPacket)>,
+    db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     peers::queue_upload_request(peer_sender.clone())
+        .or(peers::download_folder_request(peer_sender.clone(), db))
         .or(peers::send_share_resquest(peer_sender.clone()))
         .or(peers::send_user_info_request(peer_sender))
 }
@@ -58,12 +84,79 @@ pub(crate) fn users_routes(
 
 pub(crate) fn search_routes(
     sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    search::search(sender.clone(), db.clone())
+        .or(search::user_search(sender.clone(), db.clone()))
+        .or(search::room_search(sender, db.clone()))
+        .or(search::search_results(db.clone()))
+        .or(search::download_from_results(db))
+}
+
+pub(crate) fn shares_routes(
+    rescan_sender: VesselSender<()>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    shares::rescan(rescan_sender)
+}
+
+pub(crate) fn me_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    me::get_user_info(db.clone()).or(me::update_user_info(db))
+}
+
+pub(crate) fn privileges_routes(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    search::search(sender)
+    privileges::get_privileges(db).or(privileges::give_privileges(sender))
+}
+
+pub(crate) fn buddies_routes(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    buddies::get_buddies(db.clone())
+        .or(buddies::get_buddy(db.clone()))
+        .or(buddies::add_buddy(sender.clone(), db.clone()))
+        .or(buddies::update_buddy(db.clone()))
+        .or(buddies::remove_buddy(sender, db))
+}
+
+pub(crate) fn wishlist_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    wishlist::get_wishlist(db.clone())
+        .or(wishlist::get_wishlist_entry(db.clone()))
+        .or(wishlist::add_wishlist_entry(db.clone()))
+        .or(wishlist::update_wishlist_entry(db.clone()))
+        .or(wishlist::remove_wishlist_entry(db))
+}
+
+pub(crate) fn import_routes(
+    db: Database,
+    peer_sender: VesselSender<(String, PeerRequestPacket)>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    import::get_imports(db.clone())
+        .or(import::get_import(db.clone()))
+        .or(import::create_import(db.clone()))
+        .or(import::remove_import(db.clone()))
+        .or(import::queue_import_entry(db, peer_sender))
 }
 
 pub(crate) fn transfer_routes(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    transfer::get_downloads(db.clone()).or(transfer::get_uploads(db))
+    transfer::get_folder_downloads(db.clone())
+        .or(transfer::get_downloads(db.clone()))
+        .or(transfer::get_download_jobs(db.clone()))
+        .or(transfer::cancel_download_job(db.clone()))
+        .or(transfer::get_uploads(db))
+}
+
+/// Room and user names may contain spaces and other characters escaped in urls.
+pub(crate) fn decode_path_param(param: &str) -> String {
+    percent_decode(param.as_bytes())
+        .decode_utf8_lossy()
+        .to_string()
 }
@@ -1,8 +1,14 @@
 use warp::Filter;
 
-use crate::{model::QueueRequest, sender::VesselSender};
+use crate::{
+    model,
+    model::{FolderDownloadTicket, FolderRequest, QueueRequest},
+    sender::VesselSender,
+};
+use soulseek_protocol::peers::p2p::folder_content::FolderContentsRequest;
 use soulseek_protocol::peers::p2p::transfer::QueueUpload;
 use soulseek_protocol::peers::{p2p::request::PeerRequest, PeerRequestPacket};
+use vessel_database::{entity::download::FolderDownloadEntity, Database};
 
 pub fn queue_upload_request(
     peer_sender: VesselSender<(String, PeerRequestPacket)>,
@@ -21,6 +27,37 @@ pub fn queue_upload_request(
         })
 }
 
+pub fn download_folder_request(
+    peer_sender: VesselSender<(String, PeerRequestPacket)>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("peers" / String / "folders"))
+        .and(warp::body::json())
+        .map(move |peer_name: String, request: FolderRequest| {
+            let ticket = rand::random();
+
+            // Files are queued once the peer replies with the folder contents
+            let folder_download = FolderDownloadEntity::new(&peer_name, ticket, &request.folder);
+            if let Err(err) = db.insert(&folder_download) {
+                return warp::reply::json(&model::Error {
+                    cause: format!("Failed to store folder download: {}", err),
+                });
+            }
+
+            peer_sender.send((
+                peer_name,
+                PeerRequestPacket::Message(PeerRequest::FolderContentsRequest(
+                    FolderContentsRequest {
+                        ticket,
+                        folder: request.folder,
+                    },
+                )),
+            ));
+            warp::reply::json(&FolderDownloadTicket { ticket })
+        })
+}
+
 pub fn send_user_info_request(
     peer_sender_copy: VesselSender<(String, PeerRequestPacket)>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
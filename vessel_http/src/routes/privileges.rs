@@ -0,0 +1,45 @@
+use warp::Filter;
+
+use crate::{
+    model::{self, Privileges},
+    sender::VesselSender,
+};
+use soulseek_protocol::server::{privilege::PrivilegesGift, request::ServerRequest};
+use vessel_database::{entity::privileges::PrivilegesEntity, Database};
+
+pub fn get_privileges(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get().and(warp::path!("privileges")).map(move || {
+        let privileges = match PrivilegesEntity::get(&db) {
+            Some(privileges) => Privileges {
+                time_left: privileges.remaining(),
+                checked_at: Some(privileges.checked_at),
+            },
+            None => Privileges {
+                time_left: 0,
+                checked_at: None,
+            },
+        };
+
+        warp::reply::json(&privileges)
+    })
+}
+
+pub fn give_privileges(
+    sender: VesselSender<ServerRequest>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("privileges" / "give"))
+        .and(warp::body::json())
+        .map(move |gift: model::PrivilegesGift| {
+            sender.send(ServerRequest::GivePrivileges(PrivilegesGift {
+                username: gift.username,
+                days: gift.days,
+            }));
+
+            // Given days are taken from our own privileges
+            sender.send(ServerRequest::CheckPrivileges);
+            "ok"
+        })
+}
@@ -1,33 +1,166 @@
 use warp::Filter;
 
-use soulseek_protocol::server::{request::ServerRequest, search::SearchRequest};
+use soulseek_protocol::server::{
+    request::ServerRequest,
+    search::{RoomSearchQuery, SearchQuery as UserSearchQuery, SearchRequest},
+};
+
+use vessel_database::{
+    entity::{
+        download_job::DownloadJobEntity,
+        search::{SearchEntity, SearchResultQuery},
+    },
+    Database,
+};
 
 use crate::{
     model,
-    model::{SearchQuery, SearchTicket},
+    model::{SearchDownloadRequest, SearchQuery, SearchTicket},
+    routes::decode_path_param,
     sender::VesselSender,
 };
 
 pub fn search(
     sender: VesselSender<ServerRequest>,
+    db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let opt_query = warp::query::<SearchQuery>()
-        .map(Some)
-        .or_else(|_| async { Ok::<(Option<SearchQuery>,), std::convert::Infallible>((None,)) });
-
     warp::path!("search")
-        .and(opt_query)
+        .and(opt_search_query())
         .map(move |query: Option<SearchQuery>| match query {
-            Some(query) => {
-                let ticket = rand::random();
-                sender.send(ServerRequest::FileSearch(SearchRequest {
-                    ticket,
-                    query: query.term,
-                }));
-                warp::reply::json(&SearchTicket { ticket })
-            }
-            None => warp::reply::json(&model::Error {
-                cause: "Failed to decode query param.".to_string(),
+            Some(query) => send_search(&db, &sender, query.term, |ticket, query| {
+                ServerRequest::FileSearch(SearchRequest { ticket, query })
             }),
+            None => query_decode_error(),
+        })
+}
+
+pub fn user_search(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("users" / String / "search"))
+        .and(opt_search_query())
+        .map(
+            move |username: String, query: Option<SearchQuery>| match query {
+                Some(query) => {
+                    let username = decode_path_param(&username);
+                    send_search(&db, &sender, query.term, |ticket, query| {
+                        ServerRequest::UserSearch(UserSearchQuery {
+                            username,
+                            ticket,
+                            query,
+                        })
+                    })
+                }
+                None => query_decode_error(),
+            },
+        )
+}
+
+pub fn room_search(
+    sender: VesselSender<ServerRequest>,
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("rooms" / String / "search"))
+        .and(opt_search_query())
+        .map(
+            move |room: String, query: Option<SearchQuery>| match query {
+                Some(query) => {
+                    let room = decode_path_param(&room);
+                    send_search(&db, &sender, query.term, |ticket, query| {
+                        ServerRequest::RoomSearch(RoomSearchQuery {
+                            room,
+                            ticket,
+                            query,
+                        })
+                    })
+                }
+                None => query_decode_error(),
+            },
+        )
+}
+
+fn opt_search_query(
+) -> impl Filter<Extract = (Option<SearchQuery>,), Error = std::convert::Infallible> + Clone {
+    warp::query::<SearchQuery>()
+        .map(Some)
+        .or_else(|_| async { Ok::<(Option<SearchQuery>,), std::convert::Infallible>((None,)) })
+}
+
+/// Store the search and send it to the server, replies are then advertised under the returned
+/// ticket whatever the search target.
+fn send_search(
+    db: &Database,
+    sender: &VesselSender<ServerRequest>,
+    term: String,
+    request: impl FnOnce(u32, String) -> ServerRequest,
+) -> warp::reply::Json {
+    let ticket = rand::random();
+
+    // Keep track of the query so incoming replies can be filtered against it
+    if let Err(err) = db.insert(&SearchEntity::new(ticket, &term)) {
+        return warp::reply::json(&model::Error {
+            cause: format!("Failed to store search: {}", err),
+        });
+    }
+
+    sender.send(request(ticket, term));
+    warp::reply::json(&SearchTicket { ticket })
+}
+
+fn query_decode_error() -> warp::reply::Json {
+    warp::reply::json(&model::Error {
+        cause: "Failed to decode query param.".to_string(),
+    })
+}
+
+pub fn search_results(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("search" / u32 / "results"))
+        .and(warp::query::<SearchResultQuery>())
+        .map(
+            move |ticket: u32, query: SearchResultQuery| match query.execute(&db, ticket) {
+                Some(page) => warp::reply::json(&page),
+                None => warp::reply::json(&model::Error {
+                    cause: format!("Search {} not found or expired", ticket),
+                }),
+            },
+        )
+}
+
+pub fn download_from_results(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("search" / u32 / "download"))
+        .and(warp::body::json())
+        .map(move |ticket: u32, request: SearchDownloadRequest| {
+            let job = DownloadJobEntity::from_search(
+                &db,
+                rand::random(),
+                ticket,
+                &request.username,
+                &request.file_name,
+            );
+
+            // The best source is queued by the download job scheduler
+            match job {
+                Some(job) => match db.insert(&job) {
+                    Ok(()) => warp::reply::json(&job),
+                    Err(err) => warp::reply::json(&model::Error {
+                        cause: format!("Failed to store download job: {}", err),
+                    }),
+                },
+                None => warp::reply::json(&model::Error {
+                    cause: format!(
+                        "{} from {} not found in search {}",
+                        request.file_name, request.username, ticket
+                    ),
+                }),
+            }
         })
 }
@@ -0,0 +1,12 @@
+use tokio::sync::watch;
+use warp::Filter;
+
+use soulseek_protocol::server::login::SessionState;
+
+pub fn get_session(
+    session_rx: watch::Receiver<SessionState>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("session"))
+        .map(move || warp::reply::json(&*session_rx.borrow()))
+}
@@ -0,0 +1,15 @@
+use warp::Filter;
+
+use crate::sender::VesselSender;
+
+pub fn rescan(
+    rescan_sender: VesselSender<()>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("shares" / "rescan"))
+        .map(move || {
+            // A rescan requested while scanning is pending already
+            rescan_sender.send_once(());
+            "ok"
+        })
+}
@@ -1,4 +1,6 @@
-use vessel_database::entity::download::DownloadEntity;
+use crate::model;
+use vessel_database::entity::download::{DownloadEntity, FolderDownloadEntity};
+use vessel_database::entity::download_job::DownloadJobEntity;
 use vessel_database::entity::upload::UploadEntity;
 use vessel_database::Database;
 use warp::Filter;
@@ -9,6 +11,46 @@ pub fn get_downloads(
     warp::path!("downloads").map(move || warp::reply::json(&database.get_all::<DownloadEntity>()))
 }
 
+pub fn get_folder_downloads(
+    database: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("downloads" / "folders")
+        .map(move || warp::reply::json(&database.get_all::<FolderDownloadEntity>()))
+}
+
+pub fn get_download_jobs(
+    database: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("downloads" / "jobs"))
+        .map(move || warp::reply::json(&database.get_all::<DownloadJobEntity>()))
+}
+
+pub fn cancel_download_job(
+    database: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::delete()
+        .and(warp::path!("downloads" / "jobs" / u32))
+        .map(move |id: u32| {
+            let job = match database.get_by_key::<DownloadJobEntity>(&id.to_string()) {
+                Some(job) => job,
+                None => {
+                    return warp::reply::json(&model::Error {
+                        cause: format!("Download job {} not found", id),
+                    })
+                }
+            };
+
+            // A source already uploading the file is not stopped
+            match database.remove(&job) {
+                Ok(()) => warp::reply::json(&job),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to remove download job: {}", err),
+                }),
+            }
+        })
+}
+
 pub fn get_uploads(
     database: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
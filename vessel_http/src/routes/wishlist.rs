@@ -0,0 +1,108 @@
+use warp::Filter;
+
+use crate::{model, model::WishlistRequest};
+use vessel_database::{entity::wishlist::WishlistEntity, Database};
+
+pub fn get_wishlist(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("wishlist"))
+        .map(move || warp::reply::json(&db.get_all::<WishlistEntity>()))
+}
+
+pub fn get_wishlist_entry(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("wishlist" / u32))
+        .map(
+            move |id: u32| match db.get_by_key::<WishlistEntity>(&id.to_string()) {
+                Some(wishlist) => warp::reply::json(&wishlist),
+                None => not_found(id),
+            },
+        )
+}
+
+pub fn add_wishlist_entry(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("wishlist"))
+        .and(warp::body::json())
+        .map(move |request: WishlistRequest| {
+            // Entries are searched on the next wishlist interval
+            let wishlist = WishlistEntity::new(rand::random(), &request.query, request.auto_queue);
+            store(&db, &wishlist)
+        })
+}
+
+pub fn update_wishlist_entry(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::put()
+        .and(warp::path!("wishlist" / u32))
+        .and(warp::body::json())
+        .map(move |id: u32, request: WishlistRequest| {
+            let mut wishlist = match db.get_by_key::<WishlistEntity>(&id.to_string()) {
+                Some(wishlist) => wishlist,
+                None => return not_found(id),
+            };
+
+            // Matches of the previous query are not relevant anymore
+            if wishlist.query != request.query {
+                if let Err(err) = wishlist.forget_search(&db) {
+                    return search_error(err);
+                }
+                wishlist = WishlistEntity::new(id, &request.query, request.auto_queue);
+            }
+
+            wishlist.auto_queue = request.auto_queue;
+            store(&db, &wishlist)
+        })
+}
+
+pub fn remove_wishlist_entry(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::delete()
+        .and(warp::path!("wishlist" / u32))
+        .map(move |id: u32| {
+            let mut wishlist = match db.get_by_key::<WishlistEntity>(&id.to_string()) {
+                Some(wishlist) => wishlist,
+                None => return not_found(id),
+            };
+
+            if let Err(err) = wishlist.forget_search(&db) {
+                return search_error(err);
+            }
+
+            match db.remove(&wishlist) {
+                Ok(()) => warp::reply::json(&wishlist),
+                Err(err) => warp::reply::json(&model::Error {
+                    cause: format!("Failed to remove wishlist entry: {}", err),
+                }),
+            }
+        })
+}
+
+fn store(db: &Database, wishlist: &WishlistEntity) -> warp::reply::Json {
+    match db.insert(wishlist) {
+        Ok(()) => warp::reply::json(wishlist),
+        Err(err) => warp::reply::json(&model::Error {
+            cause: format!("Failed to store wishlist entry: {}", err),
+        }),
+    }
+}
+
+fn search_error(err: impl std::fmt::Display) -> warp::reply::Json {
+    warp::reply::json(&model::Error {
+        cause: format!("Failed to remove previous wishlist search: {}", err),
+    })
+}
+
+fn not_found(id: u32) -> warp::reply::Json {
+    warp::reply::json(&model::Error {
+        cause: format!("Wishlist entry {} not found", id),
+    })
+}
@@ -1,6 +1,6 @@
 use std::fmt::Debug;
 
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::{self, error::TrySendError};
 
 #[derive(Debug)]
 pub struct VesselSender<T> {
@@ -18,6 +18,15 @@ where
     pub(crate) fn send(&self, t: T) {
         self.inner.try_send(t).unwrap();
     }
+
+    /// Send a request that is only needed once, it is dropped if the same request is still
+    /// pending.
+    pub(crate) fn send_once(&self, t: T) {
+        match self.inner.try_send(t) {
+            Ok(()) | Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Closed(t)) => error!("Unable to send {:?}, receiver is gone", t),
+        }
+    }
 }
 
 impl<T> Clone for VesselSender<T> {
@@ -15,22 +15,26 @@ use crate::{
         channels::SenderPool,
         listener::{PeerListenerReceivers, PeerListenerSenders},
     },
-    tasks::spawn_server_listener_task,
+    slsk::session::Session,
+    tasks::{spawn_server_listener_task, ServerListenerChannels},
 };
 use eyre::Result;
 use soulseek_protocol::{
     peers::{p2p::response::PeerResponse, PeerRequestPacket},
     server::{
-        peer::{Peer, PeerConnectionRequest},
+        peer::PeerConnectionRequest,
         request::ServerRequest,
         response::ServerResponse,
+        search::{ExcludedSearchPhrases, SearchQuery},
     },
 };
-use vessel_database::Database;
+use vessel_database::{settings::CONFIG, Database};
 
 const PEER_LISTENER_ADDRESS: &str = "0.0.0.0:2255";
 
 mod peers;
+mod search;
+mod shares;
 mod slsk;
 mod tasks;
 
@@ -65,63 +69,202 @@ async fn main() -> Result<()> {
     let (request_peer_connection_tx, request_peer_connection_rx) =
         mpsc::channel::<ServerRequest>(channel_bound);
 
-    // Dispatch possible parrent to the global peer handler
-    let (possible_parent_tx, possible_parent_rx) = mpsc::channel::<Vec<Peer>>(channel_bound);
+    // Dispatch possible parents and parent requirements to the distributed network handler
+    let (distributed_tx, distributed_rx) = mpsc::channel::<ServerResponse>(channel_bound);
+    // Branch updates from our distributed connections
+    let (distributed_events_tx, distributed_events_rx) = mpsc::channel(channel_bound);
 
+    // Login state, tasks talking to the Soulseek server or to peers wait for it
+    let (session, session_rx) = Session::new();
+    session.connecting();
     let connection = slsk::connection::connect().await;
 
     let login_sender = http_tx.clone();
-    let (logged_in_tx, logged_in_rx) = mpsc::channel::<()>(1);
+    let privileges_check_sender = http_tx.clone();
     let (peer_address_tx, peer_address_rx) = mpsc::channel(channel_bound);
+    // Peers the server could not connect us to, their queued requests are dropped
+    let (cant_connect_tx, cant_connect_rx) = mpsc::channel(channel_bound);
+    // Report peer requests we could not deliver to the SSE clients
+    let (undelivered_tx, undelivered_rx) = mpsc::channel(channel_bound);
+
+    // Dispatch incoming searches to the search responder
+    let (search_tx, search_rx) = mpsc::channel::<SearchQuery>(channel_bound);
+    let (excluded_phrases_tx, excluded_phrases_rx) =
+        mpsc::channel::<ExcludedSearchPhrases>(channel_bound);
+
+    // Trigger a full share rescan via http
+    let (rescan_tx, rescan_rx) = mpsc::channel::<()>(1);
+    let share_index_sender = http_tx.clone();
+
+    // Wishlist search interval sent by the server, and replies to our wishlist searches
+    let (wishlist_interval_tx, wishlist_interval_rx) = mpsc::channel(1);
+    let (wishlist_tx, wishlist_rx) = mpsc::channel(channel_bound);
+    let wishlist_sender = http_tx.clone();
+
+    // Peers accepting or refusing the files queued by download jobs
+    let (source_event_tx, source_event_rx) = mpsc::channel(channel_bound);
+
+    // Searches go through the search scheduler before being written to the server connection
+    let (search_scheduler_tx, search_scheduler_rx) = mpsc::channel(channel_bound);
+    let (scheduled_search_tx, scheduled_search_rx) = mpsc::channel(channel_bound);
+    let (queued_search_tx, queued_search_rx) = mpsc::channel(channel_bound);
+
+    // Status and stats of watched users, and changes of our buddies advertised to SSE
+    let (buddy_tx, buddy_rx) = mpsc::channel(channel_bound);
+    let (buddy_event_tx, buddy_event_rx) = mpsc::channel(channel_bound);
+    let buddy_sender = http_tx.clone();
 
     // Keep the UI updated about ongoing downloads
     let (download_progress_tx, download_progress_rx) = mpsc::channel(channel_bound);
+    let import_sender = http_tx.clone();
 
     let database = Database::default();
 
     // listen for incoming client commands and forward soulseek message to the sse service
     let soulseek_server_listener = spawn_server_listener_task(
-        http_rx,
-        sse_tx,
-        peer_listener_tx,
-        request_peer_connection_rx,
-        possible_parent_tx,
+        ServerListenerChannels {
+            http_rx,
+            sse_tx: sse_tx.clone(),
+            peer_listener_tx,
+            request_peer_connection_rx,
+            distributed_tx,
+            peer_address_tx,
+            search_tx: search_tx.clone(),
+            excluded_phrases_tx,
+            cant_connect_tx,
+            wishlist_interval_tx,
+            search_scheduler_tx,
+            scheduled_search_rx,
+            buddy_tx,
+        },
         connection,
-        logged_in_tx,
-        peer_address_tx,
+        session,
+        database.clone(),
+    );
+
+    // Hold back searches over the configured limits
+    let search_scheduler = tasks::spawn_search_scheduler(
+        search_scheduler_rx,
+        scheduled_search_tx,
+        queued_search_tx,
+        CONFIG.searches,
     );
 
     // Start the warp SSE server with a soulseek mpsc event receiver
     // this task will proxy soulseek events to the web clients
-    let sse_server = tasks::spawn_sse_server(sse_rx, sse_peer_rx, download_progress_rx);
+    let sse_server = tasks::spawn_sse_server(
+        sse_rx,
+        sse_peer_rx,
+        download_progress_rx,
+        undelivered_rx,
+        queued_search_rx,
+        buddy_event_rx,
+        session_rx.clone(),
+    );
 
     // Start the HTTP api proxy with the soulseek mpsc event sender
     // Here we are only sending request via HTTP and expect no other response
     // than 201/NO_CONTENT
-    let http_server =
-        tasks::spawn_http_listener(http_tx, peer_message_dispatcher_tx, database.clone());
+    let http_server = tasks::spawn_http_listener(
+        http_tx,
+        peer_message_dispatcher_tx.clone(),
+        rescan_tx,
+        session_rx.clone(),
+        database.clone(),
+    );
+
+    // Answer incoming search requests with our shared files
+    let search_responder = tasks::spawn_search_responder(
+        database.clone(),
+        search_rx,
+        excluded_phrases_rx,
+        peer_message_dispatcher_tx.clone(),
+    );
+
+    // Search our wishlist periodically, queuing the best matches if asked to
+    let wishlist_scheduler = tasks::spawn_wishlist_scheduler(
+        database.clone(),
+        wishlist_interval_rx,
+        wishlist_rx,
+        wishlist_sender,
+        peer_message_dispatcher_tx.clone(),
+        session_rx.clone(),
+    );
+
+    // Queue the best source of downloads started from a search, falling over to the next ones
+    let download_job_scheduler = tasks::spawn_download_job_scheduler(
+        database.clone(),
+        source_event_rx,
+        peer_message_dispatcher_tx,
+        CONFIG.timeouts.download_source(),
+        session_rx.clone(),
+    );
+
+    // Search the tracks of imported playlists, one at a time
+    let import_scheduler = tasks::spawn_import_scheduler(
+        database.clone(),
+        import_sender,
+        download_progress_tx.clone(),
+        session_rx.clone(),
+    );
+
+    // Watch our buddies once logged in
+    let buddy_watcher = tasks::spawn_buddy_watcher(
+        database.clone(),
+        buddy_rx,
+        buddy_sender,
+        buddy_event_tx,
+        session_rx.clone(),
+    );
 
     // Once every thing is ready we need to login before talking to the soulseek server
     // Vessel support one and only one user connection, credentials are retrieved from vessel configuration
-    let login = tasks::spawn_login_task(login_sender);
+    let login = tasks::spawn_login_task(login_sender, session_rx.clone());
+
+    // Forget searches and their results after a while
+    let search_expiry =
+        tasks::spawn_search_expiry(database.clone(), CONFIG.timeouts.search_session());
+
+    // Keep track of our remaining privileges
+    let privileges_check =
+        tasks::spawn_privileges_check(privileges_check_sender, session_rx.clone());
+
+    // Index our shared directories in the background and keep the index updated
+    let share_indexer = tasks::spawn_share_indexer(
+        database.clone(),
+        rescan_rx,
+        share_index_sender,
+        session_rx.clone(),
+    );
 
     let listener = TcpListener::bind(PEER_LISTENER_ADDRESS).await?;
 
-    let channels = SenderPool::new(download_progress_tx);
+    let channels = SenderPool::new(
+        download_progress_tx,
+        search_tx,
+        distributed_events_tx,
+        wishlist_tx,
+        source_event_tx,
+        CONFIG.connections,
+    );
 
     // Listen for peer connection
     let peer_listener = tasks::spawn_peer_listener(
         PeerListenerSenders {
             sse_tx: sse_peer_tx,
+            sse_server_tx: sse_tx,
+            undelivered_tx,
             server_request_tx: request_peer_connection_tx,
         },
         PeerListenerReceivers {
             peer_connection_rx,
-            possible_parent_rx,
+            distributed_rx,
+            distributed_events_rx,
             peer_request_rx: peer_message_dispatcher_rx,
             peer_address_rx,
+            cant_connect_rx,
         },
-        logged_in_rx,
+        session_rx,
         listener,
         database,
         channels,
@@ -133,7 +276,16 @@ async fn main() -> Result<()> {
         http_server,
         soulseek_server_listener,
         login,
-        peer_listener
+        privileges_check,
+        peer_listener,
+        search_responder,
+        search_scheduler,
+        wishlist_scheduler,
+        download_job_scheduler,
+        import_scheduler,
+        buddy_watcher,
+        search_expiry,
+        share_indexer
     );
 
     // TODO : gracefull shutdown
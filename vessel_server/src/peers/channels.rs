@@ -1,22 +1,35 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, RwLock};
 
 use eyre::Result;
 use soulseek_protocol::{
     message_common::ConnectionType,
-    peers::{p2p::download::DownloadProgress, PeerRequestPacket},
+    peers::{
+        p2p::{download::DownloadProgress, search::SearchReply},
+        PeerRequestPacket,
+    },
+    server::search::SearchQuery,
 };
 use tokio::sync::mpsc::Sender;
+use vessel_database::settings::ConnectionSettings;
+
+use crate::peers::distributed::DistributedEvent;
+use crate::search::download::SourceEvent;
 
 #[derive(Debug, Clone)]
 pub struct SenderPool {
-    ok_connections: Arc<Mutex<HashMap<u32, PeerConnectionState>>>,
-    pending_connections: Arc<Mutex<Vec<PeerConnectionState>>>,
+    pool: Arc<RwLock<ConnectionPool>>,
+    limits: ConnectionSettings,
     download_progress_sender: Sender<DownloadProgress>,
+    search_sender: Sender<SearchQuery>,
+    distributed_sender: Sender<DistributedEvent>,
+    wishlist_sender: Sender<(u32, SearchReply)>,
+    source_event_sender: Sender<SourceEvent>,
 }
 
 #[derive(Debug, Clone)]
@@ -25,116 +38,267 @@ pub struct PeerConnectionState {
     pub token: u32,
     pub channel: Option<mpsc::Sender<PeerRequestPacket>>,
     pub conn_type: ConnectionType,
+    pub last_used: Instant,
+}
+
+impl PeerConnectionState {
+    /// A connection is healthy as long as its handler is still listening for requests.
+    fn is_healthy(&self) -> bool {
+        self.channel
+            .as_ref()
+            .is_some_and(|channel| !channel.is_closed())
+    }
+}
+
+/// An indirect connection waiting for the peer PierceFirewall message.
+#[derive(Debug, Clone)]
+struct PendingConnection {
+    state: PeerConnectionState,
+    since: Instant,
+}
+
+/// Ready connections indexed by token and by peer.
+#[derive(Debug, Default)]
+struct ConnectionPool {
+    by_token: HashMap<u32, PeerConnectionState>,
+    by_peer: HashMap<(String, ConnectionType), Vec<u32>>,
+    pending: Vec<PendingConnection>,
+}
+
+impl ConnectionPool {
+    fn insert(&mut self, state: PeerConnectionState, limits: &ConnectionSettings) -> Result<()> {
+        // File transfers are not limited, a peer may upload several files to us at once
+        if state.conn_type != ConnectionType::FileTransfer {
+            let peer_connections = self.peer_connections(&state.username, state.token);
+
+            if peer_connections >= limits.max_connections_per_peer {
+                return Err(eyre!(
+                    "Connection limit reached for peer {} ({}/{})",
+                    state.username,
+                    peer_connections,
+                    limits.max_connections_per_peer
+                ));
+            }
+        }
+
+        // A token is unique per connection, replace any stale state
+        self.remove(state.token);
+
+        self.by_peer
+            .entry((state.username.clone(), state.conn_type))
+            .or_default()
+            .push(state.token);
+        self.by_token.insert(state.token, state);
+
+        Ok(())
+    }
+
+    /// Peer to peer and distributed connections with this peer, besides the one with `token`.
+    fn peer_connections(&self, username: &str, token: u32) -> usize {
+        [
+            ConnectionType::PeerToPeer,
+            ConnectionType::DistributedNetwork,
+        ]
+        .iter()
+        .filter_map(|conn_type| self.by_peer.get(&(username.to_string(), *conn_type)))
+        .flatten()
+        .filter(|peer_token| **peer_token != token)
+        .count()
+    }
+
+    fn remove(&mut self, token: u32) -> Option<PeerConnectionState> {
+        let state = self.by_token.remove(&token)?;
+        let key = (state.username.clone(), state.conn_type);
+        if let Some(tokens) = self.by_peer.get_mut(&key) {
+            if let Some(idx) = tokens.iter().position(|token| *token == state.token) {
+                tokens.remove(idx);
+            }
+
+            if tokens.is_empty() {
+                self.by_peer.remove(&key);
+            }
+        }
+
+        Some(state)
+    }
 }
 
 impl SenderPool {
-    pub fn new(download_sender_progress_sender: Sender<DownloadProgress>) -> Self {
+    pub fn new(
+        download_sender_progress_sender: Sender<DownloadProgress>,
+        search_sender: Sender<SearchQuery>,
+        distributed_sender: Sender<DistributedEvent>,
+        wishlist_sender: Sender<(u32, SearchReply)>,
+        source_event_sender: Sender<SourceEvent>,
+        limits: ConnectionSettings,
+    ) -> Self {
         SenderPool {
-            ok_connections: Arc::new(Mutex::new(HashMap::default())),
-            pending_connections: Arc::new(Mutex::new(Default::default())),
+            pool: Arc::new(RwLock::new(ConnectionPool::default())),
+            limits,
             download_progress_sender: download_sender_progress_sender,
+            search_sender,
+            distributed_sender,
+            wishlist_sender,
+            source_event_sender,
         }
     }
 }
 
 impl SenderPool {
-    pub(crate) fn find_by_username_and_connection_type(
-        &mut self,
+    /// Most recently used healthy connection with this peer, the connection is marked as used.
+    pub(crate) async fn find_by_username_and_connection_type(
+        &self,
         username: &str,
         conn_type: ConnectionType,
     ) -> Option<PeerConnectionState> {
-        let channels = self.ok_connections.lock().unwrap();
+        let mut pool = self.pool.write().await;
+        let pool = &mut *pool;
 
-        channels
+        let token = pool
+            .by_peer
+            .get(&(username.to_string(), conn_type))?
             .iter()
-            .find(|(_, state)| state.username == username)
-            .filter(|(_, state)| state.conn_type == conn_type)
-            .map(|(_, state)| state.clone())
-    }
+            .filter_map(|token| pool.by_token.get(token))
+            .filter(|state| state.is_healthy())
+            .max_by_key(|state| state.last_used)
+            .map(|state| state.token)?;
 
-    pub fn get_parent_count(&self) -> usize {
-        let channels = self.ok_connections.lock().unwrap();
-        channels
-            .values()
-            .map(|state| state.conn_type)
-            .filter(|conn_type| *conn_type == ConnectionType::DistributedNetwork)
-            .count()
+        let state = pool.by_token.get_mut(&token)?;
+        state.last_used = Instant::now();
+        Some(state.clone())
     }
 
-    pub fn peer_init(
-        &mut self,
+    pub async fn peer_init(
+        &self,
         username: &str,
         conn_type: ConnectionType,
         token: u32,
         sender: Sender<PeerRequestPacket>,
-    ) {
-        let mut connection_states = self.ok_connections.lock().unwrap();
+    ) -> Result<()> {
+        let mut pool = self.pool.write().await;
         let state = PeerConnectionState {
             username: username.to_string(),
             token,
             channel: Some(sender),
             conn_type,
+            last_used: Instant::now(),
         };
         debug!(
             "Inserting connection state on PeerInit received token={}, state={:?}",
             token, &state
         );
-        connection_states.insert(token, state);
+        pool.insert(state, &self.limits)
     }
 
-    pub fn insert_indirect_connection_expected(
-        &mut self,
+    pub async fn insert_indirect_connection_expected(
+        &self,
         username: &str,
         conn_type: ConnectionType,
         token: u32,
     ) {
-        let mut channels = self.pending_connections.lock().unwrap();
+        let mut pool = self.pool.write().await;
 
         let state = PeerConnectionState {
             username: username.to_string(),
             token,
             channel: None,
             conn_type,
+            last_used: Instant::now(),
         };
 
         debug!("Adding PierceFirewall expected state {:?}", state);
-        channels.push(state);
+        pool.pending.push(PendingConnection {
+            state,
+            since: Instant::now(),
+        });
+    }
+
+    /// Returns true if an indirect connection with this peer is waiting for a PierceFirewall.
+    pub async fn is_pending(&self, username: &str, conn_type: ConnectionType) -> bool {
+        let pool = self.pool.read().await;
+        pool.pending.iter().any(|pending| {
+            pending.state.username == username && pending.state.conn_type == conn_type
+        })
     }
 
-    pub fn get(&self, token: u32) -> Option<PeerConnectionState> {
-        let channels = self.ok_connections.lock().unwrap();
-        channels.get(&token).cloned()
+    pub async fn get(&self, token: u32) -> Option<PeerConnectionState> {
+        let pool = self.pool.read().await;
+        pool.by_token.get(&token).cloned()
     }
 
-    pub fn ready(
+    pub async fn ready(
         &self,
         token: u32,
         tx: Sender<PeerRequestPacket>,
     ) -> Result<PeerConnectionState> {
-        let mut pending_connections = self.pending_connections.lock().unwrap();
+        let mut pool = self.pool.write().await;
 
-        let (idx, ready_state) = pending_connections
+        let idx = pool
+            .pending
             .iter()
-            .enumerate()
-            .find(|(_, state)| state.token == token)
+            .position(|pending| pending.state.token == token)
             .ok_or_else(|| eyre!("Pending connection state not found token={}", token))?;
 
-        let mut ready_state = ready_state.clone();
-        ready_state.channel = Some(tx);
-
         // Clean up pending connection
-        let _ = pending_connections.remove(idx);
+        let mut ready_state = pool.pending.remove(idx).state;
+        ready_state.channel = Some(tx);
+        ready_state.last_used = Instant::now();
 
-        let mut ok_connections = self.ok_connections.lock().unwrap();
-        ok_connections.insert(token, ready_state.clone());
+        pool.insert(ready_state.clone(), &self.limits)?;
 
         Ok(ready_state)
     }
 
-    pub fn remove_channel(&mut self, token: u32) -> Result<()> {
-        let mut channels = self.ok_connections.lock().unwrap();
+    /// Remove and return the indirect connections still waiting for a PierceFirewall after
+    /// `max_age`.
+    pub async fn expire_pending_connections(&self, max_age: Duration) -> Vec<PeerConnectionState> {
+        let mut pool = self.pool.write().await;
+        let (expired, pending): (Vec<_>, Vec<_>) = pool
+            .pending
+            .drain(..)
+            .partition(|pending| pending.since.elapsed() >= max_age);
+
+        pool.pending = pending;
+        expired.into_iter().map(|pending| pending.state).collect()
+    }
+
+    /// Forget an indirect connection the peer won't answer.
+    pub async fn remove_pending(&self, token: u32) -> Option<PeerConnectionState> {
+        let mut pool = self.pool.write().await;
+        let idx = pool
+            .pending
+            .iter()
+            .position(|pending| pending.state.token == token)?;
 
-        channels.remove(&token).map(|_| ()).ok_or_else(|| {
+        Some(pool.pending.remove(idx).state)
+    }
+
+    /// Mark a connection as used, idle connections are evicted first.
+    pub async fn touch(&self, token: u32) {
+        let mut pool = self.pool.write().await;
+        if let Some(state) = pool.by_token.get_mut(&token) {
+            state.last_used = Instant::now();
+        }
+    }
+
+    /// Remove the least recently used peer to peer connection from the pool. Its handler stops
+    /// once the request channel is dropped.
+    pub async fn evict_idle_connection(&self) -> Option<PeerConnectionState> {
+        let mut pool = self.pool.write().await;
+        let token = pool
+            .by_token
+            .values()
+            .filter(|state| state.conn_type == ConnectionType::PeerToPeer)
+            .min_by_key(|state| state.last_used)
+            .map(|state| state.token)?;
+
+        pool.remove(token)
+    }
+
+    pub async fn remove_channel(&self, token: u32) -> Result<()> {
+        let mut pool = self.pool.write().await;
+
+        pool.remove(token).map(|_| ()).ok_or_else(|| {
             eyre!(
                 "Failed to drop channel for connection with token {}, channel not found",
                 token
@@ -145,4 +309,164 @@ impl SenderPool {
     pub fn get_progress_sender(&self) -> Sender<DownloadProgress> {
         self.download_progress_sender.clone()
     }
+
+    pub fn get_search_sender(&self) -> Sender<SearchQuery> {
+        self.search_sender.clone()
+    }
+
+    pub fn get_distributed_sender(&self) -> Sender<DistributedEvent> {
+        self.distributed_sender.clone()
+    }
+
+    pub fn get_wishlist_sender(&self) -> Sender<(u32, SearchReply)> {
+        self.wishlist_sender.clone()
+    }
+
+    pub fn get_source_event_sender(&self) -> Sender<SourceEvent> {
+        self.source_event_sender.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use soulseek_protocol::message_common::ConnectionType;
+    use vessel_database::settings::ConnectionSettings;
+
+    use crate::peers::channels::SenderPool;
+
+    fn pool(max_connections_per_peer: usize) -> SenderPool {
+        let (progress_tx, _) = mpsc::channel(1);
+        let (search_tx, _) = mpsc::channel(1);
+        let (distributed_tx, _) = mpsc::channel(1);
+        let (wishlist_tx, _) = mpsc::channel(1);
+        let (source_event_tx, _) = mpsc::channel(1);
+        SenderPool::new(
+            progress_tx,
+            search_tx,
+            distributed_tx,
+            wishlist_tx,
+            source_event_tx,
+            ConnectionSettings {
+                max_connections: 16,
+                max_connections_per_peer,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn should_find_connection_by_username_and_type() {
+        let pool = pool(8);
+        let (tx, _rx) = mpsc::channel(1);
+        pool.peer_init("alice", ConnectionType::DistributedNetwork, 1, tx.clone())
+            .await
+            .unwrap();
+        pool.peer_init("alice", ConnectionType::PeerToPeer, 2, tx)
+            .await
+            .unwrap();
+
+        let state = pool
+            .find_by_username_and_connection_type("alice", ConnectionType::PeerToPeer)
+            .await
+            .unwrap();
+
+        assert_eq!(state.token, 2);
+        assert!(pool
+            .find_by_username_and_connection_type("bob", ConnectionType::PeerToPeer)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_skip_closed_connections() {
+        let pool = pool(8);
+        let (closed_tx, closed_rx) = mpsc::channel(1);
+        let (tx, _rx) = mpsc::channel(1);
+        pool.peer_init("alice", ConnectionType::PeerToPeer, 1, tx)
+            .await
+            .unwrap();
+        pool.peer_init("alice", ConnectionType::PeerToPeer, 2, closed_tx)
+            .await
+            .unwrap();
+        drop(closed_rx);
+
+        let state = pool
+            .find_by_username_and_connection_type("alice", ConnectionType::PeerToPeer)
+            .await
+            .unwrap();
+
+        assert_eq!(state.token, 1);
+    }
+
+    #[tokio::test]
+    async fn should_limit_connections_per_peer() {
+        let pool = pool(1);
+        let (tx, _rx) = mpsc::channel(1);
+        pool.peer_init("alice", ConnectionType::PeerToPeer, 1, tx.clone())
+            .await
+            .unwrap();
+
+        assert!(pool
+            .peer_init("alice", ConnectionType::DistributedNetwork, 2, tx.clone())
+            .await
+            .is_err());
+        // A rejected connection does not replace the existing one
+        assert!(pool
+            .peer_init("alice", ConnectionType::PeerToPeer, 1, tx.clone())
+            .await
+            .is_ok());
+        assert!(pool.get(1).await.is_some());
+
+        // File transfers don't count
+        assert!(pool
+            .peer_init("alice", ConnectionType::FileTransfer, 3, tx.clone())
+            .await
+            .is_ok());
+        assert!(pool
+            .peer_init("bob", ConnectionType::PeerToPeer, 4, tx)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_track_pending_connections() {
+        let pool = pool(8);
+        pool.insert_indirect_connection_expected("alice", ConnectionType::PeerToPeer, 1)
+            .await;
+
+        assert!(pool.is_pending("alice", ConnectionType::PeerToPeer).await);
+        assert!(!pool.is_pending("alice", ConnectionType::FileTransfer).await);
+        assert!(!pool.is_pending("bob", ConnectionType::PeerToPeer).await);
+
+        let (tx, _rx) = mpsc::channel(1);
+        pool.ready(1, tx).await.unwrap();
+        assert!(!pool.is_pending("alice", ConnectionType::PeerToPeer).await);
+    }
+
+    #[tokio::test]
+    async fn should_evict_least_recently_used_peer_connection() {
+        let pool = pool(8);
+        let (tx, _rx) = mpsc::channel(1);
+        pool.peer_init("alice", ConnectionType::PeerToPeer, 1, tx.clone())
+            .await
+            .unwrap();
+        pool.peer_init("bob", ConnectionType::PeerToPeer, 2, tx.clone())
+            .await
+            .unwrap();
+        pool.peer_init("carol", ConnectionType::DistributedNetwork, 3, tx)
+            .await
+            .unwrap();
+        pool.touch(1).await;
+
+        let evicted = pool.evict_idle_connection().await.unwrap();
+
+        assert_eq!(evicted.username, "bob");
+        assert!(pool.get(2).await.is_none());
+        assert_eq!(
+            pool.evict_idle_connection().await.unwrap().username,
+            "alice"
+        );
+        assert!(pool.evict_idle_connection().await.is_none());
+    }
 }
@@ -1,16 +1,13 @@
-use std::{
-    io::Cursor,
-    net::SocketAddr,
-    path::{Path, PathBuf},
-};
+use std::{fmt::Debug, io::Cursor, net::SocketAddr, path::Path};
 
 use bytes::{Buf, BytesMut};
 use eyre::Result;
 use tokio::{
     fs::OpenOptions,
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
     net::TcpStream,
     sync::mpsc::Sender,
+    time::{timeout, Duration},
 };
 
 use soulseek_protocol::{
@@ -19,22 +16,37 @@ use soulseek_protocol::{
     peers::{p2p::download::DownloadProgress, PeerRequestPacket},
     ProtocolHeader, ProtocolMessage, SlskError,
 };
-use vessel_database::entity::download::DownloadEntity;
+use vessel_database::entity::download::{DownloadEntity, FolderDownloadEntity};
 use vessel_database::Database;
 
+/// A bidirectional byte stream to a peer, a [`TcpStream`] outside of tests.
+pub(crate) trait PeerStream: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug> PeerStream for T {}
+
 #[derive(Debug)]
 pub struct PeerConnection {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Box<dyn PeerStream>>,
     buffer: BytesMut,
+    address: Option<SocketAddr>,
     pub(crate) connection_type: ConnectionType,
     pub(crate) token: Option<u32>,
 }
 
 impl PeerConnection {
     pub(crate) fn new(socket: TcpStream) -> PeerConnection {
+        let address = socket.peer_addr().ok();
+        PeerConnection::with_stream(socket, address)
+    }
+
+    pub(crate) fn with_stream(
+        stream: impl PeerStream + 'static,
+        address: Option<SocketAddr>,
+    ) -> PeerConnection {
         PeerConnection {
-            stream: BufWriter::new(socket),
+            stream: BufWriter::new(Box::new(stream)),
             buffer: BytesMut::with_capacity(4 * 1024),
+            address,
             connection_type: ConnectionType::HandShake,
             token: None,
         }
@@ -58,12 +70,9 @@ impl PeerConnection {
                 Err(e) => return Err(e),
             }
 
+            // The peer closed the connection, whether or not it left a partial message behind
             if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                if self.stream.buffer().is_empty() {
-                    // No message
-                } else {
-                    return Err(SlskError::ConnectionResetByPeer);
-                }
+                return Err(SlskError::ConnectionResetByPeer);
             }
         }
     }
@@ -88,7 +97,13 @@ impl PeerConnection {
                     self.token, message
                 );
             }
-            _ => unreachable!(),
+            PeerRequestPacket::DistributedMessage(message) => {
+                message.write_to_buf(&mut self.stream).await?;
+                debug!(
+                    "[token={:?}] - Distributed message sent to peer {:?}",
+                    self.token, message
+                );
+            }
         }
 
         self.stream.flush().await
@@ -128,10 +143,8 @@ impl PeerConnection {
     }
 
     pub fn get_peer_address_with_port(&self) -> Result<SocketAddr, std::io::Error> {
-        match &self.stream.get_ref().peer_addr() {
-            Ok(address) => Ok(address.to_owned()),
-            Err(err) => Err(std::io::Error::from(err.kind())),
-        }
+        self.address
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected))
     }
 
     pub(crate) async fn download(
@@ -139,12 +152,16 @@ impl PeerConnection {
         db: &Database,
         progress_sender: Sender<DownloadProgress>,
         user_name: String,
+        download_folder: &Path,
+        stall_timeout: Duration,
     ) -> Result<()> {
         let address = self.get_peer_address_with_port()?.to_string();
 
         // We need to parse the ticket from the upload connection
-        if self.buffer.remaining() < 4 && self.try_read_buffer().await? == 0 {
-            return Err(eyre!("Empty buffer on download init"));
+        while self.buffer.remaining() < 4 {
+            if self.try_read_buffer(stall_timeout).await? == 0 {
+                return Err(eyre!("Empty buffer on download init"));
+            }
         }
 
         let mut cursor = Cursor::new(&mut self.buffer);
@@ -157,10 +174,36 @@ impl PeerConnection {
             let file_name = &entry.file_name;
             // Fixme : replace path before download
             let file_name = file_name.replace("\\", "/");
-            let file_path = Path::new(&file_name).file_name().expect("File name error");
-            let mut download_path =
-                PathBuf::from(&vessel_database::settings::CONFIG.download_folder);
-            download_path.push(file_path);
+            let file_path = Path::new(&file_name)
+                .file_name()
+                .ok_or_else(|| eyre!("Invalid file name {}", file_name))?;
+            let mut download_path = download_folder.to_path_buf();
+
+            // Files downloaded as part of a folder keep the folder structure
+            let folder_download = entry.folder_ticket.and_then(|folder_ticket| {
+                db.get_by_key::<FolderDownloadEntity>(&FolderDownloadEntity::key_from(
+                    &user_name,
+                    folder_ticket,
+                ))
+            });
+
+            let folder_file_path = folder_download.as_ref().and_then(|folder_download| {
+                folder_download
+                    .files
+                    .iter()
+                    .find(|file| file.ticket == Some(ticket))
+                    .and_then(|file| folder_download.local_path(&file.file_name))
+            });
+
+            match folder_file_path {
+                Some(path) => {
+                    download_path.push(path);
+                    if let Some(parent) = download_path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                }
+                None => download_path.push(file_path),
+            }
 
             progress_sender
                 .send(DownloadProgress::Init {
@@ -185,14 +228,14 @@ impl PeerConnection {
                 "[token={:?}] - Starting to download {}",
                 self.token, file_name
             );
-            let mut progress = 0;
+            let mut progress: usize = 0;
             let mut percent_progress = 0;
             let file_size = entry.file_size as usize;
 
             loop {
                 let byte_red = file.write(self.buffer.chunk()).await?;
 
-                let percent = 100 * progress / file_size;
+                let percent = (100 * progress).checked_div(file_size).unwrap_or(100);
 
                 // Avoid to reprint percent every time the task yield
                 if percent > percent_progress {
@@ -201,6 +244,18 @@ impl PeerConnection {
                         .send(DownloadProgress::Progress { ticket, percent })
                         .await?;
                     debug!("{}% of {}", percent, file_name);
+
+                    if let Some(folder_ticket) = entry.folder_ticket {
+                        update_folder_progress(
+                            db,
+                            &progress_sender,
+                            &entry.user,
+                            folder_ticket,
+                            ticket,
+                            progress as u64,
+                        )
+                        .await?;
+                    }
                 }
 
                 self.buffer.advance(byte_red as usize);
@@ -208,11 +263,22 @@ impl PeerConnection {
 
                 if progress >= file_size {
                     debug!("100% of {}", file_name);
+                    if let Some(folder_ticket) = entry.folder_ticket {
+                        update_folder_progress(
+                            db,
+                            &progress_sender,
+                            &entry.user,
+                            folder_ticket,
+                            ticket,
+                            progress as u64,
+                        )
+                        .await?;
+                    }
                     file.sync_data().await?;
                     return Ok(());
                 }
 
-                if 0 == self.try_read_buffer().await? {
+                if 0 == self.try_read_buffer(stall_timeout).await? {
                     info!("Download finished for {}", file_name);
                     file.sync_data().await?;
                     return Ok(());
@@ -223,8 +289,10 @@ impl PeerConnection {
         }
     }
 
-    async fn try_read_buffer(&mut self) -> Result<usize> {
-        let bytes_red = self.stream.read_buf(&mut self.buffer).await?;
+    async fn try_read_buffer(&mut self, stall_timeout: Duration) -> Result<usize> {
+        let bytes_red = timeout(stall_timeout, self.stream.read_buf(&mut self.buffer))
+            .await
+            .map_err(|_| eyre!("Transfer stalled for {:?}", stall_timeout))??;
         if 0 == bytes_red {
             if self.buffer.is_empty() {
                 Ok(bytes_red)
@@ -237,6 +305,40 @@ impl PeerConnection {
     }
 }
 
+/// Record the progress of a file downloaded as part of a folder and advertise the progress of the
+/// whole folder.
+async fn update_folder_progress(
+    db: &Database,
+    progress_sender: &Sender<DownloadProgress>,
+    user_name: &str,
+    folder_ticket: u32,
+    ticket: u32,
+    progress: u64,
+) -> Result<()> {
+    let key = FolderDownloadEntity::key_from(user_name, folder_ticket);
+    let mut folder_download = match db.get_by_key::<FolderDownloadEntity>(&key) {
+        Some(folder_download) => folder_download,
+        None => return Ok(()),
+    };
+
+    if let Some(file) = folder_download.file_by_ticket(ticket) {
+        file.progress = progress;
+    }
+
+    db.insert(&folder_download)?;
+
+    progress_sender
+        .send(DownloadProgress::FolderProgress {
+            user_name: folder_download.user.clone(),
+            percent: folder_download.percent(),
+            folder: folder_download.folder,
+            ticket: folder_ticket,
+        })
+        .await?;
+
+    Ok(())
+}
+
 impl Drop for PeerConnection {
     fn drop(&mut self) {
         debug!("[token={:?}] - Dropping peer connection", self.token)
@@ -1,20 +1,39 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
-use eyre::Result;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::{
+    sync::mpsc::{error::SendError, Receiver, Sender},
+    time,
+};
 
 use soulseek_protocol::{
     message_common::ConnectionType,
-    peers::{p2p::response::PeerResponse, PeerRequestPacket},
-    server::{peer::PeerAddress, request::ServerRequest},
+    peers::{
+        p2p::{request::PeerRequest, response::PeerResponse, transfer::QueueUpload},
+        PeerRequestPacket, UndeliveredRequest,
+    },
+    server::{
+        peer::{PeerAddress, PeerConnectionTicket},
+        request::ServerRequest,
+        response::ServerResponse,
+    },
 };
 use vessel_database::entity::peer::PeerEntity;
+use vessel_database::settings::CONFIG;
 use vessel_database::Database;
 
 use crate::peers::{
     channels::SenderPool,
     listener::{connect_to_peer_with_fallback, ShutdownHelper},
+    queue::{OutboundQueue, QueuedRequest},
 };
+use crate::search::download::SourceEvent;
+
+/// How often indirect connections still waiting for a PierceFirewall and queued requests are
+/// checked for expiration.
+const EXPIRATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct Dispatcher {
     // Receive connection state updates from peer handler
@@ -25,6 +44,8 @@ pub struct Dispatcher {
     // Once the server receive a peer address response it send it back
     // So we can start to pop message from queue
     pub(crate) peer_address_rx: Receiver<PeerAddress>,
+    // The server could not connect us to a peer
+    pub(crate) cant_connect_rx: Receiver<PeerConnectionTicket>,
 
     // Hold peer channels and connection type
     pub(crate) channels: SenderPool,
@@ -35,13 +56,21 @@ pub struct Dispatcher {
     pub(crate) sse_tx: Sender<PeerResponse>,
     pub(crate) ready_tx: Sender<u32>,
     pub(crate) server_request_tx: Sender<ServerRequest>,
+    // Notify SSE clients about connections that could not be established
+    pub(crate) sse_server_tx: Sender<ServerResponse>,
+    // Report requests we could not deliver to SSE clients
+    pub(crate) undelivered_tx: Sender<UndeliveredRequest>,
 
     // Save message sent to peer if the connection is not ready yet
-    pub(crate) message_queue: HashMap<String, Vec<PeerRequestPacket>>,
+    pub(crate) message_queue: OutboundQueue,
+    // Connections we started dialing, until their handler is ready or they failed
+    pub(crate) dialing: HashMap<(String, ConnectionType), Instant>,
 }
 
 impl Dispatcher {
     pub async fn run(&mut self) {
+        let mut expiration_check = time::interval(EXPIRATION_CHECK_INTERVAL);
+
         loop {
             tokio::select! {
                 request = self.queue_rx.recv() => {
@@ -51,7 +80,7 @@ impl Dispatcher {
                 }
                 token = self.ready_rx.recv() => {
                     if let Some(token) = token {
-                        self.on_peer_ready(token).await.unwrap();
+                        self.on_peer_ready(token).await;
                     }
                 }
                 peer = self.peer_address_rx.recv() => {
@@ -61,80 +90,298 @@ impl Dispatcher {
                     }
 
                 }
+                ticket = self.cant_connect_rx.recv() => {
+                    if let Some(ticket) = ticket {
+                        self.on_cant_connect(ticket).await;
+                    }
+                }
+                _ = expiration_check.tick() => {
+                    self.on_pending_connections_expired().await;
+                    self.on_queued_requests_expired().await;
+                    self.on_dials_expired();
+                }
             }
         }
     }
 
+    // Peers that never answered our indirect connection request won't receive their queued
+    // messages
+    async fn on_pending_connections_expired(&mut self) {
+        let expired = self
+            .channels
+            .expire_pending_connections(CONFIG.timeouts.pending_connection())
+            .await;
+
+        for state in expired {
+            warn!(
+                "No PierceFirewall received from {} for token {}, giving up",
+                state.username, state.token
+            );
+
+            self.dialing
+                .remove(&(state.username.clone(), state.conn_type));
+
+            self.fail_queued_requests(&state.username, state.conn_type, "Connection timed out")
+                .await;
+
+            let ticket = PeerConnectionTicket {
+                token: state.token,
+                username: state.username,
+            };
+
+            if let Err(err) = self
+                .sse_server_tx
+                .send(ServerResponse::CantConnectToPeer(ticket))
+                .await
+            {
+                error!("Error sending connection timeout to SSE clients : {}", err);
+            }
+        }
+    }
+
+    // A dial whose handler never reported ready must not prevent dialing again
+    fn on_dials_expired(&mut self) {
+        let max_age = CONFIG.timeouts.pending_connection();
+        self.dialing.retain(|_, since| since.elapsed() < max_age);
+    }
+
+    async fn on_queued_requests_expired(&mut self) {
+        let expired = self.message_queue.expire(CONFIG.timeouts.queued_request());
+
+        for (username, request) in expired {
+            self.report_undelivered(username, "Request expired", request)
+                .await;
+        }
+    }
+
+    // The server could not connect us to this peer either
+    async fn on_cant_connect(&mut self, ticket: PeerConnectionTicket) {
+        let conn_types = match self.channels.remove_pending(ticket.token).await {
+            Some(state) => vec![state.conn_type],
+            None => self.message_queue.connection_types(&ticket.username),
+        };
+
+        for conn_type in conn_types {
+            self.dialing.remove(&(ticket.username.clone(), conn_type));
+            self.fail_queued_requests(&ticket.username, conn_type, "Peer unreachable")
+                .await;
+        }
+
+        if let Err(err) = self
+            .sse_server_tx
+            .send(ServerResponse::CantConnectToPeer(ticket))
+            .await
+        {
+            error!("Error sending connection failure to SSE clients : {}", err);
+        }
+    }
+
     async fn on_peer_address_received(&mut self, peer: PeerEntity) {
+        // Don't dial offline peers, their queued requests can't be delivered
+        if peer.is_offline() {
+            info!("Peer {} is offline", peer.username);
+            if let Err(err) = self.db.remove(&peer) {
+                error!(
+                    "Failed to remove {} address from the database : {}",
+                    peer.username, err
+                );
+            }
+
+            for conn_type in self.message_queue.connection_types(&peer.username) {
+                self.fail_queued_requests(&peer.username, conn_type, "Peer offline")
+                    .await;
+            }
+
+            return;
+        }
+
         self.db.insert(&peer).unwrap();
-        if let Some(queue) = self.message_queue.get(&peer.username) {
-            if let Some(msg) = queue.last() {
-                let conn_type = ConnectionType::from(msg);
-                self.initiate_connection(conn_type, peer).await;
+
+        for conn_type in self.message_queue.connection_types(&peer.username) {
+            if !self
+                .send_to_existing_connection(&peer.username, conn_type)
+                .await
+                && !self.is_connecting(&peer.username, conn_type).await
+            {
+                self.initiate_connection(conn_type, peer.clone()).await;
             }
         }
     }
 
-    async fn on_peer_ready(&mut self, token: u32) -> Result<()> {
-        let connection_state = self.channels.get(token);
+    async fn on_peer_ready(&mut self, token: u32) {
+        let connection_state = self.channels.get(token).await;
 
         if let Some(connection_state) = connection_state {
             debug!(
                 "Got peer ready with connection state : {:?}",
                 connection_state
             );
+
             let username = connection_state.username;
+            let conn_type = connection_state.conn_type;
             let sender = connection_state.channel.expect("Channel should be known");
+            self.dialing.remove(&(username.clone(), conn_type));
 
-            if let Some(queue) = self.message_queue.get_mut(&username) {
+            while let Some(queued) = self.message_queue.pop(&username, conn_type) {
                 debug!(
-                    "Sending queued messages {:?}  peer={:?}, token={}",
-                    queue, username, token
+                    "Sending queued message {:?}  peer={:?}, token={}",
+                    queued.request, username, token
                 );
-                while let Some(msg) = queue.pop() {
-                    sender.send(msg).await?;
+
+                if let Err(SendError(request)) = sender.send(queued.request).await {
+                    warn!(
+                        "Connection with {} closed before flushing its queue",
+                        username
+                    );
+                    self.message_queue.push_front(
+                        &username,
+                        conn_type,
+                        QueuedRequest { request, ..queued },
+                    );
+                    break;
                 }
             }
         }
-
-        Ok(())
     }
 
     async fn on_peer_request(&mut self, username: &str, request: PeerRequestPacket) {
-        let conn_type = ConnectionType::from(&request);
-        self.push_to_queue(username.to_string(), request).await;
-        let peer_conn_state = self
+        info!(
+            "Pushing peer request from {} to message queue : {:?}",
+            username, request
+        );
+        let conn_type = self.message_queue.push(username, request);
+
+        // Connection is established already, we can send the message right away
+        if self.send_to_existing_connection(username, conn_type).await {
+            return;
+        }
+
+        // The request is sent once the connection on its way is ready
+        if self.is_connecting(username, conn_type).await {
+            debug!("Connection with {} is on its way", username);
+            return;
+        }
+
+        // Peers on dynamic IPs move, stale addresses are requested again
+        let peer = self
+            .db
+            .get_by_key::<PeerEntity>(username)
+            .filter(|peer| !peer.is_expired(CONFIG.timeouts.peer_address()));
+
+        match peer {
+            Some(peer) => self.initiate_connection(conn_type, peer).await,
+            None => self
+                .server_request_tx
+                .send(ServerRequest::GetPeerAddress(username.to_string()))
+                .await
+                .expect("Server send error"),
+        }
+    }
+
+    // Send queued messages in order through a healthy connection with this peer if there is one.
+    // Returns false when a new connection is needed.
+    async fn send_to_existing_connection(
+        &mut self,
+        username: &str,
+        conn_type: ConnectionType,
+    ) -> bool {
+        while let Some(state) = self
             .channels
-            .find_by_username_and_connection_type(username, conn_type);
-
-        match peer_conn_state {
-            // Connection is established already, we can send the message right away
-            Some(state) => {
-                if let Some(queue) = self.message_queue.get_mut(username) {
-                    let request = queue.pop().unwrap();
-                    state
-                        .channel
-                        .expect("Peer channel should be known at this point")
-                        .send(request)
-                        .await
-                        .expect("Send error");
+            .find_by_username_and_connection_type(username, conn_type)
+            .await
+        {
+            let channel = state
+                .channel
+                .expect("Peer channel should be known at this point");
+
+            loop {
+                let queued = match self.message_queue.pop(username, conn_type) {
+                    Some(queued) => queued,
+                    None => return true,
+                };
+
+                if let Err(SendError(request)) = channel.send(queued.request).await {
+                    // The handler stopped in the meantime, try another connection
+                    debug!("Connection with {} closed, token={}", username, state.token);
+                    let _ = self.channels.remove_channel(state.token).await;
+                    self.message_queue.push_front(
+                        username,
+                        conn_type,
+                        QueuedRequest { request, ..queued },
+                    );
+                    break;
                 }
             }
-            None => match self.db.get_by_key::<PeerEntity>(username) {
-                Some(peer) => {
-                    self.initiate_connection(ConnectionType::PeerToPeer, peer)
-                        .await
-                }
-                None => self
-                    .server_request_tx
-                    .send(ServerRequest::GetPeerAddress(username.to_string()))
-                    .await
-                    .expect("Server send error"),
-            },
+        }
+
+        false
+    }
+
+    // Either dialed directly and not ready yet, or waiting for the peer PierceFirewall
+    async fn is_connecting(&self, username: &str, conn_type: ConnectionType) -> bool {
+        self.dialing
+            .contains_key(&(username.to_string(), conn_type))
+            || self.channels.is_pending(username, conn_type).await
+    }
+
+    async fn fail_queued_requests(
+        &mut self,
+        username: &str,
+        conn_type: ConnectionType,
+        reason: &str,
+    ) {
+        // Another connection with this peer may still deliver them
+        if self
+            .channels
+            .find_by_username_and_connection_type(username, conn_type)
+            .await
+            .is_some()
+        {
+            return;
+        }
+
+        for request in self.message_queue.remove(username, conn_type) {
+            self.report_undelivered(username.to_string(), reason, request)
+                .await;
+        }
+    }
+
+    async fn report_undelivered(&self, username: String, reason: &str, request: PeerRequestPacket) {
+        warn!(
+            "Dropping request to {}, reason : {}, request : {:?}",
+            username, reason, request
+        );
+
+        // Download jobs move on to their next source
+        if let PeerRequestPacket::Message(PeerRequest::QueueUpload(QueueUpload { file_name })) =
+            &request
+        {
+            let event = SourceEvent::Failed {
+                username: username.clone(),
+                file_name: file_name.clone(),
+                reason: reason.to_string(),
+            };
+
+            if let Err(err) = self.channels.get_source_event_sender().send(event).await {
+                error!("Error sending download source event : {}", err);
+            }
+        }
+
+        let undelivered = UndeliveredRequest {
+            username,
+            reason: reason.to_string(),
+            request,
+        };
+
+        if let Err(err) = self.undelivered_tx.send(undelivered).await {
+            error!("Error sending undelivered request to SSE clients : {}", err);
         }
     }
 
     async fn initiate_connection(&mut self, conn_type: ConnectionType, peer: PeerEntity) {
+        self.dialing
+            .insert((peer.username.clone(), conn_type), Instant::now());
+
         let sender = self.server_request_tx.clone();
         let sse_tx = self.sse_tx.clone();
         let channels = self.channels.clone();
@@ -149,19 +396,7 @@ impl Dispatcher {
 
         if let Err(err) = connection_result {
             error!("An errored occurred during connection : {:?}", err);
-        }
-    }
-
-    async fn push_to_queue(&mut self, username: String, request: PeerRequestPacket) {
-        info!(
-            "Pushing peer request from {} to queue to message queue : {:?}",
-            username, request
-        );
-        match self.message_queue.get_mut(&username) {
-            Some(queue) => queue.push(request),
-            None => {
-                self.message_queue.insert(username, vec![request]);
-            }
+            self.dialing.remove(&(peer.username, conn_type));
         }
     }
 }
@@ -0,0 +1,748 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time,
+};
+
+use soulseek_protocol::{
+    message_common::ConnectionType,
+    peers::{
+        distributed::{search::SearchRequest, DistributedMessage},
+        p2p::response::PeerResponse,
+        PeerRequestPacket,
+    },
+    server::{
+        distributed::EmbeddedDistributedMessage, peer::Peer, request::ServerRequest,
+        response::ServerResponse,
+    },
+};
+use vessel_database::{entity::peer::PeerEntity, settings::CONFIG, Database};
+
+use crate::peers::{
+    channels::SenderPool,
+    listener::{connect_to_peer_with_fallback, ShutdownHelper},
+};
+
+/// Parent candidates are given this delay to connect and report their branch before we pick one.
+const PARENT_SELECTION_DELAY: Duration = Duration::from_secs(10);
+
+/// How often we ask the server for our own upload speed, it decides if we can accept children.
+const OWN_STATS_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Number of forwarded searches remembered to drop duplicates.
+const RECENT_SEARCHES_CAPACITY: usize = 1024;
+
+/// Distributed connection events reported by the peer handlers.
+#[derive(Debug)]
+pub enum DistributedEvent {
+    Connected {
+        username: String,
+        token: u32,
+    },
+    BranchLevel {
+        username: String,
+        token: u32,
+        level: u32,
+    },
+    BranchRoot {
+        username: String,
+        token: u32,
+        root: String,
+    },
+    ChildDepth {
+        token: u32,
+        depth: u32,
+    },
+    Search {
+        username: String,
+        token: u32,
+        request: SearchRequest,
+    },
+    Closed {
+        username: String,
+        token: u32,
+    },
+}
+
+/// Upload speed requirements sent by the server for distributed parents.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ParentSpeed {
+    min_speed: u32,
+    ratio: u32,
+}
+
+impl ParentSpeed {
+    /// Number of children a peer uploading at `speed` can take, none below the minimum speed.
+    pub(crate) fn max_children(&self, speed: u32) -> u32 {
+        if speed < self.min_speed {
+            0
+        } else {
+            speed.checked_div(self.ratio).unwrap_or(u32::MAX)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Candidate {
+    dialed_at: Instant,
+    token: Option<u32>,
+    level: Option<u32>,
+    root: Option<String>,
+    // Time between our connection attempt and the candidate first BranchLevel
+    latency: Option<Duration>,
+    speed: Option<u32>,
+}
+
+impl Candidate {
+    fn new() -> Self {
+        Candidate {
+            dialed_at: Instant::now(),
+            token: None,
+            level: None,
+            root: None,
+            latency: None,
+            speed: None,
+        }
+    }
+
+    fn is_measured(&self) -> bool {
+        self.level.is_some() && self.speed.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct Parent {
+    username: String,
+    token: u32,
+}
+
+#[derive(Debug)]
+struct Child {
+    username: String,
+    depth: u32,
+}
+
+/// Searches recently forwarded to our children, the same search can reach us more than once.
+#[derive(Debug, Default)]
+struct RecentSearches {
+    order: VecDeque<(String, u32)>,
+    seen: HashSet<(String, u32)>,
+}
+
+impl RecentSearches {
+    /// Returns false if this search was already seen.
+    fn insert(&mut self, username: &str, ticket: u32) -> bool {
+        let search = (username.to_string(), ticket);
+        if !self.seen.insert(search.clone()) {
+            return false;
+        }
+
+        self.order.push_back(search);
+        if self.order.len() > RECENT_SEARCHES_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        true
+    }
+}
+
+/// Our position in the distributed network, we keep a single parent at a time and forward its
+/// searches to our children.
+#[derive(Debug, Default)]
+pub(crate) struct Branch {
+    level: u32,
+    root: String,
+    parent: Option<Parent>,
+    candidates: HashMap<String, Candidate>,
+    selection_deadline: Option<Instant>,
+    parent_speed: ParentSpeed,
+    own_speed: Option<u32>,
+    max_children: u32,
+    // Children by connection token
+    children: HashMap<u32, Child>,
+    child_depth: u32,
+    accept_children: Option<bool>,
+    recent_searches: RecentSearches,
+}
+
+impl Branch {
+    pub(crate) fn new(max_children: u32) -> Self {
+        Branch {
+            max_children,
+            ..Branch::default()
+        }
+    }
+
+    /// The fastest candidate among those who answered and meet the server speed requirements,
+    /// the quickest to answer wins when speeds are equal.
+    fn best_candidate(&self) -> Option<String> {
+        self.candidates
+            .iter()
+            .filter(|(_, candidate)| candidate.token.is_some() && candidate.level.is_some())
+            .filter(|(_, candidate)| {
+                candidate
+                    .speed
+                    .is_none_or(|speed| self.parent_speed.max_children(speed) > 0)
+            })
+            .max_by_key(|(_, candidate)| (candidate.speed, Reverse(candidate.latency)))
+            .map(|(username, _)| username.clone())
+    }
+
+    /// Our configured limit, lowered by the server speed requirements. We don't take children
+    /// until we know our own upload speed.
+    fn children_limit(&self) -> u32 {
+        self.own_speed
+            .map(|speed| self.parent_speed.max_children(speed))
+            .map_or(0, |limit| limit.min(self.max_children))
+    }
+
+    fn accepts_children(&self) -> bool {
+        (self.children.len() as u32) < self.children_limit()
+    }
+
+    /// Number of generations below us.
+    fn depth(&self) -> u32 {
+        self.children
+            .values()
+            .map(|child| child.depth + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn is_parent(&self, username: &str, token: u32) -> bool {
+        self.parent
+            .as_ref()
+            .is_some_and(|parent| parent.username == username && parent.token == token)
+    }
+}
+
+pub(crate) struct DistributedNetwork {
+    // Possible parents, parent speed requirements, user stats and embedded searches from the
+    // server
+    pub(crate) server_rx: Receiver<ServerResponse>,
+    // Branch updates from our distributed connections
+    pub(crate) events_rx: Receiver<DistributedEvent>,
+
+    pub(crate) server_request_tx: Sender<ServerRequest>,
+    pub(crate) sse_tx: Sender<PeerResponse>,
+    pub(crate) ready_tx: Sender<u32>,
+    pub(crate) channels: SenderPool,
+    pub(crate) shutdown_helper: ShutdownHelper,
+    pub(crate) db: Database,
+
+    pub(crate) branch: Branch,
+}
+
+impl DistributedNetwork {
+    pub async fn run(&mut self) {
+        let mut selection_check = time::interval(Duration::from_secs(1));
+        let mut own_stats_check = time::interval(OWN_STATS_INTERVAL);
+
+        // We are our own branch root until we find a parent
+        self.report_branch(0, CONFIG.username.clone()).await;
+        self.update_accept_children().await;
+
+        loop {
+            tokio::select! {
+                response = self.server_rx.recv() => {
+                    if let Some(response) = response {
+                        self.on_server_response(response).await;
+                    }
+                }
+                event = self.events_rx.recv() => {
+                    if let Some(event) = event {
+                        self.on_event(event).await;
+                    }
+                }
+                _ = selection_check.tick() => {
+                    let deadline_reached = self
+                        .branch
+                        .selection_deadline
+                        .is_some_and(|deadline| deadline <= Instant::now());
+
+                    if deadline_reached {
+                        self.select_parent().await;
+                    }
+                }
+                _ = own_stats_check.tick() => {
+                    self.send_server_request(ServerRequest::GetUserStats(CONFIG.username.clone()))
+                        .await;
+                }
+            }
+        }
+    }
+
+    async fn on_server_response(&mut self, response: ServerResponse) {
+        match response {
+            ServerResponse::PossibleParents(parents) => self.on_possible_parents(parents).await,
+            ServerResponse::ParentMinSpeed(speed) => {
+                self.branch.parent_speed.min_speed = speed;
+                self.update_accept_children().await;
+            }
+            ServerResponse::ParentSpeedRatio(ratio) => {
+                self.branch.parent_speed.ratio = ratio;
+                self.update_accept_children().await;
+            }
+            ServerResponse::UserStats(stats) if stats.username == CONFIG.username => {
+                self.branch.own_speed = Some(stats.average_speed);
+                self.update_accept_children().await;
+            }
+            ServerResponse::UserStats(stats) => {
+                if let Some(candidate) = self.branch.candidates.get_mut(&stats.username) {
+                    candidate.speed = Some(stats.average_speed);
+                    self.select_parent_if_measured().await;
+                }
+            }
+            ServerResponse::EmbeddedMessage(message) => self.on_embedded_message(message).await,
+            response => debug!("Ignoring server response {:?}", response),
+        }
+    }
+
+    // Connect to every possible parent, the best one is picked once they are measured
+    async fn on_possible_parents(&mut self, parents: Vec<Peer>) {
+        if self.branch.parent.is_some() {
+            debug!("Already connected to a distributed parent, ignoring possible parents");
+            return;
+        }
+
+        for parent in parents {
+            if parent.username == CONFIG.username
+                || self.branch.candidates.contains_key(&parent.username)
+            {
+                continue;
+            }
+
+            let parent = PeerEntity::from(parent);
+            self.branch
+                .candidates
+                .insert(parent.username.clone(), Candidate::new());
+
+            self.send_server_request(ServerRequest::GetUserStats(parent.username.clone()))
+                .await;
+            self.connect(parent);
+        }
+
+        self.branch
+            .selection_deadline
+            .get_or_insert_with(|| Instant::now() + PARENT_SELECTION_DELAY);
+    }
+
+    // The server sends searches directly to branch roots
+    async fn on_embedded_message(&mut self, message: EmbeddedDistributedMessage) {
+        if self.branch.parent.is_some() {
+            debug!("Ignoring embedded message, we are not a branch root");
+            return;
+        }
+
+        match message.distributed_message() {
+            Ok(DistributedMessage::SearchRequest(request)) => {
+                let query = request.clone().into();
+                if let Err(err) = self.channels.get_search_sender().send(query).await {
+                    error!("Error dispatching embedded search request : {}", err);
+                }
+
+                self.forward_search(request).await;
+            }
+            Ok(message) => debug!("Ignoring embedded message {:?}", message),
+            Err(err) => warn!("Invalid embedded message : {}", err),
+        }
+    }
+
+    async fn on_event(&mut self, event: DistributedEvent) {
+        match event {
+            DistributedEvent::Connected { username, token } => {
+                // Candidates and our parent report their branch, anyone else is a child
+                if self.branch.candidates.contains_key(&username)
+                    || self.branch.is_parent(&username, token)
+                {
+                    return;
+                }
+
+                self.on_child_connected(username, token).await;
+            }
+            DistributedEvent::BranchLevel {
+                username,
+                token,
+                level,
+            } => {
+                if self.branch.is_parent(&username, token) {
+                    // A parent at level 0 is the root of our branch
+                    let root = if level == 0 {
+                        username
+                    } else {
+                        self.branch.root.clone()
+                    };
+
+                    self.report_branch(level + 1, root).await;
+                } else if let Some(candidate) = self.branch.candidates.get_mut(&username) {
+                    candidate.token = Some(token);
+                    candidate.level = Some(level);
+                    candidate
+                        .latency
+                        .get_or_insert(candidate.dialed_at.elapsed());
+                    self.select_parent_if_measured().await;
+                } else {
+                    // A candidate answering after we picked our parent
+                    self.close(&username, token).await;
+                    if self.branch.children.remove(&token).is_some() {
+                        self.update_children().await;
+                    }
+                }
+            }
+            DistributedEvent::BranchRoot {
+                username,
+                token,
+                root,
+            } => {
+                if self.branch.is_parent(&username, token) {
+                    self.report_branch(self.branch.level, root).await;
+                } else if let Some(candidate) = self.branch.candidates.get_mut(&username) {
+                    candidate.root = Some(root);
+                }
+            }
+            DistributedEvent::ChildDepth { token, depth } => {
+                if let Some(child) = self.branch.children.get_mut(&token) {
+                    debug!("Distributed child {} depth is {}", child.username, depth);
+                    child.depth = depth;
+                    self.update_child_depth().await;
+                }
+            }
+            DistributedEvent::Search {
+                username,
+                token,
+                request,
+            } => {
+                if self.branch.is_parent(&username, token) {
+                    self.forward_search(request).await;
+                }
+            }
+            DistributedEvent::Closed { username, token } => {
+                if self.branch.is_parent(&username, token) {
+                    info!(
+                        "Lost distributed parent {}, looking for a new one",
+                        username
+                    );
+                    self.branch.parent = None;
+                    self.report_branch(0, CONFIG.username.clone()).await;
+                    self.send_server_request(ServerRequest::NoParents(true))
+                        .await;
+                } else if self
+                    .branch
+                    .candidates
+                    .get(&username)
+                    .is_some_and(|candidate| candidate.token == Some(token))
+                {
+                    self.branch.candidates.remove(&username);
+                } else if self.branch.children.remove(&token).is_some() {
+                    info!("Distributed child {} disconnected", username);
+                    self.update_children().await;
+                }
+            }
+        }
+    }
+
+    async fn on_child_connected(&mut self, username: String, token: u32) {
+        if !self.branch.accepts_children() {
+            info!("Refusing distributed child {}, no slot available", username);
+            self.close(&username, token).await;
+            return;
+        }
+
+        info!("Accepted distributed child {}", username);
+        self.branch
+            .children
+            .insert(token, Child { username, depth: 0 });
+
+        let level = DistributedMessage::BranchLevel(self.branch.level);
+        let root = DistributedMessage::BranchRoot(self.branch.root.clone());
+        self.send_to(token, level).await;
+        self.send_to(token, root).await;
+        self.update_children().await;
+    }
+
+    // No need to wait for the selection deadline once every candidate was measured
+    async fn select_parent_if_measured(&mut self) {
+        if self.branch.candidates.values().all(Candidate::is_measured) {
+            self.select_parent().await;
+        }
+    }
+
+    async fn select_parent(&mut self) {
+        self.branch.selection_deadline = None;
+        let selected = self.branch.best_candidate();
+        let candidates = std::mem::take(&mut self.branch.candidates);
+
+        for (username, candidate) in candidates {
+            if Some(&username) == selected.as_ref() {
+                let level = candidate.level.unwrap_or_default();
+                let root = match candidate.root {
+                    Some(root) if level > 0 => root,
+                    _ => username.clone(),
+                };
+
+                info!(
+                    "Selected {} as distributed parent, level = {}, root = {}, latency = {:?}, speed = {:?}",
+                    username, level, root, candidate.latency, candidate.speed
+                );
+
+                let token = candidate.token.unwrap_or_default();
+                self.branch.parent = Some(Parent { username, token });
+
+                self.send_server_request(ServerRequest::NoParents(false))
+                    .await;
+                self.report_branch(level + 1, root).await;
+                self.send_to(
+                    token,
+                    DistributedMessage::ChildDepth(self.branch.child_depth),
+                )
+                .await;
+            } else if let Some(token) = candidate.token {
+                self.close(&username, token).await;
+            }
+        }
+
+        if self.branch.parent.is_none() {
+            warn!("No suitable distributed parent found, asking for new candidates");
+            self.send_server_request(ServerRequest::NoParents(true))
+                .await;
+        }
+    }
+
+    async fn forward_search(&mut self, request: SearchRequest) {
+        if !self
+            .branch
+            .recent_searches
+            .insert(&request.username, request.ticket)
+        {
+            debug!(
+                "Dropping duplicate search {} from {}",
+                request.ticket, request.username
+            );
+            return;
+        }
+
+        let tokens: Vec<u32> = self.branch.children.keys().copied().collect();
+        for token in tokens {
+            self.send_to(token, DistributedMessage::SearchRequest(request.clone()))
+                .await;
+        }
+    }
+
+    // Tell the server and our children where we are in the distributed network
+    async fn report_branch(&mut self, level: u32, root: String) {
+        self.branch.level = level;
+        self.branch.root = root.clone();
+
+        self.send_server_request(ServerRequest::BranchLevel(level))
+            .await;
+        self.send_server_request(ServerRequest::BranchRoot(root.clone()))
+            .await;
+
+        let tokens: Vec<u32> = self.branch.children.keys().copied().collect();
+        for token in tokens {
+            self.send_to(token, DistributedMessage::BranchLevel(level))
+                .await;
+            self.send_to(token, DistributedMessage::BranchRoot(root.clone()))
+                .await;
+        }
+    }
+
+    async fn update_children(&mut self) {
+        self.update_child_depth().await;
+        self.update_accept_children().await;
+    }
+
+    async fn update_child_depth(&mut self) {
+        let depth = self.branch.depth();
+        if depth == self.branch.child_depth {
+            return;
+        }
+
+        self.branch.child_depth = depth;
+        self.send_server_request(ServerRequest::ChildDepth(depth))
+            .await;
+
+        if let Some(token) = self.branch.parent.as_ref().map(|parent| parent.token) {
+            self.send_to(token, DistributedMessage::ChildDepth(depth))
+                .await;
+        }
+    }
+
+    async fn update_accept_children(&mut self) {
+        let accept_children = self.branch.accepts_children();
+        if self.branch.accept_children == Some(accept_children) {
+            return;
+        }
+
+        info!(
+            "Accepting distributed children : {}, limit = {}",
+            accept_children,
+            self.branch.children_limit()
+        );
+        self.branch.accept_children = Some(accept_children);
+        self.send_server_request(ServerRequest::AcceptChildren(accept_children))
+            .await;
+    }
+
+    async fn send_to(&self, token: u32, message: DistributedMessage) {
+        let channel = self
+            .channels
+            .get(token)
+            .await
+            .and_then(|state| state.channel);
+
+        match channel {
+            Some(channel) => {
+                if let Err(err) = channel
+                    .send(PeerRequestPacket::DistributedMessage(message))
+                    .await
+                {
+                    debug!("Distributed connection {} closed : {}", token, err);
+                }
+            }
+            None => debug!("No distributed connection with token {}", token),
+        }
+    }
+
+    // Removing the connection from the pool closes the handler request channel
+    async fn close(&self, username: &str, token: u32) {
+        debug!("Closing distributed connection with {}", username);
+        let _ = self.channels.remove_channel(token).await;
+    }
+
+    fn connect(&self, parent: PeerEntity) {
+        let server_request_tx = self.server_request_tx.clone();
+        let sse_tx = self.sse_tx.clone();
+        let ready_tx = self.ready_tx.clone();
+        let channels = self.channels.clone();
+        let shutdown_helper = self.shutdown_helper.clone();
+        let db = self.db.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = connect_to_peer_with_fallback(
+                server_request_tx,
+                sse_tx,
+                ready_tx,
+                channels,
+                shutdown_helper,
+                db,
+                &parent,
+                ConnectionType::DistributedNetwork,
+            )
+            .await
+            {
+                debug!(
+                    "Unable to connect to possible parent {}, cause = {}",
+                    parent.username, err
+                );
+            }
+        });
+    }
+
+    async fn send_server_request(&self, request: ServerRequest) {
+        if let Err(err) = self.server_request_tx.send(request).await {
+            error!("Error sending distributed request to the server : {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::peers::distributed::{Branch, Candidate, Child, ParentSpeed, RecentSearches};
+
+    fn candidate(latency_ms: u64, speed: Option<u32>) -> Candidate {
+        Candidate {
+            token: Some(1),
+            level: Some(2),
+            latency: Some(Duration::from_millis(latency_ms)),
+            speed,
+            ..Candidate::new()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_compute_max_children() {
+        let parent_speed = ParentSpeed {
+            min_speed: 1000,
+            ratio: 50,
+        };
+
+        assert_eq!(parent_speed.max_children(500), 0);
+        assert_eq!(parent_speed.max_children(5000), 100);
+        assert_eq!(ParentSpeed::default().max_children(0), u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn should_select_fastest_candidate() {
+        let mut branch = Branch {
+            parent_speed: ParentSpeed {
+                min_speed: 1000,
+                ratio: 50,
+            },
+            ..Branch::default()
+        };
+
+        branch
+            .candidates
+            .insert("slow".to_string(), candidate(10, Some(500)));
+        branch
+            .candidates
+            .insert("fast".to_string(), candidate(200, Some(8000)));
+        branch
+            .candidates
+            .insert("responsive".to_string(), candidate(50, Some(8000)));
+        branch
+            .candidates
+            .insert("silent".to_string(), Candidate::new());
+
+        assert_eq!(branch.best_candidate().as_deref(), Some("responsive"));
+
+        branch.candidates.remove("responsive");
+        branch.candidates.remove("fast");
+        assert_eq!(branch.best_candidate(), None);
+    }
+
+    #[tokio::test]
+    async fn should_limit_children_and_compute_depth() {
+        let mut branch = Branch::new(2);
+        assert!(!branch.accepts_children());
+
+        branch.parent_speed = ParentSpeed {
+            min_speed: 1000,
+            ratio: 1000,
+        };
+        branch.own_speed = Some(500);
+        assert!(!branch.accepts_children());
+
+        branch.own_speed = Some(10_000);
+        assert_eq!(branch.children_limit(), 2);
+        assert_eq!(branch.depth(), 0);
+
+        for (token, depth) in [(1, 0), (2, 3)] {
+            let username = format!("child-{}", token);
+            branch.children.insert(token, Child { username, depth });
+        }
+
+        assert!(!branch.accepts_children());
+        assert_eq!(branch.depth(), 4);
+    }
+
+    #[tokio::test]
+    async fn should_drop_duplicate_searches() {
+        let mut searches = RecentSearches::default();
+
+        assert!(searches.insert("alice", 1));
+        assert!(!searches.insert("alice", 1));
+        assert!(searches.insert("alice", 2));
+        assert!(searches.insert("bob", 1));
+    }
+}
@@ -1,14 +1,28 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use eyre::Result;
 use rand::random;
-use tokio::sync::{
-    mpsc,
-    mpsc::{channel, Receiver},
-    Semaphore,
+use tokio::{
+    sync::{
+        mpsc,
+        mpsc::{channel, Receiver},
+        Semaphore,
+    },
+    time::{self, timeout},
 };
 
-use soulseek_protocol::peers::p2p::transfer::{QueueUpload, TransferReply};
+use soulseek_protocol::peers::p2p::folder_content::{FolderContentsReply, FolderContentsRequest};
+use soulseek_protocol::peers::p2p::response::UnknownMessage;
+use soulseek_protocol::peers::p2p::search::{SearchReply, SearchRequest};
+use soulseek_protocol::peers::p2p::transfer::{
+    PlaceInQueueReply, PlaceInQueueRequest, QueueFailed, QueueUpload, TransferReply,
+};
+use soulseek_protocol::query::Query;
+use soulseek_protocol::server::search::SearchQuery;
 use soulseek_protocol::{
     message_common::ConnectionType,
     peers::{
@@ -23,11 +37,32 @@ use soulseek_protocol::{
         PeerRequestPacket,
     },
 };
-use vessel_database::entity::download::DownloadEntity;
-use vessel_database::entity::upload::UploadEntity;
+use vessel_database::entity::download::{DownloadEntity, FolderDownloadEntity, FolderFile};
+use vessel_database::entity::download_job::DownloadJobEntity;
+use vessel_database::entity::search::{SearchEntity, SearchResultEntity};
+use vessel_database::entity::shared_dirs::{
+    can_access, folder_contents, resolve_virtual_path, shared_directories_for,
+};
+use vessel_database::entity::upload::{UploadEntity, UploadStats};
+use vessel_database::entity::user_info::UserInfoEntity;
+use vessel_database::settings::Settings;
 use vessel_database::Database;
 
-use crate::peers::{channels::SenderPool, connection::PeerConnection, shutdown::Shutdown};
+use crate::peers::{
+    channels::SenderPool, connection::PeerConnection, distributed::DistributedEvent,
+    shutdown::Shutdown,
+};
+use crate::search::download::SourceEvent;
+
+/// Rejection reasons understood by other Soulseek clients.
+const FILE_NOT_SHARED: &str = "File not shared.";
+const TRANSFER_CANCELLED: &str = "Cancelled";
+
+/// Unknown peer messages are logged at most once during this interval.
+const UNKNOWN_MESSAGE_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Last unknown message warning and the number of warnings suppressed since.
+static UNKNOWN_MESSAGE_WARNING: Mutex<(Option<Instant>, u32)> = Mutex::new((None, 0));
 
 #[derive(Debug)]
 pub struct PeerHandler {
@@ -40,6 +75,7 @@ pub struct PeerHandler {
     pub(crate) _shutdown_complete: mpsc::Sender<()>,
     pub(crate) connection_states: SenderPool,
     pub(crate) db: Database,
+    pub(crate) settings: &'static Settings,
     // We store this to drop the channel state because calling get_address on a dropped connection
     // Can produce Err NotConnected
     pub(crate) address: SocketAddr,
@@ -83,16 +119,31 @@ impl PeerHandler {
             }
             ConnectionType::FileTransfer => {
                 let progress_sender = self.connection_states.get_progress_sender();
-                let user_name = self.peer_username.as_ref().unwrap().clone();
+                let user_name = self.peer_username()?;
                 self.connection
-                    .download(&self.db, progress_sender, user_name)
+                    .download(
+                        &self.db,
+                        progress_sender,
+                        user_name,
+                        &self.settings.download_folder,
+                        self.settings.timeouts.transfer_stall(),
+                    )
                     .await?;
             }
             ConnectionType::DistributedNetwork => {
-                self.listen_distributed().await?;
+                let result = self.listen_distributed(handler_rx).await;
+
+                if let (Some(username), Some(token)) =
+                    (self.peer_username.clone(), self.connection.token)
+                {
+                    self.send_distributed_event(DistributedEvent::Closed { username, token })
+                        .await;
+                }
+
+                result?;
             }
             ConnectionType::HandShake => {
-                unreachable!("Connection type should be known at this point")
+                return Err(eyre!("Connection type should be known at this point"));
             }
         }
 
@@ -103,16 +154,25 @@ impl PeerHandler {
         &mut self,
         mut handler_rx: Receiver<PeerRequestPacket>,
     ) -> Result<()> {
+        let idle_timeout = self.settings.timeouts.idle_peer();
+        let idle = time::sleep(idle_timeout);
+        tokio::pin!(idle);
+
+        // Connections initiated with token 0 are not in the pool and won't receive requests
+        let registered = !matches!(self.connection.token, None | Some(0));
+
         while !self.shutdown.is_shutdown() {
             tokio::select! {
                         response = self.connection.read_message::<PeerResponse>() =>  {
+                            idle.as_mut().reset(time::Instant::now() + idle_timeout);
+                            if let Some(token) = self.connection.token {
+                                self.connection_states.touch(token).await;
+                            }
                             match response {
                                 Ok(message) => {
                                     // When receiving a SearchReply we want to close the connection asap
-                                    if let PeerResponse::SearchReply(_) = message {
-                                            return self.sse_tx.send(message)
-                                            .await
-                                            .map_err(|err| eyre!(err));
+                                    if let PeerResponse::SearchReply(reply) = message {
+                                            return self.dispatch_search_reply(reply).await;
                                     } else {
                                        // Don't flood the log with search replies
                                        info!("[token={:?}] - Got Peer message {:?}", self.connection.token, message);
@@ -132,12 +192,24 @@ impl PeerHandler {
                             }
 
                         },
-                        request = handler_rx.recv() => if let Some(request) = request  {
-                            debug!("Sending request to {:?}", self.peer_username);
-                            if let Err(err) = self.connection.write_request(request).await {
-                                error!("Handler write error, {:?}", err);
+                        request = handler_rx.recv(), if registered => match request {
+                            Some(request) => {
+                                idle.as_mut().reset(time::Instant::now() + idle_timeout);
+                                debug!("Sending request to {:?}", self.peer_username);
+                                if let Err(err) = self.connection.write_request(request).await {
+                                    error!("Handler write error, {:?}", err);
+                                }
+                            }
+                            // The connection was evicted from the pool
+                            None => {
+                                info!("Closing evicted connection with {:?}", self.peer_username);
+                                break;
                             }
                         },
+                        _ = &mut idle => {
+                            info!("Closing idle connection with {:?}", self.peer_username);
+                            break;
+                        },
                         _ = self.shutdown.recv() => {
                             // If a shutdown signal is received, return from `run`.
                             // This will result in the task terminating.
@@ -149,11 +221,52 @@ impl PeerHandler {
         Ok(())
     }
 
-    pub(crate) async fn listen_distributed(&mut self) -> Result<()> {
+    pub(crate) async fn listen_distributed(
+        &mut self,
+        mut handler_rx: Receiver<PeerRequestPacket>,
+    ) -> Result<()> {
+        let idle_timeout = self.settings.timeouts.idle_distributed();
+        let idle = time::sleep(idle_timeout);
+        tokio::pin!(idle);
+
+        // Connections initiated with token 0 are not in the pool and won't receive requests
+        let registered = !matches!(self.connection.token, None | Some(0));
+
+        if registered {
+            let username = self.peer_username()?;
+            let token = self.connection.token.unwrap_or_default();
+            self.send_distributed_event(DistributedEvent::Connected { username, token })
+                .await;
+        }
+
         while !self.shutdown.is_shutdown() {
             tokio::select! {
                         response = self.connection.read_message::<DistributedMessage>() =>  {
+                            idle.as_mut().reset(time::Instant::now() + idle_timeout);
                             match response {
+                                Ok(DistributedMessage::SearchRequest(request)) => {
+                                    let username = self.peer_username()?;
+                                    let token = self.connection.token.unwrap_or_default();
+                                    self.send_distributed_event(DistributedEvent::Search { username, token, request: request.clone() }).await;
+                                    self.connection_states
+                                        .get_search_sender()
+                                        .send(request.into())
+                                        .await?;
+                                }
+                                Ok(DistributedMessage::BranchLevel(level)) => {
+                                    let username = self.peer_username()?;
+                                    let token = self.connection.token.unwrap_or_default();
+                                    self.send_distributed_event(DistributedEvent::BranchLevel { username, token, level }).await;
+                                }
+                                Ok(DistributedMessage::BranchRoot(root)) => {
+                                    let username = self.peer_username()?;
+                                    let token = self.connection.token.unwrap_or_default();
+                                    self.send_distributed_event(DistributedEvent::BranchRoot { username, token, root }).await;
+                                }
+                                Ok(DistributedMessage::ChildDepth(depth)) => {
+                                    let token = self.connection.token.unwrap_or_default();
+                                    self.send_distributed_event(DistributedEvent::ChildDepth { token, depth }).await;
+                                }
                                 Ok(message) => trace!("Got distributed message {:?}", message),
                                 Err(e) => {
                                     return Err(eyre!("Error in connection handler with {:?} : {}", self.peer_username, e));
@@ -161,6 +274,23 @@ impl PeerHandler {
                             }
 
                         },
+                        request = handler_rx.recv(), if registered => match request {
+                            Some(request) => {
+                                debug!("Sending distributed request to {:?}", self.peer_username);
+                                if let Err(err) = self.connection.write_request(request).await {
+                                    error!("Handler write error, {:?}", err);
+                                }
+                            }
+                            // The connection was dropped from the pool
+                            None => {
+                                info!("Closing distributed connection with {:?}", self.peer_username);
+                                break;
+                            }
+                        },
+                        _ = &mut idle => {
+                            info!("Closing idle distributed connection with {:?}", self.peer_username);
+                            break;
+                        },
                         _ = self.shutdown.recv() => {
                             // If a shutdown signal is received, return from `run`.
                             // This will result in the task terminating.
@@ -172,6 +302,28 @@ impl PeerHandler {
         Ok(())
     }
 
+    async fn send_distributed_event(&self, event: DistributedEvent) {
+        if let Err(err) = self
+            .connection_states
+            .get_distributed_sender()
+            .send(event)
+            .await
+        {
+            error!("Error sending distributed event : {}", err);
+        }
+    }
+
+    async fn send_source_event(&self, event: SourceEvent) {
+        if let Err(err) = self
+            .connection_states
+            .get_source_event_sender()
+            .send(event)
+            .await
+        {
+            error!("Error sending download source event : {}", err);
+        }
+    }
+
     pub(crate) async fn init_connection_outgoing(
         &mut self,
         conn_type: ConnectionType,
@@ -185,12 +337,14 @@ impl PeerHandler {
         self.send_peer_init(token, conn_type).await?;
 
         let (tx, rx) = channel(32);
-        self.connection_states.peer_init(
-            &self.peer_username.as_ref().unwrap(),
-            self.connection_type(),
-            token,
-            tx,
-        );
+        self.connection_states
+            .peer_init(
+                &self.peer_username.as_ref().unwrap(),
+                self.connection_type(),
+                token,
+                tx,
+            )
+            .await?;
         self.connection.token = Some(token);
         self.connection.connection_type = conn_type;
 
@@ -206,7 +360,7 @@ impl PeerHandler {
         );
         self.send_pierce_firewall(token).await?;
         let (tx, rx) = channel(32);
-        let state = self.connection_states.ready(token, tx)?;
+        let state = self.connection_states.ready(token, tx).await?;
         self.connection.token = Some(token);
         self.peer_username = Some(state.username);
         self.connection.connection_type = state.conn_type;
@@ -217,10 +371,13 @@ impl PeerHandler {
     }
 
     pub(crate) async fn wait_for_connection_handshake(&mut self) -> Result<()> {
-        let message = self
-            .connection
-            .read_message::<PeerConnectionMessage>()
-            .await?;
+        // Don't let a silent peer hold a connection permit forever
+        let message = timeout(
+            self.settings.timeouts.handshake(),
+            self.connection.read_message::<PeerConnectionMessage>(),
+        )
+        .await
+        .map_err(|_| eyre!("No handshake received from {}", self.address))??;
         let rx = self.handle_connection_message(&message).await?;
         self.listen(rx).await
     }
@@ -232,27 +389,119 @@ impl PeerHandler {
             | PeerResponse::UserInfoReply(_)
             | PeerResponse::SearchReply(_) => Ok(()),
             PeerResponse::SharesRequest => self.send_shares_reply().await,
+            PeerResponse::SearchRequest(request) => self.forward_search_request(request).await,
             PeerResponse::UserInfoRequest => self.send_user_info().await,
-            PeerResponse::FolderContentsRequest(_) => todo!(),
-            PeerResponse::FolderContentsReply(_) => todo!(),
+            PeerResponse::FolderContentsRequest(request) => {
+                self.send_folder_contents(request).await
+            }
+            PeerResponse::FolderContentsReply(reply) => self.queue_folder_download(reply).await,
             PeerResponse::TransferRequest(request) => self.transfer(request).await,
             PeerResponse::TransferReply(transfer_reply) => {
                 self.transfer_reply(transfer_reply).await
             }
-            PeerResponse::UploadPlaceholder => todo!(),
             PeerResponse::QueueUpload(queue_upload) => self.queue_upload(queue_upload).await,
-            PeerResponse::PlaceInQueueReply(_) => todo!(),
-            PeerResponse::UploadFailed(_) => todo!(),
-            PeerResponse::QueueFailed(_) => todo!(),
-            PeerResponse::PlaceInQueueRequest(_) => todo!(),
-            PeerResponse::UploadQueueNotification => todo!(),
-            PeerResponse::Unknown => {
-                error!("Unknown Peer message kind : {:?}", message);
-                panic!()
+            PeerResponse::PlaceInQueueRequest(request) => self.send_place_in_queue(request).await,
+            PeerResponse::PlaceInQueueReply(reply) => {
+                info!(
+                    "{} is at place {} in {:?} upload queue",
+                    reply.filename, reply.place, self.peer_username
+                );
+                Ok(())
+            }
+            PeerResponse::UploadFailed(upload_failed) => {
+                warn!(
+                    "{:?} failed to upload {}",
+                    self.peer_username, upload_failed.filename
+                );
+                self.send_source_event(SourceEvent::Failed {
+                    username: self.peer_username()?,
+                    file_name: upload_failed.filename.clone(),
+                    reason: "Upload failed".to_string(),
+                })
+                .await;
+                Ok(())
+            }
+            PeerResponse::QueueFailed(queue_failed) => {
+                warn!(
+                    "{:?} refused to queue {}, reason : {}",
+                    self.peer_username, queue_failed.filename, queue_failed.reason
+                );
+                self.send_source_event(SourceEvent::Failed {
+                    username: self.peer_username()?,
+                    file_name: queue_failed.filename.clone(),
+                    reason: queue_failed.reason.clone(),
+                })
+                .await;
+                Ok(())
+            }
+            // Deprecated messages, nothing to do
+            PeerResponse::UploadPlaceholder | PeerResponse::UploadQueueNotification => {
+                debug!("Ignoring deprecated peer message {:?}", message);
+                Ok(())
+            }
+            PeerResponse::Unknown(unknown) => {
+                warn_unknown_message(self.peer_username.as_deref(), unknown);
+                Ok(())
             }
         }
     }
 
+    // Legacy clients send searches directly instead of going through the distributed network
+    async fn forward_search_request(&mut self, request: &SearchRequest) -> tokio::io::Result<()> {
+        let query = SearchQuery {
+            username: self.peer_username()?,
+            ticket: request.ticket,
+            query: request.query.clone(),
+        };
+
+        self.connection_states
+            .get_search_sender()
+            .send(query)
+            .await
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::BrokenPipe, err.to_string()))
+    }
+
+    // Drop search results not matching our original query before storing them and sending them to
+    // the SSE clients, peers are quite lax regarding excluded terms.
+    // Replies to unknown or expired searches are only sent to the SSE clients.
+    async fn dispatch_search_reply(&mut self, mut reply: SearchReply) -> Result<()> {
+        let search = self
+            .db
+            .get_by_key::<SearchEntity>(&reply.ticket.to_string());
+
+        if let Some(search) = &search {
+            reply.retain_matching(&Query::parse(&search.query));
+        }
+
+        if reply.is_empty() {
+            return Ok(());
+        }
+
+        // Wishlist searches are followed up by the wishlist scheduler, not by the SSE clients
+        if let Some(wishlist_id) = search.as_ref().and_then(|search| search.wishlist_id) {
+            return self
+                .connection_states
+                .get_wishlist_sender()
+                .send((wishlist_id, reply))
+                .await
+                .map_err(|err| eyre!("Error dispatching wishlist search reply: {}", err));
+        }
+
+        if search.is_some() && !reply.files.is_empty() {
+            self.db.insert(&SearchResultEntity::from(&reply))?;
+        }
+
+        // Import searches are scored by the import scheduler once their replies are stored
+        if search.is_some_and(|search| search.import_id.is_some()) {
+            return Ok(());
+        }
+
+        self.sse_tx
+            .send(PeerResponse::SearchReply(reply))
+            .await
+            .map_err(|err| eyre!(err))
+    }
+
     async fn handle_connection_message(
         &mut self,
         message: &PeerConnectionMessage,
@@ -261,7 +510,7 @@ impl PeerHandler {
         let (tx, rx) = channel(32);
         let token = match message {
             PeerConnectionMessage::PierceFirewall(token) => {
-                let state = self.connection_states.ready(*token, tx)?;
+                let state = self.connection_states.ready(*token, tx).await?;
                 self.connection.connection_type = state.conn_type;
                 self.peer_username = Some(state.username);
                 self.connection.token = Some(*token);
@@ -275,14 +524,22 @@ impl PeerHandler {
                 self.connection.connection_type = *connection_type;
                 self.peer_username = Some(username.clone());
 
+                // Distributed children connect with token 0, we need a channel to send them
+                // searches
+                let token = match (*token, connection_type) {
+                    (0, ConnectionType::DistributedNetwork) => random(),
+                    (token, _) => token,
+                };
+
                 // Token = 0 indicate an incoming search reply
-                if *token != 0 {
+                if token != 0 {
                     self.connection_states
-                        .peer_init(&username, *connection_type, *token, tx);
+                        .peer_init(&username, *connection_type, token, tx)
+                        .await?;
                 };
 
-                self.connection.token = Some(*token);
-                *token
+                self.connection.token = Some(token);
+                token
             }
         };
 
@@ -326,28 +583,26 @@ impl PeerHandler {
     }
 
     async fn send_user_info(&mut self) -> tokio::io::Result<()> {
-        // TODO : calculate correct values for total_upload, queue size and free slots
+        let user_info = UserInfoEntity::get_or(&self.db, &self.settings.user_info);
+        let upload_stats = UploadStats::current(self.settings.upload_slots);
+
         self.connection
             .write_request(PeerRequestPacket::Message(PeerRequest::UserInfoReply(
                 UserInfo {
-                    description: "Hello from vessel".to_string(),
-                    picture: None,
-                    total_upload: 0,
-                    queue_size: 0,
-                    slots_free: false,
+                    picture: user_info.read_picture(),
+                    description: user_info.description,
+                    total_upload: upload_stats.upload_slots,
+                    queue_size: upload_stats.queue_size,
+                    slots_free: upload_stats.slots_free,
                 },
             )))
             .await
     }
 
     async fn send_shares_reply(&mut self) -> tokio::io::Result<()> {
-        let shared_dirs_copy;
-        {
-            let shared_dirs = &vessel_database::SHARED_DIRS;
-            let shared_dirs = shared_dirs.lock();
-            let shared_dirs = shared_dirs.unwrap();
-            shared_dirs_copy = shared_dirs.clone();
-        }
+        // Only expose the directories this peer is allowed to browse
+        let username = self.peer_username.as_deref().unwrap_or_default();
+        let shared_dirs = shared_directories_for(&self.db, self.settings, username);
 
         self.connection
             .write_request(PeerRequestPacket::Message(PeerRequest::SharesRequest))
@@ -355,23 +610,150 @@ impl PeerHandler {
 
         self.connection
             .write_request(PeerRequestPacket::Message(PeerRequest::SharesReply(
-                shared_dirs_copy,
+                shared_dirs,
             )))
             .await
     }
 
+    async fn send_folder_contents(
+        &mut self,
+        request: &FolderContentsRequest,
+    ) -> tokio::io::Result<()> {
+        let username = self.peer_username.as_deref().unwrap_or_default();
+        let dirs = folder_contents(&self.db, self.settings, username, &request.folder);
+
+        self.connection
+            .write_request(PeerRequestPacket::Message(
+                PeerRequest::FolderContentsReply(FolderContentsReply {
+                    ticket: request.ticket,
+                    folder: request.folder.clone(),
+                    dirs,
+                }),
+            ))
+            .await
+    }
+
+    // Queue every file of a folder we requested via http as a single grouped download
+    async fn queue_folder_download(
+        &mut self,
+        reply: &FolderContentsReply,
+    ) -> tokio::io::Result<()> {
+        let username = self.peer_username.as_deref().unwrap_or_default();
+        let key = FolderDownloadEntity::key_from(username, reply.ticket);
+        let mut folder_download = match self.db.get_by_key::<FolderDownloadEntity>(&key) {
+            Some(folder_download) => folder_download,
+            None => {
+                debug!("No folder download pending for ticket {}", reply.ticket);
+                return Ok(());
+            }
+        };
+
+        folder_download.files = reply
+            .dirs
+            .iter()
+            .flat_map(|dir| {
+                dir.files.iter().map(move |file| FolderFile {
+                    file_name: format!("{}\\{}", dir.name, file.name),
+                    file_size: file.size,
+                    progress: 0,
+                    ticket: None,
+                })
+            })
+            .collect();
+        self.db.insert(&folder_download)?;
+
+        info!(
+            "Queuing {} files from folder {}",
+            folder_download.files.len(),
+            folder_download.folder
+        );
+
+        for file in folder_download.files {
+            self.connection
+                .write_request(PeerRequestPacket::Message(PeerRequest::QueueUpload(
+                    QueueUpload {
+                        file_name: file.file_name,
+                    },
+                )))
+                .await?;
+        }
+
+        Ok(())
+    }
+
     async fn transfer(&mut self, request: &TransferRequest) -> tokio::io::Result<()> {
         let ticket = request.ticket;
-        let file_size = request.file_size.expect("Ok file size");
 
-        let username = self
-            .peer_username
-            .clone()
-            .expect("Username should be known when initiating a transfer");
+        let username = self.peer_username()?;
+
+        // Legacy clients request downloads directly instead of queuing them
+        if request.direction == TransferRequest::DOWNLOAD {
+            let reason = if is_shared_with(&self.db, self.settings, &username, &request.filename) {
+                let upload = UploadEntity::new(request.filename.clone(), username, ticket);
+                self.db.insert(&upload)?;
+                "Queued"
+            } else {
+                warn!(
+                    "Rejecting transfer request for unshared file {}",
+                    request.filename
+                );
+                FILE_NOT_SHARED
+            };
+
+            return self
+                .connection
+                .write_request(PeerRequestPacket::Message(PeerRequest::TransferReply(
+                    TransferReply::TransferRejected {
+                        ticket,
+                        reason: reason.to_string(),
+                    },
+                )))
+                .await;
+        }
 
-        let download_entity = DownloadEntity::from((username, request));
+        // We moved on to another source of this file
+        if DownloadJobEntity::is_given_up(&self.db, &username, &request.filename) {
+            info!(
+                "Rejecting late upload of {} from {}",
+                request.filename, username
+            );
+            return self
+                .connection
+                .write_request(PeerRequestPacket::Message(PeerRequest::TransferReply(
+                    TransferReply::TransferRejected {
+                        ticket,
+                        reason: TRANSFER_CANCELLED.to_string(),
+                    },
+                )))
+                .await;
+        }
+
+        let file_size = match request.file_size {
+            Some(file_size) => file_size,
+            None => {
+                warn!("Rejecting upload of {} without file size", request.filename);
+                return self
+                    .connection
+                    .write_request(PeerRequestPacket::Message(PeerRequest::TransferReply(
+                        TransferReply::TransferRejected {
+                            ticket,
+                            reason: "Missing file size".to_string(),
+                        },
+                    )))
+                    .await;
+            }
+        };
+
+        let mut download_entity = DownloadEntity::from((username.clone(), request));
+        download_entity.folder_ticket = self.attach_to_folder_download(request)?;
         self.db.insert(&download_entity)?;
 
+        self.send_source_event(SourceEvent::Uploading {
+            username,
+            file_name: request.filename.clone(),
+        })
+        .await;
+
         self.connection
             .write_request(PeerRequestPacket::Message(PeerRequest::TransferReply(
                 TransferReplyOk { ticket, file_size },
@@ -381,6 +763,32 @@ impl PeerHandler {
         Ok(())
     }
 
+    // Link an incoming upload to the folder download it was queued for, if any
+    fn attach_to_folder_download(
+        &self,
+        request: &TransferRequest,
+    ) -> tokio::io::Result<Option<u32>> {
+        let username = self.peer_username.as_deref().unwrap_or_default();
+        let folder_download = self
+            .db
+            .get_all::<FolderDownloadEntity>()
+            .into_iter()
+            .filter(|folder_download| folder_download.user == username)
+            .find_map(|mut folder_download| {
+                let file = folder_download.file_by_name(&request.filename)?;
+                file.ticket = Some(request.ticket);
+                Some(folder_download)
+            });
+
+        match folder_download {
+            Some(folder_download) => {
+                self.db.insert(&folder_download)?;
+                Ok(Some(folder_download.ticket))
+            }
+            None => Ok(None),
+        }
+    }
+
     async fn transfer_reply(&mut self, transfer_reply: &TransferReply) -> tokio::io::Result<()> {
         info!("Transfer reply : {:?}", transfer_reply);
         Ok(())
@@ -389,7 +797,25 @@ impl PeerHandler {
     async fn queue_upload(&mut self, queue_upload: &QueueUpload) -> tokio::io::Result<()> {
         debug!("{:?}", queue_upload);
         let file_name = queue_upload.file_name.clone();
-        let user_name = self.peer_username.as_ref().unwrap().clone();
+
+        let user_name = self.peer_username()?;
+
+        if !is_shared_with(&self.db, self.settings, &user_name, &file_name) {
+            warn!(
+                "Rejecting upload request from {} for unshared file {}",
+                user_name, file_name
+            );
+            return self
+                .connection
+                .write_request(PeerRequestPacket::Message(PeerRequest::QueueFailed(
+                    QueueFailed {
+                        filename: file_name,
+                        reason: FILE_NOT_SHARED.to_string(),
+                    },
+                )))
+                .await;
+        }
+
         // FIXME: NO MORE RANDOM TICKET
         let ticket = random();
         let upload = UploadEntity::new(file_name, user_name, ticket);
@@ -397,6 +823,38 @@ impl PeerHandler {
         Ok(())
     }
 
+    async fn send_place_in_queue(
+        &mut self,
+        request: &PlaceInQueueRequest,
+    ) -> tokio::io::Result<()> {
+        let key = format!("{}@{}", self.peer_username()?, request.file_name);
+        let upload = match self.db.get_by_key::<UploadEntity>(&key) {
+            Some(upload) => upload,
+            None => {
+                debug!("No queued upload for {}", key);
+                return Ok(());
+            }
+        };
+
+        self.connection
+            .write_request(PeerRequestPacket::Message(PeerRequest::PlaceInQueueReply(
+                PlaceInQueueReply {
+                    place: upload.queue_position(&self.db),
+                    filename: upload.file_name,
+                },
+            )))
+            .await
+    }
+
+    fn peer_username(&self) -> tokio::io::Result<String> {
+        self.peer_username.clone().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Peer username should be known at this point",
+            )
+        })
+    }
+
     fn connection_type(&self) -> ConnectionType {
         self.connection.connection_type
     }
@@ -411,12 +869,350 @@ impl Drop for PeerHandler {
         // Otherwise connection was never ready and does not have a ready channel
         if let Some(token) = self.connection.token {
             if token != 0 {
-                if let Err(e) = self.connection_states.remove_channel(token) {
-                    panic!(
-                        "Error dropping channel with address {}, {}",
-                        self.address, e
-                    )
+                let connection_states = self.connection_states.clone();
+                let address = self.address;
+                // Evicted connections were already removed from the pool
+                tokio::spawn(async move {
+                    if let Err(e) = connection_states.remove_channel(token).await {
+                        debug!("Error dropping channel with address {}, {}", address, e)
+                    };
+                });
+            }
+        }
+    }
+}
+
+/// Restricted files are refused as if they were not shared at all.
+fn is_shared_with(db: &Database, settings: &Settings, username: &str, virtual_path: &str) -> bool {
+    can_access(db, settings, username, virtual_path)
+        && resolve_virtual_path(settings, virtual_path).is_some()
+}
+
+/// Log unknown peer messages with their raw content, a misbehaving peer could flood the log
+/// otherwise.
+fn warn_unknown_message(username: Option<&str>, message: &UnknownMessage) {
+    let mut warning = UNKNOWN_MESSAGE_WARNING.lock().unwrap();
+    let (last_warning, suppressed) = &mut *warning;
+    let now = Instant::now();
+
+    match last_warning {
+        Some(last) if now.duration_since(*last) < UNKNOWN_MESSAGE_WARNING_INTERVAL => {
+            *suppressed += 1;
+            debug!(
+                "Unknown peer message code {} from {:?}",
+                message.code, username
+            );
+        }
+        _ => {
+            let data = &message.data[..message.data.len().min(64)];
+            warn!(
+                "Unknown peer message code {} from {:?}, {} bytes : {:02x?} ({} similar warnings suppressed)",
+                message.code,
+                username,
+                message.data.len(),
+                data,
+                suppressed
+            );
+            *last_warning = Some(now);
+            *suppressed = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, net::SocketAddr, sync::Arc, time::Duration};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tokio::{
+        io::{duplex, AsyncWriteExt, BufWriter, DuplexStream},
+        sync::{broadcast, mpsc, Semaphore},
+    };
+
+    use soulseek_protocol::{
+        frame::ToBytes,
+        message_common::ConnectionType,
+        peers::p2p::{
+            folder_content::FolderContentsReply,
+            request::PeerRequest,
+            shared_directories::SharedDirectories,
+            transfer::{
+                PlaceInQueueReply, PlaceInQueueRequest, QueueFailed, TransferReply,
+                TransferRequest, UploadFailed,
+            },
+            user_info::UserInfo,
+            PeerMessageCode,
+        },
+    };
+    use vessel_database::{
+        settings::{
+            ConfiguredShare, ConnectionSettings, DistributedSettings, SearchSettings, Settings,
+            SharedDirectory, TimeoutSettings, UserInfoSettings, Visibility,
+        },
+        Database,
+    };
+
+    use crate::peers::{
+        channels::SenderPool, connection::PeerConnection, handler::PeerHandler, shutdown::Shutdown,
+    };
+
+    // Requests for our shares, user info, folder contents and uploads are answered from these
+    // settings, with a single shared file
+    fn settings() -> &'static Settings {
+        let share = std::env::temp_dir().join("vessel_handler_fuzz");
+        fs::create_dir_all(&share).unwrap();
+        fs::write(share.join("track.flac"), b"not really flac").unwrap();
+
+        Box::leak(Box::new(Settings {
+            shared_directories: vec![SharedDirectory::Configured(ConfiguredShare {
+                path: share,
+                alias: Some("music".to_string()),
+                visibility: Visibility::Public,
+            })],
+            download_folder: std::env::temp_dir().join("vessel_handler_fuzz_downloads"),
+            username: "vessel".to_string(),
+            password: "lessev".to_string(),
+            buddies: vec![],
+            trusted_users: vec![],
+            user_info: UserInfoSettings::default(),
+            upload_slots: 2,
+            timeouts: TimeoutSettings::default(),
+            connections: ConnectionSettings::default(),
+            distributed: DistributedSettings::default(),
+            searches: SearchSettings::default(),
+        }))
+    }
+
+    fn frame(code: u32, body: &[u8]) -> Vec<u8> {
+        let mut frame = (body.len() as u32 + 4).to_le_bytes().to_vec();
+        frame.extend_from_slice(&code.to_le_bytes());
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn handshake_frame(code: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = (body.len() as u32 + 1).to_le_bytes().to_vec();
+        frame.push(code);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn peer_init(connection_type: &str, token: u32) -> Vec<u8> {
+        let mut body = vec![];
+        for string in &["fuzzer", connection_type] {
+            body.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            body.extend_from_slice(string.as_bytes());
+        }
+        body.extend_from_slice(&token.to_le_bytes());
+        handshake_frame(1, &body)
+    }
+
+    // The first message of an incoming connection, `token` is not known by the connection pool
+    fn random_handshake_frame(rng: &mut StdRng, token: u32) -> Vec<u8> {
+        match rng.gen_range(0, 7) {
+            0 => peer_init("P", token),
+            // Search replies come with token 0
+            1 => peer_init("P", 0),
+            // Distributed children connect with token 0
+            2 => peer_init("D", rng.gen_range(0, 2) * token),
+            3 => peer_init("F", token),
+            4 => peer_init("X", token),
+            5 => handshake_frame(0, &token.to_le_bytes()),
+            _ => {
+                let mut body = vec![0; rng.gen_range(0, 32)];
+                rng.fill(&mut body[..]);
+                handshake_frame(rng.gen(), &body)
+            }
+        }
+    }
+
+    async fn request_frame(request: PeerRequest) -> Vec<u8> {
+        let mut frame = vec![];
+        let mut buffer = BufWriter::new(&mut frame);
+        request.write_to_buf(&mut buffer).await.unwrap();
+        buffer.flush().await.unwrap();
+        frame
+    }
+
+    async fn valid_frames() -> Vec<Vec<u8>> {
+        let mut search_request = 42u32.to_le_bytes().to_vec();
+        search_request.extend_from_slice(&4u32.to_le_bytes());
+        search_request.extend_from_slice(b"rust");
+
+        vec![
+            frame(PeerMessageCode::SearchRequest as u32, &search_request),
+            frame(PeerMessageCode::UploadPlacehold as u32, &[]),
+            frame(PeerMessageCode::UploadQueueNotification as u32, &[]),
+            frame(1337, &[1, 2, 3]),
+            request_frame(PeerRequest::SharesReply(SharedDirectories { dirs: vec![] })).await,
+            request_frame(PeerRequest::UserInfoReply(UserInfo {
+                description: "fuzzer".to_string(),
+                picture: None,
+                total_upload: 1,
+                queue_size: 2,
+                slots_free: true,
+            }))
+            .await,
+            request_frame(PeerRequest::FolderContentsReply(FolderContentsReply {
+                ticket: 1,
+                folder: "@@music".to_string(),
+                dirs: vec![],
+            }))
+            .await,
+            request_frame(PeerRequest::TransferRequest(TransferRequest {
+                direction: TransferRequest::UPLOAD,
+                ticket: 2,
+                filename: "@@music\\track.flac".to_string(),
+                file_size: Some(1337),
+            }))
+            .await,
+            request_frame(PeerRequest::TransferRequest(TransferRequest {
+                direction: TransferRequest::UPLOAD,
+                ticket: 3,
+                filename: "..".to_string(),
+                file_size: None,
+            }))
+            .await,
+            request_frame(PeerRequest::TransferReply(TransferReply::TransferReplyOk {
+                ticket: 4,
+                file_size: 1337,
+            }))
+            .await,
+            request_frame(PeerRequest::PlaceInQueueRequest(PlaceInQueueRequest {
+                file_name: "@@music\\track.flac".to_string(),
+            }))
+            .await,
+            request_frame(PeerRequest::PlaceInQueueReply(PlaceInQueueReply {
+                filename: "@@music\\track.flac".to_string(),
+                place: 3,
+            }))
+            .await,
+            request_frame(PeerRequest::UploadFailed(UploadFailed {
+                filename: "@@music\\track.flac".to_string(),
+            }))
+            .await,
+            request_frame(PeerRequest::QueueFailed(QueueFailed {
+                filename: "@@music\\track.flac".to_string(),
+                reason: "File not shared.".to_string(),
+            }))
+            .await,
+        ]
+    }
+
+    fn random_frame(rng: &mut StdRng) -> Vec<u8> {
+        let code = match rng.gen_range(0, 3) {
+            0 => rng.gen(),
+            _ => rng.gen_range(0, 60),
+        };
+
+        let mut body = vec![0; rng.gen_range(0, 64)];
+        rng.fill(&mut body[..]);
+        let mut frame = frame(code, &body);
+
+        // Sometimes lie about the message length
+        if rng.gen_bool(0.1) {
+            frame[..4].copy_from_slice(&rng.gen_range(0u32, 16).to_le_bytes());
+        }
+
+        frame
+    }
+
+    // An incoming connection waiting for its handshake
+    fn handler(stream: DuplexStream, settings: &'static Settings) -> (PeerHandler, impl Sized) {
+        let (sse_tx, sse_rx) = mpsc::channel(1024);
+        let (ready_tx, ready_rx) = mpsc::channel(1024);
+        let (progress_tx, progress_rx) = mpsc::channel(1024);
+        let (search_tx, search_rx) = mpsc::channel(1024);
+        let (distributed_tx, distributed_rx) = mpsc::channel(1024);
+        let (wishlist_tx, wishlist_rx) = mpsc::channel(1024);
+        let (source_event_tx, source_event_rx) = mpsc::channel(1024);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+
+        let address: SocketAddr = "127.0.0.1:2255".parse().unwrap();
+        let connection = PeerConnection::with_stream(stream, Some(address));
+
+        let handler = PeerHandler {
+            peer_username: None,
+            connection,
+            sse_tx,
+            ready_tx,
+            shutdown: Shutdown::new(shutdown_rx),
+            limit_connections: Arc::new(Semaphore::new(0)),
+            _shutdown_complete: shutdown_complete_tx,
+            connection_states: SenderPool::new(
+                progress_tx,
+                search_tx,
+                distributed_tx,
+                wishlist_tx,
+                source_event_tx,
+                ConnectionSettings::default(),
+            ),
+            db: Database::temporary(),
+            settings,
+            address,
+        };
+
+        // Keep the other ends of the channels alive while the handler runs
+        let receivers = (
+            sse_rx,
+            ready_rx,
+            progress_rx,
+            search_rx,
+            distributed_rx,
+            wishlist_rx,
+            source_event_rx,
+            shutdown_tx,
+            shutdown_complete_rx,
+        );
+
+        (handler, receivers)
+    }
+
+    #[tokio::test]
+    async fn should_never_panic_on_peer_messages() {
+        let mut rng = StdRng::seed_from_u64(2255);
+        let valid_frames = valid_frames().await;
+        let settings = settings();
+
+        for token in 1..=200 {
+            let (mut peer, stream) = duplex(1024 * 1024);
+            let (mut handler, _guard) = handler(stream, settings);
+            let (_handler_tx, handler_rx) = mpsc::channel(1);
+
+            let task = if rng.gen_bool(0.5) {
+                let handshake = random_handshake_frame(&mut rng, token);
+                peer.write_all(&handshake).await.unwrap();
+                tokio::spawn(async move { handler.wait_for_connection_handshake().await })
+            } else {
+                handler.peer_username = Some("fuzzer".to_string());
+                handler.connection.connection_type = ConnectionType::PeerToPeer;
+                // This token was never registered, dropping the handler must not panic
+                handler.connection.token = Some(token);
+                tokio::spawn(async move { handler.listen_p2p(handler_rx).await })
+            };
+
+            for _ in 0..rng.gen_range(1, 16) {
+                let frame = if rng.gen_bool(0.5) {
+                    valid_frames[rng.gen_range(0, valid_frames.len())].clone()
+                } else {
+                    random_frame(&mut rng)
                 };
+
+                // The handler may already have closed the connection
+                if peer.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+
+            peer.shutdown().await.unwrap();
+
+            let result = tokio::time::timeout(Duration::from_secs(5), task)
+                .await
+                .expect("Handler should stop once the peer closed the connection");
+
+            if let Err(err) = result {
+                assert!(!err.is_panic(), "Handler panicked : {}", err);
             }
         }
     }
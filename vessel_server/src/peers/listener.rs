@@ -17,30 +17,27 @@ use tracing::{error, info};
 
 use soulseek_protocol::{
     message_common::ConnectionType,
-    peers::{p2p::response::PeerResponse, PeerRequestPacket},
+    peers::{p2p::response::PeerResponse, PeerRequestPacket, UndeliveredRequest},
     server::{
-        peer::{
-            Peer, PeerAddress, PeerConnectionRequest, PeerConnectionTicket, RequestConnectionToPeer,
-        },
+        peer::{PeerAddress, PeerConnectionRequest, PeerConnectionTicket, RequestConnectionToPeer},
         request::ServerRequest,
+        response::ServerResponse,
     },
     SlskError,
 };
 use vessel_database::entity::peer::PeerEntity;
+use vessel_database::settings::CONFIG;
 use vessel_database::Database;
 
 use crate::peers::{
     channels::SenderPool,
     connection::PeerConnection,
     dispatcher::Dispatcher,
+    distributed::{Branch, DistributedEvent, DistributedNetwork},
     handler::{connect_direct, pierce_firewall, PeerHandler},
     shutdown::Shutdown,
 };
 
-/// TODO : Make this value configurable
-const MAX_CONNECTIONS: usize = 4096;
-const MAX_PARENT: usize = 10;
-
 #[derive(Debug, Clone)]
 pub(crate) struct ShutdownHelper {
     notify_shutdown: broadcast::Sender<()>,
@@ -59,19 +56,23 @@ struct GlobalConnectionHandler {
 
 pub struct PeerListenerSenders {
     pub sse_tx: mpsc::Sender<PeerResponse>,
+    pub sse_server_tx: mpsc::Sender<ServerResponse>,
+    pub undelivered_tx: mpsc::Sender<UndeliveredRequest>,
     pub server_request_tx: Sender<ServerRequest>,
 }
 
 // This is mainly used to avoid having to much arguments in function definition
 pub struct PeerListenerReceivers {
     pub peer_connection_rx: Receiver<PeerConnectionRequest>,
-    pub possible_parent_rx: Receiver<Vec<Peer>>,
+    pub distributed_rx: Receiver<ServerResponse>,
+    pub distributed_events_rx: Receiver<DistributedEvent>,
     pub peer_request_rx: Receiver<(String, PeerRequestPacket)>,
     pub peer_address_rx: Receiver<PeerAddress>,
+    pub cant_connect_rx: Receiver<PeerConnectionTicket>,
 }
 
 impl GlobalConnectionHandler {
-    async fn run(&mut self, mut receivers: PeerListenerReceivers) -> crate::Result<()> {
+    async fn run(&mut self, receivers: PeerListenerReceivers) -> crate::Result<()> {
         let server_request_tx = self.senders.server_request_tx.clone();
         let sse_tx = self.senders.sse_tx.clone();
         let channels = self.channels.clone();
@@ -83,13 +84,29 @@ impl GlobalConnectionHandler {
             ready_rx,
             queue_rx: receivers.peer_request_rx,
             peer_address_rx: receivers.peer_address_rx,
+            cant_connect_rx: receivers.cant_connect_rx,
             channels: channels.clone(),
             db: db.clone(),
             shutdown_helper: shutdown_helper.clone(),
             sse_tx: sse_tx.clone(),
             ready_tx: ready_tx.clone(),
             server_request_tx: server_request_tx.clone(),
+            sse_server_tx: self.senders.sse_server_tx.clone(),
+            undelivered_tx: self.senders.undelivered_tx.clone(),
             message_queue: Default::default(),
+            dialing: Default::default(),
+        };
+
+        let mut distributed_network = DistributedNetwork {
+            server_rx: receivers.distributed_rx,
+            events_rx: receivers.distributed_events_rx,
+            server_request_tx: server_request_tx.clone(),
+            sse_tx: sse_tx.clone(),
+            ready_tx: ready_tx.clone(),
+            channels: channels.clone(),
+            shutdown_helper: shutdown_helper.clone(),
+            db: db.clone(),
+            branch: Branch::new(CONFIG.distributed.max_children),
         };
 
         let _ = tokio::join!(
@@ -109,15 +126,7 @@ impl GlobalConnectionHandler {
                 channels.clone(),
                 db.clone()
             ),
-            connect_to_parents(
-                server_request_tx,
-                sse_tx,
-                ready_tx,
-                channels,
-                shutdown_helper.clone(),
-                &mut receivers.possible_parent_rx,
-                db
-            )
+            distributed_network.run()
         );
 
         Ok(())
@@ -136,12 +145,11 @@ impl GlobalConnectionHandler {
             loop {
                 match self.peer_listener.accept().await {
                     Ok(socket) => {
-                        self.shutdown_helper
-                            .limit_connections
-                            .acquire()
-                            .await
-                            .unwrap()
-                            .forget();
+                        acquire_connection_permit(
+                            &self.shutdown_helper.limit_connections,
+                            &channels,
+                        )
+                        .await;
 
                         debug!(
                             "Incoming direct connection from {:?} accepted",
@@ -151,7 +159,7 @@ impl GlobalConnectionHandler {
                         debug!(
                             "Available connections : {}/{}",
                             self.shutdown_helper.limit_connections.available_permits(),
-                            MAX_CONNECTIONS
+                            CONFIG.connections.max_connections
                         );
 
                         let address = socket.peer_addr()?;
@@ -168,6 +176,7 @@ impl GlobalConnectionHandler {
                             _shutdown_complete: self.shutdown_helper.shutdown_complete_tx.clone(),
                             connection_states: channels.clone(),
                             db: db.clone(),
+                            settings: &CONFIG,
                             address,
                         };
 
@@ -217,7 +226,7 @@ pub async fn run(
     let shutdown_helper = ShutdownHelper {
         notify_shutdown,
         shutdown_complete_tx,
-        limit_connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+        limit_connections: Arc::new(Semaphore::new(CONFIG.connections.max_connections)),
     };
 
     // Initialize the listener state
@@ -320,12 +329,7 @@ async fn listen_indirect_peer_connection_request(
             continue;
         };
 
-        shutdown_helper
-            .limit_connections
-            .acquire()
-            .await
-            .unwrap()
-            .forget();
+        acquire_connection_permit(&shutdown_helper.limit_connections, &channels).await;
 
         debug!(
             "Available permit : {:?}",
@@ -346,8 +350,8 @@ async fn listen_indirect_peer_connection_request(
         let conn_type = connection_request.connection_type;
 
         channels
-            .clone()
-            .insert_indirect_connection_expected(&username, conn_type, token);
+            .insert_indirect_connection_expected(&username, conn_type, token)
+            .await;
 
         let connection_result = prepare_direct_connection_to_peer(
             channels.clone(),
@@ -379,48 +383,6 @@ async fn listen_indirect_peer_connection_request(
     Ok(())
 }
 
-async fn connect_to_parents(
-    request_peer_connection_tx: mpsc::Sender<ServerRequest>,
-    sse_tx: mpsc::Sender<PeerResponse>,
-    ready_tx: mpsc::Sender<u32>,
-    channels: SenderPool,
-    shutdown_helper: ShutdownHelper,
-    possible_parent_rx: &mut Receiver<Vec<Peer>>,
-    database: Database,
-) -> Result<(), soulseek_protocol::Error> {
-    loop {
-        while let Some(parents) = possible_parent_rx.recv().await {
-            for parent in parents {
-                let parent = PeerEntity::from(parent);
-                let parent_count = channels.get_parent_count();
-                debug!("Connected to {}/{} parents", parent_count, MAX_PARENT);
-
-                if parent_count >= MAX_PARENT {
-                    info!("Max parent count reached");
-                    let server_request_sender = request_peer_connection_tx.clone();
-                    server_request_sender
-                        .send(ServerRequest::NoParents(false))
-                        .await?;
-
-                    return Ok(());
-                };
-
-                connect_to_peer_with_fallback(
-                    request_peer_connection_tx.clone(),
-                    sse_tx.clone(),
-                    ready_tx.clone(),
-                    channels.clone(),
-                    shutdown_helper.clone(),
-                    database.clone(),
-                    &parent,
-                    ConnectionType::DistributedNetwork,
-                )
-                .await?;
-            }
-        }
-    }
-}
-
 // Try to connect directly to a peer and fallback to indirect connection
 // if direct connection fails
 pub(crate) async fn connect_to_peer_with_fallback(
@@ -433,12 +395,7 @@ pub(crate) async fn connect_to_peer_with_fallback(
     peer: &PeerEntity,
     conn_type: ConnectionType,
 ) -> Result<()> {
-    shutdown_helper
-        .limit_connections
-        .acquire()
-        .await
-        .unwrap()
-        .forget();
+    acquire_connection_permit(&shutdown_helper.limit_connections, &channels).await;
 
     debug!(
         "Available permit : {:?}",
@@ -453,7 +410,7 @@ pub(crate) async fn connect_to_peer_with_fallback(
         ready_tx.clone(),
         shutdown_helper.clone(),
         peer.clone(),
-        database,
+        database.clone(),
     )
     .and_then(|handler| connect_direct(handler, conn_type))
     .or_else(|e| {
@@ -462,6 +419,14 @@ pub(crate) async fn connect_to_peer_with_fallback(
             peer, e
         );
 
+        // The address might be outdated, ask the server again next time
+        if let Err(err) = database.remove(peer) {
+            error!(
+                "Failed to remove {} address from the database : {}",
+                peer.username, err
+            );
+        }
+
         request_indirect_connection(
             request_peer_connection_tx.clone(),
             channels.clone(),
@@ -477,14 +442,16 @@ pub(crate) async fn connect_to_peer_with_fallback(
 // before upgrading the connection
 async fn request_indirect_connection(
     request_peer_connection_tx: Sender<ServerRequest>,
-    mut channels: SenderPool,
+    channels: SenderPool,
     peer: &PeerEntity,
     conn_type: ConnectionType,
 ) -> Result<()> {
     let token = random();
 
     // Save the channel state so we can later create the handler with the correct connection type
-    channels.insert_indirect_connection_expected(&peer.username, conn_type, token);
+    channels
+        .insert_indirect_connection_expected(&peer.username, conn_type, token)
+        .await;
 
     info!("Falling back to indirect connection with token {}", token);
 
@@ -521,6 +488,7 @@ async fn prepare_direct_connection_to_peer(
             _shutdown_complete: shutdown_helper.shutdown_complete_tx.clone(),
             connection_states: channels,
             db,
+            settings: &CONFIG,
             address,
         }),
         Ok(Err(e)) => Err(eyre!(
@@ -555,6 +523,7 @@ async fn prepare_direct_connection_to_peer_with_fallback(
             _shutdown_complete: shutdown_helper.shutdown_complete_tx.clone(),
             connection_states: channels,
             db,
+            settings: &CONFIG,
             address,
         }),
         Ok(Err(e)) => Err(eyre!(
@@ -565,3 +534,21 @@ async fn prepare_direct_connection_to_peer_with_fallback(
         Err(e) => Err(e.into()),
     }
 }
+
+// When the connection limit is reached, close the least recently used peer connection and wait
+// for its permit to be released
+async fn acquire_connection_permit(limit_connections: &Semaphore, channels: &SenderPool) {
+    match limit_connections.try_acquire() {
+        Ok(permit) => permit.forget(),
+        Err(_) => {
+            if let Some(evicted) = channels.evict_idle_connection().await {
+                info!(
+                    "Connection limit reached, closing idle connection with {}",
+                    evicted.username
+                );
+            }
+
+            limit_connections.acquire().await.unwrap().forget();
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod channels;
 pub mod connection;
 pub mod dispatcher;
+pub mod distributed;
 pub mod handler;
 pub mod listener;
+pub mod queue;
 pub mod shutdown;
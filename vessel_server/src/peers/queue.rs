@@ -0,0 +1,190 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use soulseek_protocol::{message_common::ConnectionType, peers::PeerRequestPacket};
+
+/// Requests waiting for a connection with a peer, one queue per peer and connection type.
+/// Requests are delivered in the order they were queued.
+#[derive(Debug, Default)]
+pub(crate) struct OutboundQueue {
+    queues: HashMap<(String, ConnectionType), VecDeque<QueuedRequest>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct QueuedRequest {
+    pub(crate) request: PeerRequestPacket,
+    pub(crate) queued_at: Instant,
+}
+
+impl OutboundQueue {
+    /// Queue a request, returns the type of connection needed to deliver it.
+    pub(crate) fn push(&mut self, username: &str, request: PeerRequestPacket) -> ConnectionType {
+        let conn_type = ConnectionType::from(&request);
+        self.queues
+            .entry((username.to_string(), conn_type))
+            .or_default()
+            .push_back(QueuedRequest {
+                request,
+                queued_at: Instant::now(),
+            });
+
+        conn_type
+    }
+
+    /// Oldest request waiting for this connection.
+    pub(crate) fn pop(
+        &mut self,
+        username: &str,
+        conn_type: ConnectionType,
+    ) -> Option<QueuedRequest> {
+        let key = (username.to_string(), conn_type);
+        let queue = self.queues.get_mut(&key)?;
+        let request = queue.pop_front();
+
+        if queue.is_empty() {
+            self.queues.remove(&key);
+        }
+
+        request
+    }
+
+    /// Put back a request that could not be sent, it keeps its place and age.
+    pub(crate) fn push_front(
+        &mut self,
+        username: &str,
+        conn_type: ConnectionType,
+        request: QueuedRequest,
+    ) {
+        self.queues
+            .entry((username.to_string(), conn_type))
+            .or_default()
+            .push_front(request);
+    }
+
+    /// Connection types with requests waiting for this peer.
+    pub(crate) fn connection_types(&self, username: &str) -> Vec<ConnectionType> {
+        self.queues
+            .keys()
+            .filter(|(user, _)| user == username)
+            .map(|(_, conn_type)| *conn_type)
+            .collect()
+    }
+
+    /// Remove every request waiting for this connection.
+    pub(crate) fn remove(
+        &mut self,
+        username: &str,
+        conn_type: ConnectionType,
+    ) -> Vec<PeerRequestPacket> {
+        self.queues
+            .remove(&(username.to_string(), conn_type))
+            .map(|queue| queue.into_iter().map(|queued| queued.request).collect())
+            .unwrap_or_default()
+    }
+
+    /// Remove and return requests queued for longer than `ttl`.
+    pub(crate) fn expire(&mut self, ttl: Duration) -> Vec<(String, PeerRequestPacket)> {
+        let mut expired = vec![];
+
+        for ((username, _), queue) in self.queues.iter_mut() {
+            // Requests are queued in order, expired ones are at the front
+            while queue
+                .front()
+                .is_some_and(|queued| queued.queued_at.elapsed() >= ttl)
+            {
+                if let Some(queued) = queue.pop_front() {
+                    expired.push((username.clone(), queued.request));
+                }
+            }
+        }
+
+        self.queues.retain(|_, queue| !queue.is_empty());
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use soulseek_protocol::{
+        message_common::ConnectionType,
+        peers::{
+            distributed::DistributedMessage,
+            p2p::{request::PeerRequest, transfer::QueueUpload},
+            PeerRequestPacket,
+        },
+    };
+
+    use crate::peers::queue::OutboundQueue;
+
+    fn queue_upload(file_name: &str) -> PeerRequestPacket {
+        PeerRequestPacket::Message(PeerRequest::QueueUpload(QueueUpload {
+            file_name: file_name.to_string(),
+        }))
+    }
+
+    fn file_name(request: PeerRequestPacket) -> String {
+        match request {
+            PeerRequestPacket::Message(PeerRequest::QueueUpload(queue_upload)) => {
+                queue_upload.file_name
+            }
+            other => panic!("Unexpected request {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_deliver_requests_in_order() {
+        let mut queue = OutboundQueue::default();
+        queue.push("alice", queue_upload("first"));
+        queue.push("alice", queue_upload("second"));
+        queue.push("bob", queue_upload("other"));
+
+        let first = queue.pop("alice", ConnectionType::PeerToPeer).unwrap();
+        assert_eq!(file_name(first.request), "first");
+
+        let second = queue.pop("alice", ConnectionType::PeerToPeer).unwrap();
+        queue.push_front("alice", ConnectionType::PeerToPeer, second);
+        let second = queue.pop("alice", ConnectionType::PeerToPeer).unwrap();
+        assert_eq!(file_name(second.request), "second");
+
+        assert!(queue.pop("alice", ConnectionType::PeerToPeer).is_none());
+        assert_eq!(queue.connection_types("alice"), vec![]);
+        assert_eq!(
+            queue.connection_types("bob"),
+            vec![ConnectionType::PeerToPeer]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_queue_per_connection_type() {
+        let mut queue = OutboundQueue::default();
+        let conn_type = queue.push(
+            "alice",
+            PeerRequestPacket::DistributedMessage(DistributedMessage::Ping),
+        );
+        queue.push("alice", queue_upload("file"));
+
+        assert_eq!(conn_type, ConnectionType::DistributedNetwork);
+        assert_eq!(queue.remove("alice", ConnectionType::PeerToPeer).len(), 1);
+        assert!(queue.pop("alice", ConnectionType::PeerToPeer).is_none());
+        assert!(queue
+            .pop("alice", ConnectionType::DistributedNetwork)
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn should_expire_old_requests() {
+        let mut queue = OutboundQueue::default();
+        queue.push("alice", queue_upload("file"));
+
+        assert!(queue.expire(Duration::from_secs(60)).is_empty());
+
+        let expired = queue.expire(Duration::from_secs(0));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, "alice");
+        assert!(queue.connection_types("alice").is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time,
+};
+
+use soulseek_protocol::peers::{
+    p2p::{request::PeerRequest, transfer::QueueUpload},
+    PeerRequestPacket,
+};
+use vessel_database::{
+    entity::download_job::{DownloadJobEntity, DownloadJobState},
+    Database,
+};
+
+/// New jobs are picked up and queued sources checked for timeout on this interval.
+const DOWNLOAD_JOB_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a peer did with a file we queued, reported by the peer handlers.
+#[derive(Debug)]
+pub enum SourceEvent {
+    /// The peer is about to upload the file
+    Uploading { username: String, file_name: String },
+    /// The peer refused to queue the file or failed to upload it
+    Failed {
+        username: String,
+        file_name: String,
+        reason: String,
+    },
+}
+
+/// Queue the best source of every download job and fall over to the next one when it refuses the
+/// file, fails to upload it or does not start uploading before `source_timeout`.
+pub struct DownloadJobScheduler {
+    pub(crate) db: Database,
+    pub(crate) event_rx: Receiver<SourceEvent>,
+    // Queue downloads via the peer message dispatcher
+    pub(crate) peer_request_tx: Sender<(String, PeerRequestPacket)>,
+    pub(crate) source_timeout: Duration,
+}
+
+impl DownloadJobScheduler {
+    pub async fn run(&mut self) {
+        let mut check = time::interval(DOWNLOAD_JOB_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = check.tick() => self.check_jobs().await,
+                Some(event) = self.event_rx.recv() => self.on_source_event(event),
+                else => break,
+            }
+        }
+    }
+
+    async fn check_jobs(&self) {
+        for mut job in self.db.get_all::<DownloadJobEntity>() {
+            if job.is_timed_out(self.source_timeout) {
+                info!(
+                    "No upload of {} after {:?}, trying next source",
+                    job.file_name, self.source_timeout
+                );
+                job.fail_current("Timed out");
+            }
+
+            if job.state == DownloadJobState::Pending {
+                self.queue_next(&mut job).await;
+            }
+        }
+    }
+
+    async fn queue_next(&self, job: &mut DownloadJobEntity) {
+        let next = job
+            .queue_next()
+            .map(|source| (source.username.clone(), source.file_name.clone()));
+
+        match next {
+            Some((username, file_name)) => {
+                info!("Queuing {} from {}", file_name, username);
+
+                let request =
+                    PeerRequestPacket::Message(PeerRequest::QueueUpload(QueueUpload { file_name }));
+
+                // The job is still pending in the database and will be retried
+                if let Err(err) = self.peer_request_tx.send((username, request)).await {
+                    return error!("Unable to queue download: {}", err);
+                }
+            }
+            None => warn!("No source left to download {}", job.file_name),
+        }
+
+        self.store(job);
+    }
+
+    fn on_source_event(&self, event: SourceEvent) {
+        let (username, file_name) = match &event {
+            SourceEvent::Uploading {
+                username,
+                file_name,
+            }
+            | SourceEvent::Failed {
+                username,
+                file_name,
+                ..
+            } => (username, file_name),
+        };
+
+        let job = self
+            .db
+            .get_all::<DownloadJobEntity>()
+            .into_iter()
+            .find(|job| job.is_current_source(username, file_name));
+
+        let mut job = match job {
+            Some(job) => job,
+            // Not a download started from a search
+            None => return,
+        };
+
+        match &event {
+            SourceEvent::Uploading { .. } => job.transferring(),
+            SourceEvent::Failed { reason, .. } => {
+                info!(
+                    "{} failed to send {}, reason : {}, trying next source",
+                    username, file_name, reason
+                );
+                job.fail_current(reason);
+            }
+        }
+
+        self.store(&job);
+    }
+
+    fn store(&self, job: &DownloadJobEntity) {
+        if let Err(err) = self.db.insert(job) {
+            error!("Failed to store download job {}: {}", job.id, err);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use tokio::{sync::mpsc::Sender, time};
+
+use soulseek_protocol::{
+    peers::p2p::download::DownloadProgress,
+    server::{request::ServerRequest, search::SearchRequest},
+};
+use vessel_database::{
+    entity::{
+        import::{ImportEntity, ImportEntryEntity, ImportStatus, MIN_QUEUE_SCORE},
+        search::SearchEntity,
+    },
+    Database,
+};
+
+/// Imports are searched one entry at a time on this interval, the server does not like being
+/// flooded with searches.
+const IMPORT_SEARCH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Replies keep coming for a while after a search, entries are scored after this delay.
+const IMPORT_SCORING_DELAY: Duration = Duration::from_secs(60);
+
+const IMPORT_SCORING_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Search the entries of the imported playlists and pick the best reply for each of them, imports
+/// with `auto_queue` get their confident matches queued for download.
+pub struct ImportScheduler {
+    pub(crate) db: Database,
+    pub(crate) server_request_tx: Sender<ServerRequest>,
+    // Entry status changes are advertised to the SSE clients
+    pub(crate) progress_tx: Sender<DownloadProgress>,
+}
+
+impl ImportScheduler {
+    pub async fn run(&mut self) {
+        let mut search_interval = time::interval(IMPORT_SEARCH_INTERVAL);
+        let mut scoring_check = time::interval(IMPORT_SCORING_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = search_interval.tick() => self.search_next().await,
+                _ = scoring_check.tick() => self.score_searched().await,
+            }
+        }
+    }
+
+    fn imports(&self) -> Vec<ImportEntity> {
+        let mut imports = self.db.get_all::<ImportEntity>();
+        imports.sort_by_key(|import| import.created_at);
+        imports
+    }
+
+    async fn search_next(&self) {
+        let next = self.imports().into_iter().find_map(|import| {
+            import
+                .entries(&self.db)
+                .into_iter()
+                .find(|entry| entry.status == ImportStatus::Pending)
+        });
+
+        let mut entry = match next {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let ticket = rand::random();
+        let query = entry.query();
+        entry.searched(ticket);
+
+        if let Err(err) = self
+            .db
+            .insert(&SearchEntity::for_import(ticket, &query, entry.import_id))
+            .and_then(|_| self.db.insert(&entry))
+        {
+            return error!("Failed to store import search for {:?}: {}", query, err);
+        }
+
+        debug!("Searching {:?} for import {}", query, entry.import_id);
+        let request = ServerRequest::FileSearch(SearchRequest { ticket, query });
+        if let Err(err) = self.server_request_tx.send(request).await {
+            return error!("Unable to send import search: {}", err);
+        }
+
+        self.notify(&entry).await;
+    }
+
+    async fn score_searched(&self) {
+        for import in self.imports() {
+            for mut entry in import.entries(&self.db) {
+                if !entry.awaits_scoring(IMPORT_SCORING_DELAY) {
+                    continue;
+                }
+
+                entry.score_results(&self.db);
+
+                let confident = entry
+                    .best_match
+                    .as_ref()
+                    .is_some_and(|best_match| best_match.score >= MIN_QUEUE_SCORE);
+
+                if import.auto_queue && confident {
+                    if let Err(err) = entry.queue_best_match(&self.db, rand::random()) {
+                        error!("Failed to queue {:?}: {}", entry.track.title, err);
+                    }
+                }
+
+                if let Err(err) = self.db.insert(&entry) {
+                    error!("Failed to store import entry: {}", err);
+                    continue;
+                }
+
+                self.notify(&entry).await;
+            }
+        }
+    }
+
+    async fn notify(&self, entry: &ImportEntryEntity) {
+        let progress = DownloadProgress::ImportProgress {
+            import_id: entry.import_id,
+            index: entry.index,
+            title: entry.track.title.clone(),
+            status: entry.status.as_str().to_string(),
+        };
+
+        if let Err(err) = self.progress_tx.send(progress).await {
+            error!("Error sending import progress to SSE clients : {}", err);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub(crate) mod download;
+pub(crate) mod import;
+pub(crate) mod responder;
+pub(crate) mod scheduler;
+pub(crate) mod wishlist;
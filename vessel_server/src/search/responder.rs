@@ -0,0 +1,153 @@
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use soulseek_protocol::{
+    peers::{
+        p2p::{request::PeerRequest, search::SearchReply},
+        PeerRequestPacket,
+    },
+    query::Query,
+    server::search::{ExcludedSearchPhrases, SearchQuery},
+};
+use vessel_database::{
+    entity::{shared_dirs::search_shared_files, upload::UploadStats},
+    settings::CONFIG,
+    Database,
+};
+
+/// Maximum number of files sent back in a single search reply
+const MAX_SEARCH_RESULTS: usize = 500;
+
+/// Answer incoming searches, either received from our distributed parent or sent by the server
+/// for user and room searches, with the shared files matching the query.
+pub struct SearchResponder {
+    pub(crate) db: Database,
+    // Incoming search requests from the server and distributed connections
+    pub(crate) search_rx: Receiver<SearchQuery>,
+    // Phrases the server does not want to see in search results
+    pub(crate) excluded_phrases_rx: Receiver<ExcludedSearchPhrases>,
+    // Send search replies to the peer message dispatcher
+    pub(crate) peer_request_tx: Sender<(String, PeerRequestPacket)>,
+    pub(crate) excluded_phrases: ExcludedSearchPhrases,
+}
+
+impl SearchResponder {
+    pub async fn run(&mut self) {
+        // Disabled once the sender is gone, the last phrases are kept
+        let mut excluded_phrases_open = true;
+
+        loop {
+            tokio::select! {
+                search = self.search_rx.recv() => match search {
+                    Some(search) => self.on_search_received(search).await,
+                    None => break,
+                },
+                excluded_phrases = self.excluded_phrases_rx.recv(), if excluded_phrases_open => {
+                    match excluded_phrases {
+                        Some(excluded_phrases) => {
+                            debug!("Updating excluded search phrases : {:?}", excluded_phrases);
+                            self.excluded_phrases = excluded_phrases;
+                        }
+                        None => excluded_phrases_open = false,
+                    }
+                }
+            }
+        }
+    }
+
+    async fn on_search_received(&mut self, search: SearchQuery) {
+        // Filter out our own searches
+        if search.username == CONFIG.username {
+            return;
+        }
+
+        let query = Query::parse(&search.query);
+        if query.is_empty() {
+            return;
+        }
+
+        // Files in restricted shares are still advertised, as locked results
+        let (files, locked_results) = search_shared_files(
+            &self.db,
+            &CONFIG,
+            &query,
+            &self.excluded_phrases,
+            &search.username,
+            MAX_SEARCH_RESULTS,
+        );
+        if files.is_empty() && locked_results.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Found {} results and {} locked results for search {:?} from {}",
+            files.len(),
+            locked_results.len(),
+            search.query,
+            search.username
+        );
+
+        let upload_stats = UploadStats::current(CONFIG.upload_slots);
+        let reply = SearchReply {
+            username: CONFIG.username.clone(),
+            ticket: search.ticket,
+            files,
+            slot_free: upload_stats.slots_free,
+            average_speed: 0,
+            queue_length: upload_stats.queue_size,
+            locked_results,
+        };
+
+        if let Err(err) = self
+            .peer_request_tx
+            .send((
+                search.username,
+                PeerRequestPacket::Message(PeerRequest::SearchReply(reply)),
+            ))
+            .await
+        {
+            error!("Error sending search reply to peer dispatcher: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{sync::mpsc, time::timeout};
+
+    use soulseek_protocol::server::search::ExcludedSearchPhrases;
+    use vessel_database::Database;
+
+    use crate::search::responder::SearchResponder;
+
+    #[tokio::test]
+    async fn should_stop_once_searches_are_over() {
+        let (search_tx, search_rx) = mpsc::channel(8);
+        let (excluded_phrases_tx, excluded_phrases_rx) = mpsc::channel(8);
+        let (peer_request_tx, _peer_request_rx) = mpsc::channel(8);
+
+        let mut responder = tokio::spawn(async move {
+            SearchResponder {
+                db: Database::temporary(),
+                search_rx,
+                excluded_phrases_rx,
+                peer_request_tx,
+                excluded_phrases: ExcludedSearchPhrases::default(),
+            }
+            .run()
+            .await
+        });
+
+        // Still waiting for searches without the excluded phrases
+        drop(excluded_phrases_tx);
+        assert!(timeout(Duration::from_millis(100), &mut responder)
+            .await
+            .is_err());
+
+        drop(search_tx);
+        assert!(timeout(Duration::from_millis(100), &mut responder)
+            .await
+            .is_ok());
+    }
+}
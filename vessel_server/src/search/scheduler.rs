@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::{self, Instant},
+};
+
+use soulseek_protocol::server::{request::ServerRequest, search::QueuedSearch};
+use vessel_database::settings::SearchSettings;
+
+/// Waited for when no search is queued, the scheduler wakes up on incoming searches anyway.
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+
+/// Send file, user, room and wishlist searches to the Soulseek server within the configured
+/// limits. Searches over the limit are queued and their position reported to the SSE clients.
+pub struct SearchScheduler {
+    pub(crate) request_rx: Receiver<ServerRequest>,
+    // Searches allowed to go through, written to the server connection
+    pub(crate) server_tx: Sender<ServerRequest>,
+    pub(crate) queued_tx: Sender<QueuedSearch>,
+    pub(crate) limits: SearchSettings,
+}
+
+impl SearchScheduler {
+    pub async fn run(&mut self) {
+        let mut bucket = TokenBucket::new(self.limits, Instant::now());
+        let mut queue = VecDeque::new();
+
+        loop {
+            let wait = if queue.is_empty() {
+                IDLE_WAIT
+            } else {
+                bucket.next_token_in(Instant::now())
+            };
+
+            tokio::select! {
+                request = self.request_rx.recv() => match request {
+                    Some(request) => queue.push_back(request),
+                    None => break,
+                },
+                _ = time::sleep(wait) => {},
+            }
+
+            if let Err(err) = self.send_allowed(&mut bucket, &mut queue).await {
+                return error!("Search scheduler stopped: {}", err);
+            }
+        }
+    }
+
+    /// Send as many queued searches as the limits allow and report the position of the others.
+    async fn send_allowed(
+        &self,
+        bucket: &mut TokenBucket,
+        queue: &mut VecDeque<ServerRequest>,
+    ) -> eyre::Result<()> {
+        let queued = queue.len();
+
+        while !queue.is_empty() && bucket.try_take(Instant::now()) {
+            let request = queue.pop_front().expect("queue is not empty");
+            let ticket = request.search_ticket();
+
+            self.server_tx.send(request).await?;
+
+            if let Some(ticket) = ticket {
+                self.report(ticket, None).await?;
+            }
+        }
+
+        let sent = queued - queue.len();
+        let is_new = |position: usize| position + 1 == queue.len();
+
+        // Positions only change when a search is sent, otherwise only the new search is reported
+        for (position, request) in queue.iter().enumerate() {
+            if sent > 0 || is_new(position) {
+                if let Some(ticket) = request.search_ticket() {
+                    self.report(ticket, Some(position)).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn report(&self, ticket: u32, position: Option<usize>) -> eyre::Result<()> {
+        self.queued_tx
+            .send(QueuedSearch { ticket, position })
+            .await
+            .map_err(|err| eyre!("Error sending search position to SSE: {}", err))
+    }
+}
+
+/// Allows `burst` searches at once, then one every `60 / per_minute` seconds.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limits: SearchSettings, now: Instant) -> Self {
+        let capacity = f64::from(limits.burst.max(1));
+
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            per_second: f64::from(limits.per_minute.max(1)) / 60.0,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Time until a search can be sent.
+    fn next_token_in(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.per_second)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{sync::mpsc, time::Instant};
+
+    use soulseek_protocol::server::{request::ServerRequest, search::SearchRequest};
+    use vessel_database::settings::SearchSettings;
+
+    use crate::search::scheduler::{SearchScheduler, TokenBucket};
+
+    const LIMITS: SearchSettings = SearchSettings {
+        per_minute: 6,
+        burst: 2,
+    };
+
+    fn search(ticket: u32) -> ServerRequest {
+        ServerRequest::FileSearch(SearchRequest {
+            ticket,
+            query: "nirvana".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn should_allow_bursts_then_limit() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(LIMITS, start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert_eq!(bucket.next_token_in(start), Duration::from_secs(10));
+
+        let later = start + Duration::from_secs(10);
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+
+        // Tokens do not pile up over the burst size
+        let much_later = later + Duration::from_secs(3600);
+        assert!(bucket.try_take(much_later));
+        assert!(bucket.try_take(much_later));
+        assert!(!bucket.try_take(much_later));
+    }
+
+    #[tokio::test]
+    async fn should_queue_searches_over_the_limit() {
+        let (request_tx, request_rx) = mpsc::channel(8);
+        let (server_tx, mut server_rx) = mpsc::channel(8);
+        let (queued_tx, mut queued_rx) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            SearchScheduler {
+                request_rx,
+                server_tx,
+                queued_tx,
+                // One search every 100ms
+                limits: SearchSettings {
+                    per_minute: 600,
+                    burst: 2,
+                },
+            }
+            .run()
+            .await
+        });
+
+        for ticket in 1..=3 {
+            request_tx.send(search(ticket)).await.unwrap();
+        }
+
+        let start = Instant::now();
+        for ticket in 1..=3 {
+            let request = server_rx.recv().await.unwrap();
+            assert_eq!(request.search_ticket(), Some(ticket));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let mut reports = vec![];
+        while reports.len() < 4 {
+            let queued = queued_rx.recv().await.unwrap();
+            reports.push((queued.ticket, queued.position));
+        }
+        assert_eq!(reports, vec![(1, None), (2, None), (3, Some(0)), (3, None)]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::{self, Instant},
+};
+
+use soulseek_protocol::{
+    peers::{
+        p2p::{request::PeerRequest, search::SearchReply, transfer::QueueUpload},
+        PeerRequestPacket,
+    },
+    server::{request::ServerRequest, search::SearchRequest},
+};
+use vessel_database::{
+    entity::{search::SearchEntity, wishlist::WishlistEntity},
+    Database,
+};
+
+/// Used until the server sends us the wishlist interval after login.
+const DEFAULT_WISHLIST_INTERVAL: Duration = Duration::from_secs(720);
+
+/// Replies keep coming for a while after a search, the best match is picked after this delay.
+const AUTO_QUEUE_DELAY: Duration = Duration::from_secs(60);
+
+const AUTO_QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Search every wishlist entry on the interval given by the server and record the matching files,
+/// entries with `auto_queue` get their best match queued for download.
+pub struct WishlistScheduler {
+    pub(crate) db: Database,
+    // Wishlist search interval in seconds, sent by the server after login
+    pub(crate) interval_rx: Receiver<u32>,
+    // Filtered search replies for wishlist searches, with the entry id
+    pub(crate) reply_rx: Receiver<(u32, SearchReply)>,
+    pub(crate) server_request_tx: Sender<ServerRequest>,
+    // Queue auto downloads via the peer message dispatcher
+    pub(crate) peer_request_tx: Sender<(String, PeerRequestPacket)>,
+}
+
+impl WishlistScheduler {
+    pub async fn run(&mut self) {
+        let mut search_interval = time::interval(DEFAULT_WISHLIST_INTERVAL);
+        let mut auto_queue_check = time::interval(AUTO_QUEUE_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = search_interval.tick() => self.search_all().await,
+                _ = auto_queue_check.tick() => self.auto_queue().await,
+                Some(interval) = self.interval_rx.recv() => {
+                    info!("Wishlist searches every {} seconds", interval);
+                    let period = Duration::from_secs(u64::from(interval.max(1)));
+                    search_interval = time::interval_at(Instant::now() + period, period);
+                }
+                Some((wishlist_id, reply)) = self.reply_rx.recv() => {
+                    self.on_search_reply(wishlist_id, &reply);
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn search_all(&self) {
+        for mut wishlist in self.db.get_all::<WishlistEntity>() {
+            let ticket = rand::random();
+
+            if let Err(err) = wishlist.forget_search(&self.db) {
+                warn!("Failed to remove previous wishlist search: {}", err);
+            }
+
+            let search = SearchEntity::for_wishlist(ticket, &wishlist.query, wishlist.id);
+            wishlist.searched(ticket);
+
+            if let Err(err) = self
+                .db
+                .insert(&search)
+                .and_then(|_| self.db.insert(&wishlist))
+            {
+                error!(
+                    "Failed to store wishlist search for {:?}: {}",
+                    wishlist.query, err
+                );
+                continue;
+            }
+
+            let request = ServerRequest::WishlistSearch(SearchRequest {
+                ticket,
+                query: wishlist.query,
+            });
+
+            if let Err(err) = self.server_request_tx.send(request).await {
+                return error!("Unable to send wishlist search: {}", err);
+            }
+        }
+    }
+
+    fn on_search_reply(&self, wishlist_id: u32, reply: &SearchReply) {
+        let mut wishlist = match self
+            .db
+            .get_by_key::<WishlistEntity>(&wishlist_id.to_string())
+        {
+            Some(wishlist) => wishlist,
+            // The entry was removed in the meantime
+            None => return,
+        };
+
+        // A reply to a previous search, possibly for another query
+        if wishlist.ticket != Some(reply.ticket) {
+            return debug!("Ignoring late reply to wishlist search {}", reply.ticket);
+        }
+
+        let added = wishlist.record_matches(reply);
+        if added == 0 {
+            return;
+        }
+
+        debug!(
+            "{} new matches from {} for wishlist {:?}",
+            added, reply.username, wishlist.query
+        );
+
+        if let Err(err) = self.db.insert(&wishlist) {
+            error!("Failed to store wishlist matches: {}", err);
+        }
+    }
+
+    async fn auto_queue(&self) {
+        for mut wishlist in self.db.get_all::<WishlistEntity>() {
+            if !wishlist.awaits_auto_queue(AUTO_QUEUE_DELAY) {
+                continue;
+            }
+
+            let (username, file_name) = match wishlist.best_match() {
+                Some(best) => (best.username.clone(), best.file_name.clone()),
+                None => continue,
+            };
+
+            info!(
+                "Queuing {} from {} for wishlist {:?}",
+                file_name, username, wishlist.query
+            );
+
+            let request = PeerRequestPacket::Message(PeerRequest::QueueUpload(QueueUpload {
+                file_name: file_name.clone(),
+            }));
+
+            if let Err(err) = self.peer_request_tx.send((username.clone(), request)).await {
+                return error!("Unable to queue wishlist download: {}", err);
+            }
+
+            wishlist.mark_queued(&username, &file_name);
+            if let Err(err) = self.db.insert(&wishlist) {
+                error!("Failed to store wishlist download: {}", err);
+            }
+        }
+    }
+}
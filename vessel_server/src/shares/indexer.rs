@@ -0,0 +1,159 @@
+use std::{path::PathBuf, time::Duration};
+
+use eyre::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        watch,
+    },
+    time::{timeout_at, Instant},
+};
+
+use soulseek_protocol::server::{login::SessionState, request::ServerRequest};
+use vessel_database::{
+    entity::shared_dirs::{
+        scan_shared_directories, shared_folders_and_files, update_shared_paths, ScanStats,
+    },
+    settings::CONFIG,
+    Database,
+};
+
+/// Filesystem events are batched during this delay before updating the share index, copying an
+/// album produces a lot of them.
+const WATCHER_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Keep the share index up to date and notify the Soulseek server about our share count. Shares
+/// are indexed right away, the count is only sent once logged in and again after each login.
+pub struct ShareIndexer {
+    pub(crate) db: Database,
+    // Full rescan requests coming from http
+    pub(crate) rescan_rx: Receiver<()>,
+    // Send SharedFoldersAndFiles to the Soulseek server
+    pub(crate) server_request_tx: Sender<ServerRequest>,
+    pub(crate) session_rx: watch::Receiver<SessionState>,
+}
+
+impl ShareIndexer {
+    pub async fn run(&mut self) {
+        self.rescan().await;
+
+        let (watcher_tx, mut watcher_rx) = mpsc::channel::<Vec<PathBuf>>(1024);
+
+        // Dropping the watcher stops it, keep it around for the whole task lifetime
+        let _watcher = match watch_shared_directories(watcher_tx) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(
+                    "Unable to watch shared directories, live updates are disabled: {}",
+                    err
+                );
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                Some(()) = self.rescan_rx.recv() => self.rescan().await,
+                Ok(()) = self.session_rx.changed() => {
+                    if self.is_logged_in() {
+                        self.send_shared_folders_and_files().await;
+                    }
+                }
+                Some(paths) = watcher_rx.recv() => {
+                    let paths = debounce(paths, &mut watcher_rx).await;
+                    self.update(paths).await;
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn rescan(&self) {
+        info!("Scanning shared directories");
+        let db = self.db.clone();
+        let result = tokio::task::spawn_blocking(move || scan_shared_directories(&db)).await;
+        self.on_index_updated(flatten(result)).await;
+    }
+
+    async fn update(&self, paths: Vec<PathBuf>) {
+        debug!("Updating share index for {:?}", paths);
+        let db = self.db.clone();
+        let result = tokio::task::spawn_blocking(move || update_shared_paths(&db, &paths)).await;
+        self.on_index_updated(flatten(result)).await;
+    }
+
+    async fn on_index_updated(&self, result: Result<ScanStats>) {
+        match result {
+            Ok(stats) => info!(
+                "Share index updated, {} files scanned, {} updated, {} removed",
+                stats.scanned, stats.updated, stats.removed
+            ),
+            Err(err) => {
+                return error!("Error updating share index: {}", err);
+            }
+        }
+
+        if self.is_logged_in() {
+            self.send_shared_folders_and_files().await;
+        }
+    }
+
+    fn is_logged_in(&self) -> bool {
+        *self.session_rx.borrow() == SessionState::LoggedIn
+    }
+
+    async fn send_shared_folders_and_files(&self) {
+        let shared_folders_and_files = shared_folders_and_files();
+        if let Err(err) = self
+            .server_request_tx
+            .send(ServerRequest::SharedFolderAndFiles(
+                shared_folders_and_files,
+            ))
+            .await
+        {
+            error!("Error sending shared folder and files to server: {}", err);
+        }
+    }
+}
+
+fn flatten<T>(result: Result<std::io::Result<T>, tokio::task::JoinError>) -> eyre::Result<T> {
+    Ok(result??)
+}
+
+/// Collect the paths reported by the watcher during [`WATCHER_DEBOUNCE`].
+async fn debounce(
+    mut paths: Vec<PathBuf>,
+    watcher_rx: &mut Receiver<Vec<PathBuf>>,
+) -> Vec<PathBuf> {
+    let deadline = Instant::now() + WATCHER_DEBOUNCE;
+
+    while let Ok(Some(more_paths)) = timeout_at(deadline, watcher_rx.recv()).await {
+        paths.extend(more_paths);
+    }
+
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn watch_shared_directories(
+    watcher_tx: Sender<Vec<PathBuf>>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                // The receiver is only gone when the indexer stops
+                let _ = watcher_tx.blocking_send(event.paths);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Share watcher error: {}", err),
+        }
+    })?;
+
+    for dir in &CONFIG.shared_directories {
+        watcher.watch(dir.path(), RecursiveMode::Recursive)?;
+    }
+
+    Ok(watcher)
+}
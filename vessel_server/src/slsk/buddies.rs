@@ -0,0 +1,135 @@
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use soulseek_protocol::server::{
+    request::ServerRequest,
+    response::ServerResponse,
+    user::{BuddyEvent, UserAdded},
+};
+use vessel_database::{entity::buddy::BuddyEntity, Database};
+
+/// Watch every buddy once logged in and keep their cached status and stats up to date, status and
+/// share count changes are advertised to the SSE clients.
+pub struct BuddyWatcher {
+    pub(crate) db: Database,
+    // `UserAdded`, `UserStatus` and `UserStats` responses from the Soulseek server
+    pub(crate) response_rx: Receiver<ServerResponse>,
+    pub(crate) server_request_tx: Sender<ServerRequest>,
+    pub(crate) event_tx: Sender<BuddyEvent>,
+}
+
+impl BuddyWatcher {
+    pub async fn run(&mut self) {
+        // Buddies added later via http are watched by the http route
+        for buddy in self.db.get_all::<BuddyEntity>() {
+            if let Err(err) = self
+                .server_request_tx
+                .send(ServerRequest::AddUser(buddy.username))
+                .await
+            {
+                return error!("Unable to watch buddies: {}", err);
+            }
+        }
+
+        while let Some(response) = self.response_rx.recv().await {
+            self.on_response(response).await;
+        }
+    }
+
+    async fn on_response(&self, response: ServerResponse) {
+        let username = match &response {
+            ServerResponse::UserAdded(UserAdded::Ok { username, .. })
+            | ServerResponse::UserAdded(UserAdded::NotFound { username }) => username,
+            ServerResponse::UserStatus(status) => &status.username,
+            ServerResponse::UserStats(stats) => &stats.username,
+            _ => return,
+        };
+
+        // Status and stats of users who are not buddies are only forwarded to SSE
+        let mut buddy = match self.db.get_by_key::<BuddyEntity>(username) {
+            Some(buddy) => buddy,
+            None => return,
+        };
+
+        let events = match &response {
+            ServerResponse::UserAdded(added) => buddy.on_user_added(added),
+            ServerResponse::UserStatus(status) => buddy.on_status(status),
+            ServerResponse::UserStats(stats) => buddy.on_stats(stats),
+            _ => return,
+        };
+
+        if let Err(err) = self.db.insert(&buddy) {
+            return error!("Failed to store buddy {}: {}", buddy.username, err);
+        }
+
+        for event in events {
+            if let Err(err) = self.event_tx.send(event).await {
+                error!("Error sending buddy event to SSE: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use soulseek_protocol::server::{
+        request::ServerRequest,
+        response::ServerResponse,
+        user::{BuddyEvent, Status, UserStatus},
+    };
+    use vessel_database::{entity::buddy::BuddyEntity, Database};
+
+    use crate::slsk::buddies::BuddyWatcher;
+
+    fn status(username: &str, status: Status) -> ServerResponse {
+        ServerResponse::UserStatus(UserStatus {
+            username: username.to_string(),
+            status,
+            privileged: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn should_watch_buddies_and_report_changes() {
+        let db = Database::temporary();
+        db.insert(&BuddyEntity::new("alice", "")).unwrap();
+
+        let (response_tx, response_rx) = mpsc::channel(8);
+        let (server_request_tx, mut server_request_rx) = mpsc::channel(8);
+        let (event_tx, mut event_rx) = mpsc::channel(8);
+
+        let mut watcher = BuddyWatcher {
+            db: db.clone(),
+            response_rx,
+            server_request_tx,
+            event_tx,
+        };
+        tokio::spawn(async move { watcher.run().await });
+
+        match server_request_rx.recv().await {
+            Some(ServerRequest::AddUser(username)) => assert_eq!(username, "alice"),
+            request => panic!("Unexpected request {:?}", request),
+        }
+
+        response_tx
+            .send(status("bob", Status::Online))
+            .await
+            .unwrap();
+        response_tx
+            .send(status("alice", Status::Away))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            event_rx.recv().await,
+            Some(BuddyEvent::Away {
+                username: "alice".to_string()
+            })
+        );
+
+        let alice = db.get_by_key::<BuddyEntity>("alice").unwrap();
+        assert_eq!(alice.status, Some(Status::Away));
+        assert!(db.get_by_key::<BuddyEntity>("bob").is_none());
+    }
+}
@@ -1 +1,3 @@
+pub(crate) mod buddies;
 pub(crate) mod connection;
+pub(crate) mod session;
@@ -0,0 +1,158 @@
+use eyre::Result;
+use tokio::sync::watch;
+
+use soulseek_protocol::server::login::{LoginResponse, SessionState};
+
+/// Our session with the Soulseek server. State changes are published to the tasks waiting for
+/// login and to the http and SSE clients.
+#[derive(Debug)]
+pub struct Session {
+    state_tx: watch::Sender<SessionState>,
+}
+
+impl Session {
+    pub fn new() -> (Self, watch::Receiver<SessionState>) {
+        let (state_tx, state_rx) = watch::channel(SessionState::default());
+        (Session { state_tx }, state_rx)
+    }
+
+    pub fn connecting(&self) {
+        self.transition(SessionState::Connecting);
+    }
+
+    pub fn logging_in(&self) {
+        self.transition(SessionState::LoggingIn);
+    }
+
+    pub fn on_login_response(&self, response: &LoginResponse) {
+        self.transition(SessionState::from(response));
+    }
+
+    pub fn disconnected(&self) {
+        self.transition(SessionState::Disconnected);
+    }
+
+    fn transition(&self, next: SessionState) {
+        let current = self.state_tx.borrow().clone();
+
+        if !is_valid_transition(&current, &next) {
+            warn!("Ignoring session transition {:?} -> {:?}", current, next);
+            return;
+        }
+
+        info!("Session state {:?} -> {:?}", current, next);
+        if self.state_tx.send(next).is_err() {
+            warn!("No task listening for session state changes");
+        }
+    }
+}
+
+fn is_valid_transition(current: &SessionState, next: &SessionState) -> bool {
+    use SessionState::*;
+
+    match (current, next) {
+        (_, Disconnected) => true,
+        (Disconnected | Failed(_), Connecting) => true,
+        // The connection is only made once for now, a new login reuses it
+        (Connecting | Failed(_), LoggingIn) => true,
+        (LoggingIn, LoggedIn | Failed(_)) => true,
+        _ => false,
+    }
+}
+
+/// Wait until we are logged in, fails only if the session is gone. A rejected login is not final,
+/// a new login request can still succeed.
+pub async fn logged_in(state_rx: &mut watch::Receiver<SessionState>) -> Result<()> {
+    loop {
+        match &*state_rx.borrow() {
+            SessionState::LoggedIn => return Ok(()),
+            SessionState::Failed(reason) => {
+                warn!("Login failed, waiting for a new login: {}", reason)
+            }
+            _ => {}
+        }
+
+        state_rx
+            .changed()
+            .await
+            .map_err(|_| eyre!("Session closed before login"))?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use tokio::time::timeout;
+
+    use soulseek_protocol::server::login::{LoginResponse, SessionState};
+
+    use crate::slsk::session::{logged_in, Session};
+
+    fn success() -> LoginResponse {
+        LoginResponse::Success {
+            greeting_message: "Welcome".to_string(),
+            user_ip: Ipv4Addr::LOCALHOST,
+            password_md5_digest: "digest".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_log_in() {
+        let (session, mut state_rx) = Session::new();
+        session.connecting();
+        session.logging_in();
+
+        let waiter = tokio::spawn(async move { logged_in(&mut state_rx).await });
+        session.on_login_response(&success());
+
+        assert!(waiter.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_login_after_failure() {
+        let (session, mut state_rx) = Session::new();
+        session.connecting();
+        session.logging_in();
+        session.on_login_response(&LoginResponse::Failure {
+            reason: "INVALID PASSWORD".to_string(),
+        });
+
+        assert_eq!(
+            *state_rx.borrow(),
+            SessionState::Failed("INVALID PASSWORD".to_string())
+        );
+
+        let mut waiter = tokio::spawn(async move { logged_in(&mut state_rx).await });
+        let still_waiting = timeout(Duration::from_millis(100), &mut waiter).await;
+        assert!(still_waiting.is_err());
+
+        session.logging_in();
+        session.on_login_response(&success());
+        assert!(waiter.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_stop_waiting_once_the_session_is_gone() {
+        let (session, mut state_rx) = Session::new();
+        drop(session);
+
+        assert!(logged_in(&mut state_rx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_ignore_out_of_order_login_response() {
+        let (session, state_rx) = Session::new();
+        session.on_login_response(&success());
+        assert_eq!(*state_rx.borrow(), SessionState::Disconnected);
+
+        session.connecting();
+        session.logging_in();
+        session.on_login_response(&success());
+        session.connecting();
+        assert_eq!(*state_rx.borrow(), SessionState::LoggedIn);
+
+        session.disconnected();
+        assert_eq!(*state_rx.borrow(), SessionState::Disconnected);
+    }
+}
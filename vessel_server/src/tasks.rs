@@ -1,5 +1,7 @@
+use std::time::Duration;
+
 use futures::TryFutureExt;
-use tokio::{net::TcpListener, signal, task::JoinHandle};
+use tokio::{net::TcpListener, signal, task::JoinHandle, time};
 
 use crate::{
     peers,
@@ -7,58 +9,108 @@ use crate::{
         channels::SenderPool,
         listener::{PeerListenerReceivers, PeerListenerSenders},
     },
-    slsk::connection::SlskConnection,
+    search::{
+        download::{DownloadJobScheduler, SourceEvent},
+        import::ImportScheduler,
+        responder::SearchResponder,
+        scheduler::SearchScheduler,
+        wishlist::WishlistScheduler,
+    },
+    shares::indexer::ShareIndexer,
+    slsk::{
+        buddies::BuddyWatcher,
+        connection::SlskConnection,
+        session::{self, Session},
+    },
 };
 use soulseek_protocol::{
     peers::{
-        p2p::{download::DownloadProgress, response::PeerResponse},
-        PeerRequestPacket,
+        p2p::{download::DownloadProgress, response::PeerResponse, search::SearchReply},
+        PeerRequestPacket, UndeliveredRequest,
     },
     server::{
-        login::LoginRequest,
-        peer::{Peer, PeerAddress, PeerConnectionRequest},
+        login::{LoginRequest, SessionState},
+        peer::{PeerAddress, PeerConnectionRequest, PeerConnectionTicket},
         request::ServerRequest,
         response::ServerResponse,
+        search::{ExcludedSearchPhrases, QueuedSearch, SearchQuery},
+        user::BuddyEvent,
+    },
+};
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    watch,
+};
+use vessel_database::{
+    entity::{
+        chat::ChatMessageEntity,
+        privileges::{PrivilegedUserEntity, PrivilegesEntity},
+        search::expire_searches,
     },
+    settings::SearchSettings,
+    Database,
 };
-use tokio::sync::mpsc::{Receiver, Sender};
-use vessel_database::Database;
+
+/// Our remaining privileges are checked again after this delay, privileges given to us by other
+/// users are not notified.
+const PRIVILEGES_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+const SEARCH_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Channels of the Soulseek server listener, requests sent to the server come in and responses
+/// are dispatched to the tasks handling them.
+pub struct ServerListenerChannels {
+    // Requests from the HTTP server
+    pub http_rx: Receiver<ServerRequest>,
+    pub sse_tx: Sender<ServerResponse>,
+    pub peer_listener_tx: Sender<PeerConnectionRequest>,
+    // Requests from the peer listener
+    pub request_peer_connection_rx: Receiver<ServerRequest>,
+    pub distributed_tx: Sender<ServerResponse>,
+    pub peer_address_tx: Sender<PeerAddress>,
+    pub search_tx: Sender<SearchQuery>,
+    pub excluded_phrases_tx: Sender<ExcludedSearchPhrases>,
+    pub cant_connect_tx: Sender<PeerConnectionTicket>,
+    pub wishlist_interval_tx: Sender<u32>,
+    // Searches go through the search scheduler before reaching the server
+    pub search_scheduler_tx: Sender<ServerRequest>,
+    pub scheduled_search_rx: Receiver<ServerRequest>,
+    pub buddy_tx: Sender<ServerResponse>,
+}
 
 pub fn spawn_server_listener_task(
-    http_rx: Receiver<ServerRequest>,
-    sse_tx: Sender<ServerResponse>,
-    peer_listener_tx: Sender<PeerConnectionRequest>,
-    request_peer_connection_rx: Receiver<ServerRequest>,
-    possible_parent_tx: Sender<Vec<Peer>>,
+    channels: ServerListenerChannels,
     connection: SlskConnection,
-    logged_in_tx: Sender<()>,
-    peer_address_tx: Sender<PeerAddress>,
+    session: Session,
+    database: Database,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        server_listener(
-            http_rx,
-            sse_tx,
-            peer_listener_tx,
-            request_peer_connection_rx,
-            possible_parent_tx,
-            connection,
-            logged_in_tx,
-            peer_address_tx,
-        )
-        .await;
+        server_listener(channels, connection, session, database).await;
     })
 }
 
 async fn server_listener(
-    mut http_rx: Receiver<ServerRequest>,
-    sse_tx: Sender<ServerResponse>,
-    peer_listener_tx: Sender<PeerConnectionRequest>,
-    mut request_peer_connection_rx: Receiver<ServerRequest>,
-    possible_parent_tx: Sender<Vec<Peer>>,
+    channels: ServerListenerChannels,
     mut connection: SlskConnection,
-    logged_in_tx: Sender<()>,
-    peer_address_tx: Sender<PeerAddress>,
+    session: Session,
+    database: Database,
 ) {
+    let ServerListenerChannels {
+        mut http_rx,
+        sse_tx,
+        peer_listener_tx,
+        mut request_peer_connection_rx,
+        distributed_tx,
+        peer_address_tx,
+        search_tx,
+        excluded_phrases_tx,
+        cant_connect_tx,
+        wishlist_interval_tx,
+        search_scheduler_tx,
+        mut scheduled_search_rx,
+        buddy_tx,
+    } = channels;
+
     info!("Starting Soulseek server TCP listener");
     loop {
         tokio::select! {
@@ -85,19 +137,137 @@ async fn server_listener(
                                         .map_err(|err| eyre!("Error dispatching connection request with token {} to peer listener: {}", token, err))
                                 }
 
-                                ServerResponse::PossibleParents(parents) => {
-                                    possible_parent_tx
-                                        .send(parents)
+                                response @ (ServerResponse::PossibleParents(_)
+                                | ServerResponse::ParentMinSpeed(_)
+                                | ServerResponse::ParentSpeedRatio(_)
+                                | ServerResponse::EmbeddedMessage(_)) => {
+                                    distributed_tx
+                                        .send(response)
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching distributed network message to peer listener : {}", err))
+                                }
+
+                                // Stats are used to measure possible parents and to update buddies as well
+                                ServerResponse::UserStats(stats) => {
+                                    let buddy_stats = stats.clone();
+                                    distributed_tx
+                                        .send(ServerResponse::UserStats(stats.clone()))
+                                        .and_then(|_| buddy_tx.send(ServerResponse::UserStats(buddy_stats)))
+                                        .and_then(|_| sse_tx.send(ServerResponse::UserStats(stats)))
                                         .await
-                                        .map_err(|err| eyre!("Error dispatching possible parents to peer listener : {}", err))
+                                        .map_err(|err| eyre!("Error dispatching user stats : {}", err))
                                 }
 
-                                ServerResponse::PrivilegedUsers(_) => {
-                                    logged_in_tx
-                                        .send(())
+                                ServerResponse::SearchReply(search) => {
+                                    search_tx
+                                        .send(search)
                                         .await
-                                        .map_err(|err| eyre!("Error sending privileged user to login task: {}", err))
+                                        .map_err(|err| eyre!("Error dispatching search request to search responder: {}", err))
+                                }
+
+                                ServerResponse::ExcludedSearchPhrases(excluded_phrases) => {
+                                    excluded_phrases_tx
+                                        .send(excluded_phrases)
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching excluded search phrases to search responder: {}", err))
+                                }
+
+                                ServerResponse::CantConnectToPeer(ticket) => {
+                                    cant_connect_tx
+                                        .send(ticket)
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching connection failure to message dispatcher: {}", err))
+                                }
+
+                                ServerResponse::WishlistInterval(interval) => {
+                                    wishlist_interval_tx
+                                        .send(interval)
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching wishlist interval to wishlist scheduler: {}", err))
+                                }
+
+                                ServerResponse::UserAdded(added) => {
+                                    buddy_tx
+                                        .send(ServerResponse::UserAdded(added.clone()))
+                                        .and_then(|_| sse_tx.send(ServerResponse::UserAdded(added)))
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching watched user : {}", err))
+                                }
+
+                                ServerResponse::UserStatus(status) => {
+                                    buddy_tx
+                                        .send(ServerResponse::UserStatus(status.clone()))
+                                        .and_then(|_| sse_tx.send(ServerResponse::UserStatus(status)))
+                                        .await
+                                        .map_err(|err| eyre!("Error dispatching user status : {}", err))
+                                }
+
+                                ServerResponse::PrivilegedUsers(users) => {
+                                    info!("{} privileged users", users.0.len());
+                                    if let Err(err) = PrivilegedUserEntity::replace_all(&database, users.0) {
+                                        error!("Error storing privileged users: {}", err);
+                                    }
+                                    Ok(())
+                                }
+
+                                ServerResponse::PrivilegedUserAdded(username) => {
+                                    if let Err(err) = PrivilegedUserEntity::add(&database, username) {
+                                        error!("Error storing privileged user: {}", err);
+                                    }
+                                    Ok(())
                                 }
+
+                                ServerResponse::TimeLeft(time_left) => {
+                                    if let Err(err) = database.insert(&PrivilegesEntity::new(time_left)) {
+                                        error!("Error storing our privileges: {}", err);
+                                    }
+
+                                    sse_tx
+                                        .send(ServerResponse::TimeLeft(time_left))
+                                        .await
+                                        .map_err(|err| eyre!("Error sending privileges time left to SSE: {}", err))
+                                }
+
+                                response @ (ServerResponse::ChatMessage(_)
+                                | ServerResponse::PublicChatMessage(_)) => {
+                                    if let ServerResponse::ChatMessage(message)
+                                    | ServerResponse::PublicChatMessage(message) = &response
+                                    {
+                                        if let Err(err) = ChatMessageEntity::from_room(message).store(&database) {
+                                            error!("Error storing message from room {}: {}", message.room, err);
+                                        }
+                                    }
+
+                                    sse_tx
+                                        .send(response)
+                                        .await
+                                        .map_err(|err| eyre!("Error sending chat message to SSE: {}", err))
+                                }
+
+                                // Private messages are delivered again on every login until acknowledged
+                                ServerResponse::PrivateMessage(message) => {
+                                    match ChatMessageEntity::from_private(&message).store(&database) {
+                                        Ok(_) => connection
+                                            .write_request(&ServerRequest::AcknowledgePrivateMessage(message.id))
+                                            .await
+                                            .expect("Error writing to soulseek connection"),
+                                        Err(err) => error!("Error storing private message from {}: {}", message.username, err),
+                                    }
+
+                                    sse_tx
+                                        .send(ServerResponse::PrivateMessage(message))
+                                        .await
+                                        .map_err(|err| eyre!("Error sending private message to SSE: {}", err))
+                                }
+
+                                ServerResponse::LoginResponse(response) => {
+                                    session.on_login_response(&response);
+                                    sse_tx
+                                        .send(ServerResponse::LoginResponse(response))
+                                        .await
+                                        .map_err(|err| eyre!("Error sending login response to SSE: {}", err))
+                                }
+
                                 response => {
                                     sse_tx
                                         .send(response)
@@ -108,6 +278,9 @@ async fn server_listener(
                                 if let Err(e) = err {
                                     return error!(" Error reading Soulseek stream : {}", e);
                             }
+                        } else {
+                            session.disconnected();
+                            return error!("Soulseek server closed the connection");
                         }
                      }
                      Err(err) => error!("An error occured while reading soulseek server response : {:?}", err),
@@ -117,10 +290,27 @@ async fn server_listener(
               http_command = http_rx.recv() => {
                   if let Some(request) = http_command {
                     info!("Got http request {:?}", request);
-                    connection.write_request(&request).await.expect("Error writing to soulseek connection")
+                    if let ServerRequest::Login(_) = request {
+                        session.logging_in();
+                    }
+
+                    // Searches are sent by the search scheduler within the configured limits
+                    if request.search_ticket().is_some() {
+                        if let Err(err) = search_scheduler_tx.send(request).await {
+                            error!("Error dispatching search to search scheduler: {}", err);
+                        }
+                    } else {
+                        connection.write_request(&request).await.expect("Error writing to soulseek connection")
+                    }
                   }
               }
 
+              scheduled_search = scheduled_search_rx.recv() => {
+                if let Some(request) = scheduled_search {
+                    connection.write_request(&request).await.expect("Error writing to soulseek connection")
+                }
+              }
+
               peer_connection_request = request_peer_connection_rx.recv() => {
                 if let Some(request) = peer_connection_request {
                     connection.write_request(&request).await.expect("Error writing to soulseek connection")
@@ -134,23 +324,36 @@ pub fn spawn_sse_server(
     sse_rx: Receiver<ServerResponse>,
     sse_peer_rx: Receiver<PeerResponse>,
     download_progress_rx: Receiver<DownloadProgress>,
+    undelivered_rx: Receiver<UndeliveredRequest>,
+    queued_search_rx: Receiver<QueuedSearch>,
+    buddy_event_rx: Receiver<BuddyEvent>,
+    session_rx: watch::Receiver<SessionState>,
 ) -> JoinHandle<()> {
     tokio::spawn(async {
-        vessel_sse::start_sse_listener(sse_rx, sse_peer_rx, download_progress_rx).await;
+        vessel_sse::start_sse_listener(
+            sse_rx,
+            sse_peer_rx,
+            download_progress_rx,
+            undelivered_rx,
+            queued_search_rx,
+            buddy_event_rx,
+            session_rx,
+        )
+        .await;
     })
 }
 
 pub fn spawn_peer_listener(
     senders: PeerListenerSenders,
     receivers: PeerListenerReceivers,
-    mut logged_in_rx: Receiver<()>,
+    mut session_rx: watch::Receiver<SessionState>,
     listener: TcpListener,
     database: Database,
     channels: SenderPool,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        while logged_in_rx.recv().await.is_none() {
-            // Wait for soulseek login
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Peer listener not started: {}", err);
         }
 
         peers::listener::run(
@@ -166,7 +369,47 @@ pub fn spawn_peer_listener(
     })
 }
 
-pub fn spawn_login_task(login_sender: Sender<ServerRequest>) -> JoinHandle<()> {
+pub fn spawn_search_responder(
+    db: Database,
+    search_rx: Receiver<SearchQuery>,
+    excluded_phrases_rx: Receiver<ExcludedSearchPhrases>,
+    peer_request_tx: Sender<(String, PeerRequestPacket)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        SearchResponder {
+            db,
+            search_rx,
+            excluded_phrases_rx,
+            peer_request_tx,
+            excluded_phrases: ExcludedSearchPhrases::default(),
+        }
+        .run()
+        .await
+    })
+}
+
+pub fn spawn_share_indexer(
+    db: Database,
+    rescan_rx: Receiver<()>,
+    server_request_tx: Sender<ServerRequest>,
+    session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        ShareIndexer {
+            db,
+            rescan_rx,
+            server_request_tx,
+            session_rx,
+        }
+        .run()
+        .await
+    })
+}
+
+pub fn spawn_login_task(
+    login_sender: Sender<ServerRequest>,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
     debug!("Spawning logging task");
     tokio::spawn(async move {
         let listen_port_sender = login_sender.clone();
@@ -176,7 +419,15 @@ pub fn spawn_login_task(login_sender: Sender<ServerRequest>) -> JoinHandle<()> {
         let password = &vessel_database::settings::CONFIG.password;
         login_sender
             .send(ServerRequest::Login(LoginRequest::new(username, password)))
-            .and_then(|_| listen_port_sender.send(ServerRequest::SetListenPort(2255)))
+            .await
+            .expect("Unable to establish connection with soulseek server");
+
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("{}", err);
+        }
+
+        listen_port_sender
+            .send(ServerRequest::SetListenPort(2255))
             .and_then(|_| parent_request_sender.send(ServerRequest::NoParents(true)))
             .and_then(|_| join_nicotine_room.send(ServerRequest::JoinRoom("nicotine".to_string())))
             .await
@@ -184,10 +435,169 @@ pub fn spawn_login_task(login_sender: Sender<ServerRequest>) -> JoinHandle<()> {
     })
 }
 
+pub fn spawn_wishlist_scheduler(
+    db: Database,
+    interval_rx: Receiver<u32>,
+    reply_rx: Receiver<(u32, SearchReply)>,
+    server_request_tx: Sender<ServerRequest>,
+    peer_request_tx: Sender<(String, PeerRequestPacket)>,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Wishlist scheduler not started: {}", err);
+        }
+
+        WishlistScheduler {
+            db,
+            interval_rx,
+            reply_rx,
+            server_request_tx,
+            peer_request_tx,
+        }
+        .run()
+        .await
+    })
+}
+
+pub fn spawn_download_job_scheduler(
+    db: Database,
+    event_rx: Receiver<SourceEvent>,
+    peer_request_tx: Sender<(String, PeerRequestPacket)>,
+    source_timeout: Duration,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Download job scheduler not started: {}", err);
+        }
+
+        DownloadJobScheduler {
+            db,
+            event_rx,
+            peer_request_tx,
+            source_timeout,
+        }
+        .run()
+        .await
+    })
+}
+
+pub fn spawn_import_scheduler(
+    db: Database,
+    server_request_tx: Sender<ServerRequest>,
+    progress_tx: Sender<DownloadProgress>,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Import scheduler not started: {}", err);
+        }
+
+        ImportScheduler {
+            db,
+            server_request_tx,
+            progress_tx,
+        }
+        .run()
+        .await
+    })
+}
+
+pub fn spawn_search_scheduler(
+    request_rx: Receiver<ServerRequest>,
+    server_tx: Sender<ServerRequest>,
+    queued_tx: Sender<QueuedSearch>,
+    limits: SearchSettings,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        SearchScheduler {
+            request_rx,
+            server_tx,
+            queued_tx,
+            limits,
+        }
+        .run()
+        .await
+    })
+}
+
+/// Remove expired searches and their stored results.
+pub fn spawn_search_expiry(database: Database, ttl: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval(SEARCH_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let db = database.clone();
+            match tokio::task::spawn_blocking(move || expire_searches(&db, ttl)).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(expired)) => debug!("{} expired searches removed", expired),
+                Ok(Err(err)) => error!("Failed to remove expired searches: {}", err),
+                Err(err) => error!("Search expiry task failed: {}", err),
+            }
+        }
+    })
+}
+
+/// Check our remaining privileges once logged in and then periodically.
+pub fn spawn_privileges_check(
+    server_request_tx: Sender<ServerRequest>,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Privileges check not started: {}", err);
+        }
+
+        let mut interval = time::interval(PRIVILEGES_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = server_request_tx.send(ServerRequest::CheckPrivileges).await {
+                return error!("Unable to check our privileges: {}", err);
+            }
+        }
+    })
+}
+
 pub fn spawn_http_listener(
     http_tx: Sender<ServerRequest>,
     peer_message_dispatcher_tx: Sender<(String, PeerRequestPacket)>,
+    rescan_tx: Sender<()>,
+    session_rx: watch::Receiver<SessionState>,
     database: Database,
 ) -> JoinHandle<()> {
-    tokio::spawn(async { vessel_http::start(http_tx, peer_message_dispatcher_tx, database).await })
+    tokio::spawn(async {
+        vessel_http::start(
+            http_tx,
+            peer_message_dispatcher_tx,
+            rescan_tx,
+            session_rx,
+            database,
+        )
+        .await
+    })
+}
+
+pub fn spawn_buddy_watcher(
+    db: Database,
+    response_rx: Receiver<ServerResponse>,
+    server_request_tx: Sender<ServerRequest>,
+    event_tx: Sender<BuddyEvent>,
+    mut session_rx: watch::Receiver<SessionState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Users can only be watched once logged in
+        if let Err(err) = session::logged_in(&mut session_rx).await {
+            return error!("Buddy watcher not started: {}", err);
+        }
+
+        BuddyWatcher {
+            db,
+            response_rx,
+            server_request_tx,
+            event_tx,
+        }
+        .run()
+        .await
+    })
 }
@@ -1,15 +1,51 @@
 use crate::client::{Client, Clients};
-use crate::MAX_SEARCH_RESULT;
+use crate::{MAX_SEARCH_RESULT, MAX_TRACKED_TICKETS};
 use futures::{Stream, StreamExt};
 use soulseek_protocol::{
-    peers::p2p::{download::DownloadProgress, response::PeerResponse},
-    server::response::ServerResponse,
+    peers::{
+        p2p::{download::DownloadProgress, response::PeerResponse},
+        UndeliveredRequest,
+    },
+    server::{
+        login::SessionState, response::ServerResponse, search::QueuedSearch, user::BuddyEvent,
+    },
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
 };
-use std::{collections::HashMap, convert::Infallible};
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
+use tokio::{
+    sync::{mpsc::Receiver, watch},
+    task::JoinHandle,
+};
 use warp::sse::Event;
 
+/// Search replies sent per ticket, only the most recent tickets are tracked.
+#[derive(Default)]
+struct SearchReplyCounts {
+    counts: HashMap<u32, u32>,
+    tickets: VecDeque<u32>,
+}
+
+impl SearchReplyCounts {
+    fn increment(&mut self, ticket: u32) -> u32 {
+        if !self.counts.contains_key(&ticket) {
+            if self.tickets.len() >= MAX_TRACKED_TICKETS {
+                if let Some(oldest) = self.tickets.pop_front() {
+                    self.counts.remove(&oldest);
+                }
+            }
+
+            self.tickets.push_back(ticket);
+        }
+
+        let count = self.counts.entry(ticket).or_default();
+        *count += 1;
+        *count
+    }
+}
+
 #[derive(Default, Clone)]
 pub(crate) struct Broadcaster {
     pub(crate) clients: Clients,
@@ -67,6 +103,7 @@ impl Broadcaster {
                     ServerResponse::RoomList(_) => "room_lists",
                     ServerResponse::AdminMessage(_) => "admin_message",
                     ServerResponse::PrivilegedUsers(_) => "privileged_users",
+                    ServerResponse::TimeLeft(_) => "privileges_time_left",
                     ServerResponse::EmbeddedMessage(_) => "embedded_message",
                     ServerResponse::SimilarUsers(_) => "similar_users",
                     ServerResponse::ItemRecommendations(_) => "item_recommendations",
@@ -103,23 +140,15 @@ impl Broadcaster {
         let broadcaster = self.clone();
         tokio::task::spawn(async move {
             info!("Starting to dispatch vessel message to SSE clients");
-            let mut ticket_counts = HashMap::<u32, u32>::new();
+            let mut reply_counts = SearchReplyCounts::default();
             while let Some(message) = peer_rx.recv().await {
                 let data = serde_json::to_string(&message).expect("Serialization error");
 
+                // Every reply is stored by vessel, clients can page through them once the cap is
+                // reached
                 let sse_event = match message {
                     PeerResponse::SearchReply(reply) => {
-                        let ticket_count = ticket_counts.get(&reply.ticket);
-
-                        let count = if let Some(count) = ticket_count {
-                            count + 1
-                        } else {
-                            0
-                        };
-
-                        ticket_counts.insert(reply.ticket, count);
-
-                        if count < MAX_SEARCH_RESULT {
+                        if reply_counts.increment(reply.ticket) <= MAX_SEARCH_RESULT {
                             Some("search_reply")
                         } else {
                             None
@@ -146,6 +175,10 @@ impl Broadcaster {
                 let event = match &progress {
                     DownloadProgress::Init { .. } => "download_started".to_string(),
                     DownloadProgress::Progress { .. } => "download_progress".to_string(),
+                    DownloadProgress::FolderProgress { .. } => {
+                        "folder_download_progress".to_string()
+                    }
+                    DownloadProgress::ImportProgress { .. } => "import_progress".to_string(),
                 };
 
                 let data = serde_json::to_string(&progress).expect("Serialization error");
@@ -154,4 +187,69 @@ impl Broadcaster {
             }
         })
     }
+
+    pub(crate) fn dispatch_undelivered_requests(
+        &self,
+        mut rx: Receiver<UndeliveredRequest>,
+    ) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+        tokio::task::spawn(async move {
+            info!("Starting to dispatch undelivered peer requests to SSE clients");
+            while let Some(undelivered) = rx.recv().await {
+                let data = serde_json::to_string(&undelivered).expect("Serialization error");
+                broadcaster.send_message_to_clients("peer_request_failed", &data);
+            }
+        })
+    }
+
+    pub(crate) fn dispatch_queued_searches(
+        &self,
+        mut rx: Receiver<QueuedSearch>,
+    ) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+        tokio::task::spawn(async move {
+            info!("Starting to dispatch queued searches to SSE clients");
+            while let Some(queued) = rx.recv().await {
+                let event = match queued.position {
+                    Some(_) => "search_queued",
+                    None => "search_sent",
+                };
+
+                let data = serde_json::to_string(&queued).expect("Serialization error");
+                broadcaster.send_message_to_clients(event, &data);
+            }
+        })
+    }
+
+    pub(crate) fn dispatch_buddy_events(&self, mut rx: Receiver<BuddyEvent>) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+        tokio::task::spawn(async move {
+            info!("Starting to dispatch buddy events to SSE clients");
+            while let Some(buddy_event) = rx.recv().await {
+                let event = match buddy_event {
+                    BuddyEvent::Online { .. } => "buddy_online",
+                    BuddyEvent::Away { .. } => "buddy_away",
+                    BuddyEvent::Offline { .. } => "buddy_offline",
+                    BuddyEvent::SharesChanged { .. } => "buddy_shares_changed",
+                };
+
+                let data = serde_json::to_string(&buddy_event).expect("Serialization error");
+                broadcaster.send_message_to_clients(event, &data);
+            }
+        })
+    }
+
+    pub(crate) fn dispatch_session_state(
+        &self,
+        mut rx: watch::Receiver<SessionState>,
+    ) -> JoinHandle<()> {
+        let broadcaster = self.clone();
+        tokio::task::spawn(async move {
+            info!("Starting to dispatch session state to SSE clients");
+            while rx.changed().await.is_ok() {
+                let data = serde_json::to_string(&*rx.borrow()).expect("Serialization error");
+                broadcaster.send_message_to_clients("session", &data);
+            }
+        })
+    }
 }
@@ -4,15 +4,22 @@ extern crate tokio;
 extern crate tracing;
 
 use soulseek_protocol::{
-    peers::p2p::{download::DownloadProgress, response::PeerResponse},
-    server::response::ServerResponse,
+    peers::{
+        p2p::{download::DownloadProgress, response::PeerResponse},
+        UndeliveredRequest,
+    },
+    server::{
+        login::SessionState, response::ServerResponse, search::QueuedSearch, user::BuddyEvent,
+    },
 };
 
 use crate::broadcast::Broadcaster;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc::Receiver, watch};
 use warp::Filter;
 
 const MAX_SEARCH_RESULT: u32 = 500;
+/// Replies are only counted for the most recent searches
+const MAX_TRACKED_TICKETS: usize = 256;
 
 mod broadcast;
 mod client;
@@ -21,6 +28,10 @@ pub async fn start_sse_listener(
     rx: Receiver<ServerResponse>,
     peer_rx: Receiver<PeerResponse>,
     download_progress_rx: Receiver<DownloadProgress>,
+    undelivered_rx: Receiver<UndeliveredRequest>,
+    queued_search_rx: Receiver<QueuedSearch>,
+    buddy_event_rx: Receiver<BuddyEvent>,
+    session_rx: watch::Receiver<SessionState>,
 ) {
     info!("Starting server sent event broadcast ...");
     let cors = warp::cors().allow_any_origin();
@@ -36,6 +47,18 @@ pub async fn start_sse_listener(
     // Dispatch download progress to SSE
     let download_progress = broadcaster.dispatch_download_progress(download_progress_rx);
 
+    // Dispatch peer requests we could not deliver to SSE
+    let undelivered_requests = broadcaster.dispatch_undelivered_requests(undelivered_rx);
+
+    // Dispatch the position of searches held back by the search limit to SSE
+    let queued_searches = broadcaster.dispatch_queued_searches(queued_search_rx);
+
+    // Dispatch buddy status and share changes to SSE
+    let buddy_events = broadcaster.dispatch_buddy_events(buddy_event_rx);
+
+    // Dispatch login state changes to SSE
+    let session_state = broadcaster.dispatch_session_state(session_rx);
+
     let users = warp::any().map(move || broadcaster.clone());
 
     let sse_events = warp::path!("events")
@@ -54,5 +77,9 @@ pub async fn start_sse_listener(
         event_dispatcher,
         peer_event_dispatcher,
         download_progress,
+        undelivered_requests,
+        queued_searches,
+        buddy_events,
+        session_state,
     );
 }